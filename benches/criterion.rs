@@ -1,8 +1,11 @@
-use axum_demo::api::hello::hello_service::hello;
+use axum_demo::{api::hello::hello_service::hello, infra::config::GreetingConfig};
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 
 fn greet_benchmark(c: &mut Criterion) {
-    c.bench_function("greet", |b| b.iter(|| hello(black_box("World"))));
+    let template = GreetingConfig::default().template;
+    c.bench_function("greet", |b| {
+        b.iter(|| hello(black_box(&template), black_box("World")))
+    });
 }
 
 criterion_group!(benches, greet_benchmark);
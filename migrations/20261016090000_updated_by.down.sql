@@ -0,0 +1,3 @@
+ALTER TABLE short_urls DROP COLUMN updated_by;
+ALTER TABLE items DROP COLUMN updated_by;
+ALTER TABLE items DROP COLUMN created_by;
@@ -0,0 +1,3 @@
+ALTER TABLE items ADD COLUMN created_by INTEGER REFERENCES users(id);
+ALTER TABLE items ADD COLUMN updated_by INTEGER REFERENCES users(id);
+ALTER TABLE short_urls ADD COLUMN updated_by INTEGER REFERENCES users(id);
@@ -0,0 +1,8 @@
+CREATE TABLE impersonations (
+    id SERIAL PRIMARY KEY,
+    token UUID NOT NULL UNIQUE,
+    admin_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMPTZ NOT NULL
+);
@@ -0,0 +1 @@
+DROP FUNCTION url_host;
@@ -0,0 +1,4 @@
+-- The lowercased host of a URL, or NULL if it has none
+CREATE FUNCTION url_host(url TEXT) RETURNS TEXT
+    LANGUAGE SQL IMMUTABLE STRICT
+    RETURN lower(substring(url FROM '^[A-Za-z][A-Za-z0-9+.-]*://(?:[^/?#@]*@)?([^/?#:]+)'));
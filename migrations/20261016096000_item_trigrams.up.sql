@@ -0,0 +1,10 @@
+-- Trigram index for fuzzy item search.
+-- Without pg_trgm the migration still succeeds, and search falls back to substring matching.
+DO $$
+BEGIN
+    CREATE EXTENSION IF NOT EXISTS pg_trgm;
+    CREATE INDEX items_name_trgm_idx ON items USING GIN (name gin_trgm_ops);
+EXCEPTION WHEN OTHERS THEN
+    RAISE WARNING 'pg_trgm is unavailable, fuzzy item search falls back to substring matching: %', SQLERRM;
+END
+$$;
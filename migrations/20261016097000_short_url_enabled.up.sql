@@ -0,0 +1 @@
+ALTER TABLE short_urls ADD COLUMN enabled BOOLEAN NOT NULL DEFAULT TRUE;
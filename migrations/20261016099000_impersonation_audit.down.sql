@@ -0,0 +1,10 @@
+DELETE FROM impersonations WHERE admin_id IS NULL OR user_id IS NULL;
+ALTER TABLE impersonations
+    ALTER COLUMN admin_id SET NOT NULL,
+    ALTER COLUMN user_id SET NOT NULL,
+    DROP CONSTRAINT impersonations_admin_id_fkey,
+    DROP CONSTRAINT impersonations_user_id_fkey,
+    ADD CONSTRAINT impersonations_admin_id_fkey
+        FOREIGN KEY (admin_id) REFERENCES users(id) ON DELETE CASCADE,
+    ADD CONSTRAINT impersonations_user_id_fkey
+        FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE;
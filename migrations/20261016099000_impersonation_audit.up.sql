@@ -0,0 +1,9 @@
+ALTER TABLE impersonations
+    ALTER COLUMN admin_id DROP NOT NULL,
+    ALTER COLUMN user_id DROP NOT NULL,
+    DROP CONSTRAINT impersonations_admin_id_fkey,
+    DROP CONSTRAINT impersonations_user_id_fkey,
+    ADD CONSTRAINT impersonations_admin_id_fkey
+        FOREIGN KEY (admin_id) REFERENCES users(id) ON DELETE SET NULL,
+    ADD CONSTRAINT impersonations_user_id_fkey
+        FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE SET NULL;
@@ -3,11 +3,13 @@
 use crate::{
     api::hello::hello_service,
     infra::{
+        config::GreetingConfig,
+        error::{ApiResult, ClientError},
         extract::{Json, Query},
         state::AppState,
     },
 };
-use axum::{routing::get, Router};
+use axum::{extract::State, routing::get, Router};
 use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
 use tracing::instrument;
@@ -18,20 +20,27 @@ pub fn routes() -> Router<AppState> {
     Router::new().route("/hello", get(hello))
 }
 
-/// A name query parameter.
-#[derive(Deserialize, IntoParams)]
+/// Who to greet, and in which language.
+#[derive(Default, Deserialize, IntoParams)]
 pub struct GreetingParams {
+    /// The name to greet, defaults to the configured name.
     name: Option<String>,
+    /// One of the configured languages, such as `no`.
+    lang: Option<String>,
 }
 
 impl Debug for GreetingParams {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.name.fmt(f)
+        f.debug_struct("GreetingParams")
+            .field("name", &self.name)
+            .field("lang", &self.lang)
+            .finish()
     }
 }
 
 /// This is a response to the hello endpoint.
 #[derive(Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
 pub struct Greeting {
     /// A personal greeting.
     pub greeting: String,
@@ -53,30 +62,55 @@ impl Greeting {
 #[utoipa::path(
     get,
     path = "/api/hello",
+    tag = "hello",
     params(GreetingParams),
     responses(
         (status = 200, description = "Success", body = Greeting),
+        (status = 422, description = "Unsupported language", body = ErrorBody),
     )
 )]
-#[instrument]
-pub async fn hello(Query(params): Query<GreetingParams>) -> Json<Greeting> {
-    let name = params.name.as_deref().unwrap_or("World");
-    Json(Greeting {
-        greeting: hello_service::hello(name),
-    })
+#[instrument(skip(greeting))]
+pub async fn hello(
+    State(greeting): State<GreetingConfig>,
+    Query(params): Query<GreetingParams>,
+) -> ApiResult<Json<Greeting>> {
+    let template = match params.lang.as_deref() {
+        Some(lang) => greeting.languages.get(lang).ok_or_else(|| {
+            ClientError::UnprocessableEntity(format!("unsupported language: {lang}"))
+        })?,
+        None => &greeting.template,
+    };
+    let name = params.name.as_deref().unwrap_or(&greeting.default_name);
+    Ok(Json(Greeting {
+        greeting: hello_service::hello(template, name),
+    }))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::infra::config::GreetingTemplate;
+
+    fn config() -> State<GreetingConfig> {
+        let mut greeting = crate::infra::config::load_config().unwrap().greeting;
+        greeting.template = GreetingTemplate::try_from("Hi {name}".to_string()).unwrap();
+        greeting.default_name = "there".to_string();
+        greeting.languages.insert(
+            "no".to_string(),
+            GreetingTemplate::try_from("Hei, {name}!".to_string()).unwrap(),
+        );
+        State(greeting)
+    }
 
     #[sqlx::test]
-    async fn hello_without_name_defaults_to_world() {
-        let response = hello(Query(GreetingParams { name: None })).await;
+    async fn hello_without_name_uses_configured_default() {
+        let response = hello(config(), Query(GreetingParams::default()))
+            .await
+            .unwrap();
 
         assert_eq!(
             Greeting {
-                greeting: "Hello, World!".to_string(),
+                greeting: "Hi there".to_string(),
             },
             response.0
         );
@@ -84,16 +118,54 @@ mod tests {
 
     #[sqlx::test]
     async fn hello_test() {
-        let response = hello(Query(GreetingParams {
-            name: Some("NotWorld".to_string()),
-        }))
-        .await;
+        let response = hello(
+            config(),
+            Query(GreetingParams {
+                name: Some("NotWorld".to_string()),
+                lang: None,
+            }),
+        )
+        .await
+        .unwrap();
 
         assert_eq!(
             Greeting {
-                greeting: "Hello, NotWorld!".to_string(),
+                greeting: "Hi NotWorld".to_string(),
             },
             response.0
         );
     }
+
+    #[sqlx::test]
+    async fn hello_with_lang_uses_language_template() {
+        let response = hello(
+            config(),
+            Query(GreetingParams {
+                name: Some("Foo".to_string()),
+                lang: Some("no".to_string()),
+            }),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            Greeting {
+                greeting: "Hei, Foo!".to_string(),
+            },
+            response.0
+        );
+    }
+
+    #[sqlx::test]
+    async fn hello_with_unknown_lang_is_rejected() {
+        let result = hello(
+            config(),
+            Query(GreetingParams {
+                name: None,
+                lang: Some("xx".to_string()),
+            }),
+        )
+        .await;
+        assert!(result.is_err());
+    }
 }
@@ -1,9 +1,10 @@
 //! A service for greeting someone.
 
+use crate::infra::config::GreetingTemplate;
 use tracing::instrument;
 
 /// Returns a greeting based on someone's name.
 #[instrument(ret)]
-pub fn hello(name: &str) -> String {
-    format!("Hello, {name}!")
+pub fn hello(template: &GreetingTemplate, name: &str) -> String {
+    template.render(name)
 }
@@ -1,13 +1,35 @@
 //! APIs for getting information about the application.
 
-use crate::infra::{extract::Json, state::AppState};
-use axum::{routing::get, Router};
+use std::collections::HashSet;
+
+use crate::{
+    api::info::migration_repository::{self, AppliedMigration},
+    infra::{
+        config::Config,
+        database::{DbPool, MIGRATOR},
+        error::ApiResult,
+        extract::Json,
+        security::{Admin, User},
+        selfcheck::{self, CheckStatus},
+        state::AppState,
+        version,
+    },
+};
+use axum::{extract::State, routing::get, Router};
+use chrono::{DateTime, Utc};
+use http::StatusCode;
 use serde::{Deserialize, Serialize};
+use tracing::instrument;
 use utoipa::ToSchema;
 
 /// The item API endpoints.
 pub fn routes() -> Router<AppState> {
-    Router::new().route("/info", get(info))
+    Router::new()
+        .route("/info", get(info))
+        .route("/version", get(get_version))
+        .route("/ready", get(ready))
+        .route("/admin/migrations", get(migrations))
+        .route("/debug/config", get(debug_config))
 }
 
 /// Application information.
@@ -23,6 +45,7 @@ pub struct AppInfo {
 #[utoipa::path(
     get,
     path = "/api/info",
+    tag = "info",
     responses(
         (status = 200, description = "Success", body = AppInfo),
     )
@@ -30,6 +53,172 @@ pub struct AppInfo {
 pub async fn info() -> Json<AppInfo> {
     Json(AppInfo {
         name: env!("CARGO_PKG_NAME"),
-        version: env!("CARGO_PKG_VERSION"),
+        version: version::VERSION,
+    })
+}
+
+/// The version of the running application.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Version {
+    /// The application version.
+    #[schema(example = "0.1.23")]
+    pub version: String,
+    /// The short sha of the commit that was built.
+    #[schema(example = "c2898c9")]
+    pub git_sha: String,
+    /// When the application was built.
+    pub build_time: DateTime<Utc>,
+}
+
+/// Returns the version of the running application.
+#[utoipa::path(
+    get,
+    path = "/api/version",
+    tag = "info",
+    responses(
+        (status = 200, description = "Success", body = Version),
+    )
+)]
+pub async fn get_version() -> Json<Version> {
+    Json(Version {
+        version: version::VERSION.to_string(),
+        git_sha: version::GIT_SHA.to_string(),
+        build_time: version::build_time(),
     })
 }
+
+/// Whether the application is ready to serve requests.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Readiness {
+    /// Whether all required dependencies are reachable.
+    pub ready: bool,
+    /// The status of each dependency.
+    pub dependencies: Vec<DependencyStatus>,
+}
+
+/// The status of a single dependency.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct DependencyStatus {
+    /// The name of the dependency.
+    #[schema(example = "database")]
+    pub name: String,
+    /// Whether the application can only be ready when it is reachable.
+    pub required: bool,
+    /// Whether the dependency is reachable.
+    pub healthy: bool,
+    /// Why the dependency is not reachable.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Checks whether the application is ready to serve requests.
+///
+/// Every dependency is probed, but only the required ones
+/// decide whether the application is ready.
+#[utoipa::path(
+    get,
+    path = "/api/ready",
+    tag = "info",
+    responses(
+        (status = 200, description = "Ready", body = Readiness),
+        (status = 503, description = "Service Unavailable", body = Readiness),
+    )
+)]
+#[instrument(skip_all)]
+pub async fn ready(
+    State(db): State<DbPool>,
+    State(config): State<Config>,
+) -> (StatusCode, Json<Readiness>) {
+    let self_check = selfcheck::check(&config, &db).await;
+    let ready = self_check.is_healthy();
+    let dependencies = self_check
+        .results()
+        .iter()
+        .map(|result| DependencyStatus {
+            name: result.name.to_string(),
+            required: !result.optional,
+            healthy: result.status == CheckStatus::Pass,
+            error: match &result.status {
+                CheckStatus::Pass => None,
+                CheckStatus::Fail(reason) => Some(reason.clone()),
+            },
+        })
+        .collect();
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (
+        status,
+        Json(Readiness {
+            ready,
+            dependencies,
+        }),
+    )
+}
+
+/// The state of the database migrations.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct MigrationStatus {
+    /// Migrations that have been applied to the database.
+    pub applied: Vec<AppliedMigration>,
+    /// Versions of embedded migrations that have not been applied yet.
+    #[schema(example = json!([]))]
+    pub pending: Vec<i64>,
+}
+
+/// Returns the applied and pending database migrations.
+///
+/// This endpoint is read-only, migrations are only applied at startup.
+#[utoipa::path(
+    get,
+    path = "/api/admin/migrations",
+    tag = "info",
+    responses(
+        (status = 200, description = "Ok", body = MigrationStatus),
+        (status = 401, description = "Unauthorized", body = ErrorBody),
+        (status = 403, description = "Forbidden", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody),
+    ),
+    security(
+        ("basic" = [])
+    )
+)]
+#[instrument(skip(db))]
+pub async fn migrations(
+    db: State<DbPool>,
+    _admin: User<Admin>,
+) -> ApiResult<Json<MigrationStatus>> {
+    let mut tx = db.begin().await?;
+    let applied = migration_repository::list_applied_migrations(&mut tx).await?;
+    tx.commit().await?;
+    let applied_versions: HashSet<i64> = applied.iter().map(|m| m.version).collect();
+    let pending = MIGRATOR
+        .iter()
+        .filter(|m| !m.migration_type.is_down_migration())
+        .map(|m| m.version)
+        .filter(|version| !applied_versions.contains(version))
+        .collect();
+    Ok(Json(MigrationStatus { applied, pending }))
+}
+
+/// Returns the loaded configuration, with secrets redacted.
+#[utoipa::path(
+    get,
+    path = "/api/debug/config",
+    tag = "info",
+    responses(
+        (status = 200, description = "Ok", body = Object),
+        (status = 401, description = "Unauthorized", body = ErrorBody),
+        (status = 403, description = "Forbidden", body = ErrorBody),
+    ),
+    security(
+        ("basic" = [])
+    )
+)]
+#[instrument(skip(config))]
+pub async fn debug_config(State(config): State<Config>, _admin: User<Admin>) -> Json<Config> {
+    Json(config)
+}
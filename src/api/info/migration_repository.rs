@@ -0,0 +1,52 @@
+//! Types and functions for inspecting database migrations.
+
+use crate::infra::{database::Tx, error::ApiResult};
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+use utoipa::ToSchema;
+
+/// A migration that has been applied to the database.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema, sqlx::FromRow)]
+pub struct AppliedMigration {
+    /// The migration version.
+    #[schema(example = json!(20221007213507i64))]
+    pub version: i64,
+    /// The migration description.
+    #[schema(example = "items")]
+    pub description: String,
+    /// Whether the migration completed successfully.
+    pub success: bool,
+}
+
+/// Lists the migrations that have been applied, ordered by version.
+#[instrument(skip(tx))]
+pub async fn list_applied_migrations(tx: &mut Tx) -> ApiResult<Vec<AppliedMigration>> {
+    // Not checked at compile time, since the table is managed by the migrator itself
+    let migrations = sqlx::query_as(
+        r#"
+        SELECT version, description, success FROM _sqlx_migrations
+        ORDER BY version
+        "#,
+    )
+    .fetch_all(tx.as_mut())
+    .await?;
+    Ok(migrations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::PgPool;
+
+    #[sqlx::test]
+    async fn lists_all_embedded_migrations(db: PgPool) {
+        let mut tx = db.begin().await.unwrap();
+        let applied = list_applied_migrations(&mut tx).await.unwrap();
+        let embedded = crate::infra::database::MIGRATOR
+            .iter()
+            .filter(|m| !m.migration_type.is_down_migration())
+            .count();
+        assert_eq!(embedded, applied.len());
+        assert!(applied.iter().all(|m| m.success));
+    }
+}
@@ -1 +1,2 @@
 pub mod info_api;
+pub mod migration_repository;
@@ -0,0 +1,149 @@
+//! Types and functions for storing and loading item attachments from the database.
+
+use crate::infra::{database::Tx, error::ApiResult, security::User};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tracing::{instrument, Instrument};
+
+/// A new attachment.
+#[derive(PartialEq, Eq)]
+pub struct NewAttachment {
+    /// The content type of the attachment.
+    pub content_type: String,
+    /// The attachment's content.
+    pub data: Vec<u8>,
+}
+
+impl std::fmt::Debug for NewAttachment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NewAttachment")
+            .field("content_type", &self.content_type)
+            .field("size", &self.data.len())
+            .finish()
+    }
+}
+
+/// An existing attachment.
+#[derive(PartialEq, Eq, Serialize, Deserialize)]
+pub struct Attachment {
+    /// The item the attachment belongs to.
+    pub item_id: i32,
+    /// The content type of the attachment.
+    pub content_type: String,
+    /// The size of the attachment in bytes.
+    pub size: i32,
+    /// The attachment's content.
+    pub data: Vec<u8>,
+    /// The user who uploaded the attachment.
+    pub created_by: i32,
+    /// The time the attachment was uploaded.
+    pub created_at: OffsetDateTime,
+}
+
+impl std::fmt::Debug for Attachment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Attachment")
+            .field("item_id", &self.item_id)
+            .field("content_type", &self.content_type)
+            .field("size", &self.size)
+            .field("created_by", &self.created_by)
+            .field("created_at", &self.created_at)
+            .finish()
+    }
+}
+
+/// Stores an attachment for an item, replacing any existing one.
+#[instrument(skip(tx))]
+pub async fn upsert_attachment<R>(
+    tx: &mut Tx,
+    item_id: i32,
+    new_attachment: NewAttachment,
+    user: User<R>,
+) -> ApiResult<Attachment> {
+    tracing::info!("Storing attachment");
+    let attachment = sqlx::query_as!(
+        Attachment,
+        r#"
+        INSERT INTO attachments (item_id, content_type, size, data, created_by)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (item_id) DO UPDATE
+        SET content_type = $2, size = $3, data = $4, created_by = $5, created_at = NOW()
+        RETURNING *
+        "#,
+        item_id,
+        new_attachment.content_type,
+        new_attachment.data.len() as i32,
+        new_attachment.data,
+        user.id()
+    )
+    .fetch_one(tx.as_mut())
+    .await?;
+    tracing::info!("Stored attachment {:?}", attachment);
+    Ok(attachment)
+}
+
+/// Reads an item's attachment.
+#[instrument(skip(tx))]
+pub async fn fetch_attachment(tx: &mut Tx, item_id: i32) -> ApiResult<Option<Attachment>> {
+    tracing::info!("Reading attachment");
+    let attachment = sqlx::query_as!(
+        Attachment,
+        r#"
+        SELECT * FROM attachments
+        WHERE item_id = $1
+        "#,
+        item_id
+    )
+    .fetch_optional(tx.as_mut())
+    .instrument(tracing::info_span!("fetch_optional"))
+    .await?;
+    tracing::info!("Found attachment: {:?}", attachment);
+    Ok(attachment)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api::item::item_repository::{self, NewItem},
+        infra::validation::Valid,
+    };
+    use sqlx::PgPool;
+
+    #[sqlx::test]
+    async fn upsert_then_fetch_returns_attachment(db: PgPool) {
+        let mut tx = db.begin().await.unwrap();
+        let user =
+            crate::infra::security::authenticate(&mut tx, "user", "user", bcrypt::DEFAULT_COST)
+                .await
+                .unwrap();
+        let item = item_repository::create_item(
+            &mut tx,
+            Valid::new(NewItem {
+                name: "Foo".to_string(),
+                description: None,
+            })
+            .unwrap(),
+            user.clone(),
+        )
+        .await
+        .unwrap();
+
+        upsert_attachment(
+            &mut tx,
+            item.id,
+            NewAttachment {
+                content_type: "text/plain".to_string(),
+                data: b"hello".to_vec(),
+            },
+            user,
+        )
+        .await
+        .unwrap();
+
+        let attachment = fetch_attachment(&mut tx, item.id).await.unwrap().unwrap();
+        assert_eq!("text/plain", attachment.content_type);
+        assert_eq!(5, attachment.size);
+        assert_eq!(b"hello".to_vec(), attachment.data);
+    }
+}
@@ -2,30 +2,49 @@
 
 use crate::{
     api::item::{
+        attachment_repository::NewAttachment,
+        item_events::{ItemEvent, ItemEvents},
         item_repository::{Item, NewItem},
         item_service,
     },
     infra::{
-        database::DbPool,
+        config::{Config, ItemsConfig, PaginationConfig},
+        database::{with_retry_on_serialization, DbPool},
         error::{ApiError, ApiResult, ClientError},
         extract::{Json, Query},
-        pagination::PaginationParams,
+        pagination::{Page, PageLinks, PaginationParams},
+        security::{Admin, User},
         state::AppState,
+        timestamped::TimestampParams,
         validation::Valid,
     },
 };
-use axum::{extract::State, Router};
+use axum::{
+    body::Body,
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        DefaultBodyLimit, Multipart, OriginalUri, State,
+    },
+    response::{IntoResponse, Response},
+    Router,
+};
 use axum_extra::{
+    headers::{IfModifiedSince, IfUnmodifiedSince, LastModified},
     json_lines::AsResponse,
     response::JsonLines,
     routing::{RouterExt, TypedPath},
+    TypedHeader,
 };
 use futures::Stream;
-use http::StatusCode;
+use http::{
+    header::{CONTENT_DISPOSITION, CONTENT_TYPE, X_CONTENT_TYPE_OPTIONS},
+    HeaderValue, StatusCode,
+};
 use serde::{Deserialize, Serialize};
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
+use tokio::sync::broadcast::{error::RecvError, Receiver};
 use tracing::instrument;
-use utoipa::IntoParams;
+use utoipa::{IntoParams, ToSchema};
 
 /// The item API endpoints.
 pub fn routes() -> Router<AppState> {
@@ -35,104 +54,272 @@ pub fn routes() -> Router<AppState> {
         .typed_put(update_item)
         .typed_delete(delete_item)
         .typed_get(list_items)
+        .typed_get(count_items)
+        .typed_get(search_items)
+        .typed_post(assign_orphans)
         .typed_get(stream_items)
+        .typed_get(item_events_ws)
+}
+
+/// The item attachment endpoints.
+pub fn attachment_routes() -> Router<AppState> {
+    Router::new()
+        .typed_post(upload_attachment)
+        .typed_get(download_attachment)
+        // The attachment size limit is enforced by the handler
+        .layer(DefaultBodyLimit::disable())
 }
 
 #[derive(Deserialize, TypedPath)]
 #[typed_path("/items", rejection(ClientError))]
 struct Items;
 
+#[derive(Deserialize, TypedPath)]
+#[typed_path("/items/count", rejection(ClientError))]
+struct ItemsCount;
+
+#[derive(Deserialize, TypedPath)]
+#[typed_path("/items/search", rejection(ClientError))]
+struct ItemsSearch;
+
+#[derive(Deserialize, TypedPath)]
+#[typed_path("/admin/items/orphans", rejection(ClientError))]
+struct AdminItemsOrphans;
+
 #[derive(Deserialize, TypedPath)]
 #[typed_path("/items2", rejection(ClientError))]
 struct Items2;
 
+#[derive(Deserialize, TypedPath)]
+#[typed_path("/ws", rejection(ClientError))]
+struct Ws;
+
 #[derive(Deserialize, TypedPath)]
 #[typed_path("/items/:id", rejection(ClientError))]
 struct ItemsId(i32);
 
+#[derive(Deserialize, TypedPath)]
+#[typed_path("/items/:id/attachment", rejection(ClientError))]
+struct ItemsIdAttachment(i32);
+
+/// Options for creating an item.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, IntoParams)]
+pub struct CreateItemParams {
+    /// Only check that the item could be created, without creating it.
+    #[serde(default)]
+    validate_only: bool,
+}
+
 /// Creates a new item.
 #[utoipa::path(
     post,
     path = "/api/items",
+    tag = "items",
     request_body = NewItem,
+    params(CreateItemParams),
     responses(
         (status = 201, description = "Created", body = Item),
+        (status = 204, description = "Valid, but not created"),
+        (status = 401, description = "Unauthorized", body = ErrorBody),
         (status = 409, description = "Conflict", body = ErrorBody),
+        (status = 422, description = "Unprocessable Entity", body = ErrorBody),
         (status = 500, description = "Internal Server Error", body = ErrorBody),
+    ),
+    security(
+        ("basic" = [])
     )
 )]
 #[instrument(skip_all, fields(new_item))]
 async fn create_item(
     Items: Items,
     db: State<DbPool>,
+    events: State<ItemEvents>,
+    config: State<Config>,
+    user: User,
+    Query(params): Query<CreateItemParams>,
     Json(new_item): Json<NewItem>,
-) -> ApiResult<(StatusCode, Json<Item>)> {
+) -> ApiResult<Response> {
     let new_item = Valid::new(new_item)?;
-    let mut tx = db.begin().await?;
-    let item = item_service::create_item(&mut tx, new_item).await?;
-    tx.commit().await?;
-    Ok((StatusCode::CREATED, Json(item)))
+    if params.validate_only {
+        let mut tx = db.begin().await?;
+        item_service::create_item(&mut tx, new_item, user).await?;
+        // The insert succeeded, so constraints are satisfied
+        tx.rollback().await?;
+        return Ok(StatusCode::NO_CONTENT.into_response());
+    }
+    let attempts = config.database.transaction_attempts;
+    let item = with_retry_on_serialization(&db, attempts, |tx| {
+        Box::pin(item_service::create_item(
+            tx,
+            new_item.clone(),
+            user.clone(),
+        ))
+    })
+    .await?;
+    events.publish(ItemEvent::ItemCreated(item.clone()));
+    Ok((StatusCode::CREATED, Json(item)).into_response())
+}
+
+/// Notifies about item events over a WebSocket.
+///
+/// The server pushes an [`ItemEvent`] as JSON for every created item.
+/// Clients may send `ping` to receive `pong`, and `subscribe` to receive `subscribed`.
+#[instrument(skip_all)]
+async fn item_events_ws(
+    Ws: Ws,
+    events: State<ItemEvents>,
+    _user: User,
+    ws: WebSocketUpgrade,
+) -> Response {
+    let events = events.subscribe();
+    ws.on_upgrade(move |socket| forward_item_events(socket, events))
+}
+
+/// Forward item events to a socket, and answer client messages, until either side closes.
+async fn forward_item_events(mut socket: WebSocket, mut events: Receiver<ItemEvent>) {
+    loop {
+        let reply = tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) => match serde_json::to_string(&event) {
+                    Ok(json) => Message::Text(json),
+                    Err(e) => {
+                        tracing::error!("Failed to serialize item event: {}", e);
+                        continue;
+                    }
+                },
+                Err(RecvError::Lagged(n)) => {
+                    tracing::warn!("WebSocket client missed {} item events", n);
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            },
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => match text.trim() {
+                    "ping" => Message::Text("pong".to_string()),
+                    "subscribe" => Message::Text("subscribed".to_string()),
+                    other => Message::Text(format!("unknown command: {other}")),
+                },
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => continue,
+            },
+        };
+        if socket.send(reply).await.is_err() {
+            break;
+        }
+    }
 }
 
 /// Gets an item.
+///
+/// The response has a `Last-Modified` header, and `If-Modified-Since` gives `304`
+/// if the item has not been modified since.
 #[utoipa::path(
     get,
     path = "/api/items/{id}",
+    tag = "items",
+    params(TimestampParams),
     responses(
         (status = 200, description = "Ok", body = Item),
+        (status = 304, description = "Not Modified"),
         (status = 404, description = "Not Found", body = ErrorBody),
         (status = 500, description = "Internal Server Error", body = ErrorBody),
     )
 )]
 #[instrument(skip_all, fields(id))]
-async fn get_item(ItemsId(id): ItemsId, db: State<DbPool>) -> ApiResult<(StatusCode, Json<Item>)> {
+async fn get_item(
+    ItemsId(id): ItemsId,
+    db: State<DbPool>,
+    if_modified_since: Option<TypedHeader<IfModifiedSince>>,
+    Query(timestamp): Query<TimestampParams>,
+) -> ApiResult<Response> {
     let mut tx = db.begin().await?;
     let item = item_service::read_item(&mut tx, id)
         .await?
         .ok_or(ClientError::NotFound)?;
     tx.commit().await?;
-    Ok((StatusCode::OK, Json(item)))
+    let updated_at = SystemTime::from(item.updated_at);
+    let last_modified = TypedHeader(LastModified::from(updated_at));
+    if let Some(TypedHeader(since)) = if_modified_since {
+        if !since.is_modified(updated_at) {
+            return Ok((StatusCode::NOT_MODIFIED, last_modified).into_response());
+        }
+    }
+    Ok((last_modified, timestamp.respond(item)).into_response())
 }
 
 /// Updates an item.
+///
+/// With `If-Unmodified-Since`, the update fails with `412` if the item has been modified since.
 #[utoipa::path(
     put,
     path = "/api/items/{id}",
+    tag = "items",
     request_body = NewItem,
     responses(
         (status = 200, description = "Ok", body = Item),
+        (status = 401, description = "Unauthorized", body = ErrorBody),
         (status = 404, description = "Not Found", body = ErrorBody),
+        (status = 409, description = "Conflict", body = ErrorBody),
+        (status = 412, description = "Precondition Failed", body = ErrorBody),
         (status = 500, description = "Internal Server Error", body = ErrorBody),
+    ),
+    security(
+        ("basic" = [])
     )
 )]
-#[instrument(skip(db))]
+#[instrument(skip(db, events, config))]
 async fn update_item(
     ItemsId(id): ItemsId,
     db: State<DbPool>,
+    events: State<ItemEvents>,
+    config: State<Config>,
+    user: User,
+    if_unmodified_since: Option<TypedHeader<IfUnmodifiedSince>>,
     Json(new_item): Json<NewItem>,
-) -> ApiResult<(StatusCode, Json<Item>)> {
+) -> ApiResult<(StatusCode, TypedHeader<LastModified>, Json<Item>)> {
     let new_item = Valid::new(new_item)?;
-    let mut tx = db.begin().await?;
-    let item = item_service::update_item(&mut tx, id, new_item).await?;
-    tx.commit().await?;
-    Ok((StatusCode::OK, Json(item)))
+    let unmodified_since =
+        if_unmodified_since.map(|TypedHeader(since)| SystemTime::from(since).into());
+    let attempts = config.database.transaction_attempts;
+    let item = with_retry_on_serialization(&db, attempts, |tx| {
+        Box::pin(item_service::update_item(
+            tx,
+            id,
+            new_item.clone(),
+            user.clone(),
+            unmodified_since,
+        ))
+    })
+    .await?;
+    events.publish(ItemEvent::ItemUpdated(item.clone()));
+    let last_modified = LastModified::from(SystemTime::from(item.updated_at));
+    Ok((StatusCode::OK, TypedHeader(last_modified), Json(item)))
 }
 
 /// Deletes an item.
 #[utoipa::path(
     delete,
     path = "/api/items/{id}",
+    tag = "items",
     responses(
-        (status = 200, description = "Ok", body = Item),
+        (status = 204, description = "No Content"),
         (status = 404, description = "Not Found", body = ErrorBody),
         (status = 500, description = "Internal Server Error", body = ErrorBody),
     )
 )]
 #[instrument(skip_all, fields(id))]
-async fn delete_item(ItemsId(id): ItemsId, db: State<DbPool>) -> ApiResult<StatusCode> {
-    let mut tx = db.begin().await?;
-    item_service::delete_item(&mut tx, id).await?;
-    tx.commit().await?;
+async fn delete_item(
+    ItemsId(id): ItemsId,
+    db: State<DbPool>,
+    events: State<ItemEvents>,
+    config: State<Config>,
+) -> ApiResult<StatusCode> {
+    let attempts = config.database.transaction_attempts;
+    with_retry_on_serialization(&db, attempts, |tx| {
+        Box::pin(item_service::delete_item(tx, id))
+    })
+    .await?;
+    events.publish(ItemEvent::ItemDeleted { id });
     Ok(StatusCode::NO_CONTENT)
 }
 
@@ -140,37 +327,169 @@ async fn delete_item(ItemsId(id): ItemsId, db: State<DbPool>) -> ApiResult<Statu
 #[utoipa::path(
     get,
     path = "/api/items",
-    params(PaginationParams),
+    tag = "items",
+    params(PaginationParams, TimestampParams),
     responses(
-        (status = 200, description = "Success", body = [Item]),
+        (status = 200, description = "Success", body = [Item],
+            headers(("link" = String, description = "Links to the first, previous, next and last pages"))),
         (status = 500, description = "Internal error", body = ErrorBody),
     )
 )]
 #[instrument(skip_all)]
 async fn list_items(
     Items: Items,
+    OriginalUri(uri): OriginalUri,
     db: State<DbPool>,
+    pagination: State<PaginationConfig>,
     Query(params): Query<PaginationParams>,
-) -> ApiResult<Json<Vec<Item>>> {
+    Query(timestamp): Query<TimestampParams>,
+) -> ApiResult<Response> {
+    let params = params.clamp(&pagination);
     let mut tx = db.begin().await?;
     let items = item_service::list_items(&mut tx, &params).await?;
+    let total = item_service::count_items(&mut tx).await?;
+    let links = PageLinks::new(&uri, &params, total);
+    if params.envelope() {
+        let page = timestamp.respond(Page::new(items, total, &params));
+        return Ok((links, page).into_response());
+    }
+    Ok((links, timestamp.respond(items)).into_response())
+}
+
+/// The number of items.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ItemCount {
+    /// The total number of items.
+    #[schema(example = 42)]
+    pub count: i64,
+}
+
+/// Counts all items.
+#[utoipa::path(
+    get,
+    path = "/api/items/count",
+    tag = "items",
+    responses(
+        (status = 200, description = "Success", body = ItemCount),
+        (status = 500, description = "Internal error", body = ErrorBody),
+    )
+)]
+#[instrument(skip_all)]
+async fn count_items(ItemsCount: ItemsCount, db: State<DbPool>) -> ApiResult<Json<ItemCount>> {
+    let mut tx = db.begin().await?;
+    let count = item_service::count_items(&mut tx).await?;
+    tx.commit().await?;
+    Ok(Json(ItemCount { count }))
+}
+
+/// What to search for.
+#[derive(Clone, Debug, Serialize, Deserialize, IntoParams)]
+pub struct SearchParams {
+    /// Text resembling the names of the items to find, tolerating typos.
+    #[param(example = "bluebery mufin")]
+    fuzzy: String,
+}
+
+/// Searches for items by name.
+///
+/// Results are ordered by how similar their names are to the query,
+/// and only include names above the configured similarity threshold.
+#[utoipa::path(
+    get,
+    path = "/api/items/search",
+    tag = "items",
+    params(SearchParams, PaginationParams),
+    responses(
+        (status = 200, description = "Success", body = [Item]),
+        (status = 400, description = "Bad Request", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody),
+    )
+)]
+#[instrument(skip_all, fields(fuzzy = search.fuzzy))]
+async fn search_items(
+    ItemsSearch: ItemsSearch,
+    db: State<DbPool>,
+    pagination: State<PaginationConfig>,
+    items: State<ItemsConfig>,
+    Query(search): Query<SearchParams>,
+    Query(params): Query<PaginationParams>,
+) -> ApiResult<Json<Vec<Item>>> {
+    let query = search.fuzzy.trim();
+    if query.is_empty() {
+        return Err(ClientError::BadRequest(
+            "fuzzy search must not be blank".to_string(),
+        ))?;
+    }
+    let params = params.clamp(&pagination);
+    let mut tx = db.begin().await?;
+    let items = item_service::search_items(&mut tx, query, items.fuzzy_threshold, &params).await?;
+    tx.commit().await?;
     Ok(Json(items))
 }
 
+/// The outcome of assigning orphaned items to an owner.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct OrphansAssigned {
+    /// The number of items that were assigned.
+    #[schema(example = 3)]
+    pub updated: u64,
+}
+
+/// Assigns items without an owner to the configured default owner.
+///
+/// Items that already have an owner are skipped, so this is safe to run repeatedly.
+#[utoipa::path(
+    post,
+    path = "/api/admin/items/orphans",
+    tag = "items",
+    responses(
+        (status = 200, description = "Ok", body = OrphansAssigned),
+        (status = 401, description = "Unauthorized", body = ErrorBody),
+        (status = 403, description = "Forbidden", body = ErrorBody),
+        (status = 500, description = "Internal Server Error", body = ErrorBody),
+    ),
+    security(
+        ("basic" = [])
+    )
+)]
+#[instrument(skip_all)]
+async fn assign_orphans(
+    AdminItemsOrphans: AdminItemsOrphans,
+    db: State<DbPool>,
+    items: State<ItemsConfig>,
+    _admin: User<Admin>,
+) -> ApiResult<Json<OrphansAssigned>> {
+    let mut tx = db.begin().await?;
+    let updated = item_service::assign_orphans(&mut tx, &items.default_owner).await?;
+    tx.commit().await?;
+    Ok(Json(OrphansAssigned { updated }))
+}
+
 /// Options for how to stream result.
 #[derive(Clone, Copy, Debug, Serialize, Deserialize, IntoParams)]
 pub struct StreamParams {
     /// The delay between each result in milliseconds.
+    /// Values above the configured maximum are clamped.
     throttle: Option<u64>,
 }
 
+impl StreamParams {
+    /// The requested delay between each result, clamped to `max`.
+    pub fn throttle(&self, max: Duration) -> Duration {
+        Duration::from_millis(self.throttle.unwrap_or(0)).min(max)
+    }
+}
+
 /// Streams all items.
+///
+/// The response is newline-delimited JSON with one [`Item`] per line.
 #[utoipa::path(
     get,
     path = "/api/items2",
-    params(StreamParams),
+    tag = "items",
+    params(PaginationParams, StreamParams),
     responses(
-        (status = 200, description = "Success", body = [Item]),
+        (status = 200, description = "One item per line", body = Item, content_type = "application/x-ndjson"),
         (status = 500, description = "Internal error", body = ErrorBody),
     )
 )]
@@ -178,15 +497,140 @@ pub struct StreamParams {
 async fn stream_items<'a>(
     Items2: Items2,
     State(db): State<DbPool>,
+    State(config): State<Config>,
     Query(params): Query<PaginationParams>,
     Query(stream_params): Query<StreamParams>,
 ) -> ApiResult<JsonLines<impl Stream<Item = Result<Item, ApiError>>, AsResponse>> {
+    let params = params.clamp(&config.pagination);
     let conn = db.acquire().await?;
-    let throttle = Duration::from_millis(stream_params.throttle.unwrap_or(0));
+    let throttle = stream_params.throttle(config.stream.max_throttle);
     Ok(JsonLines::new(item_service::stream_items(
-        conn, params, throttle,
+        conn,
+        params,
+        throttle,
+        config.stream.max_duration,
     )))
 }
 
+/// Uploads an attachment for an item, replacing any existing one.
+///
+/// The first field of the multipart body is used as the attachment.
+/// Only the item's creator or an administrator may upload it.
+#[utoipa::path(
+    post,
+    path = "/api/items/{id}/attachment",
+    tag = "items",
+    request_body(content = Vec<u8>, content_type = "multipart/form-data"),
+    responses(
+        (status = 204, description = "No Content"),
+        (status = 400, description = "Bad Request", body = ErrorBody),
+        (status = 401, description = "Unauthorized", body = ErrorBody),
+        (status = 403, description = "Forbidden", body = ErrorBody),
+        (status = 404, description = "Not Found", body = ErrorBody),
+        (status = 413, description = "Payload Too Large", body = ErrorBody),
+        (status = 500, description = "Internal Server Error", body = ErrorBody),
+    ),
+    security(
+        ("basic" = [])
+    )
+)]
+#[instrument(skip_all, fields(id))]
+async fn upload_attachment(
+    ItemsIdAttachment(id): ItemsIdAttachment,
+    db: State<DbPool>,
+    config: State<Config>,
+    user: User,
+    mut multipart: Multipart,
+) -> ApiResult<StatusCode> {
+    let max_size = config.server.max_attachment_size;
+    let mut field = multipart
+        .next_field()
+        .await
+        .map_err(|e| ClientError::Custom(e.status(), e.body_text()))?
+        .ok_or_else(|| ClientError::BadRequest("missing attachment".to_string()))?;
+    let content_type = field
+        .content_type()
+        .unwrap_or("application/octet-stream")
+        .to_string();
+
+    // Read the field in chunks to avoid buffering more than the limit
+    let mut data = Vec::new();
+    while let Some(chunk) = field
+        .chunk()
+        .await
+        .map_err(|e| ClientError::Custom(e.status(), e.body_text()))?
+    {
+        if data.len() + chunk.len() > max_size {
+            tracing::warn!("Attachment exceeds {} bytes", max_size);
+            return Err(ClientError::Custom(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("attachment exceeds {max_size} bytes"),
+            ))?;
+        }
+        data.extend_from_slice(&chunk);
+    }
+
+    let new_attachment = NewAttachment { content_type, data };
+    let mut tx = db.begin().await?;
+    item_service::attach_file(&mut tx, id, new_attachment, user).await?;
+    tx.commit().await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Downloads an item's attachment.
+///
+/// The content type is chosen by the uploader, so the attachment is always
+/// served as a download, never rendered inline by the browser.
+#[utoipa::path(
+    get,
+    path = "/api/items/{id}/attachment",
+    tag = "items",
+    responses(
+        (status = 200, description = "Ok", body = Vec<u8>, content_type = "application/octet-stream"),
+        (status = 404, description = "Not Found", body = ErrorBody),
+        (status = 500, description = "Internal Server Error", body = ErrorBody),
+    )
+)]
+#[instrument(skip_all, fields(id))]
+async fn download_attachment(
+    ItemsIdAttachment(id): ItemsIdAttachment,
+    db: State<DbPool>,
+) -> ApiResult<Response> {
+    let mut tx = db.begin().await?;
+    let attachment = item_service::read_attachment(&mut tx, id)
+        .await?
+        .ok_or(ClientError::NotFound)?;
+    tx.commit().await?;
+    let content_type = HeaderValue::from_str(&attachment.content_type)
+        .unwrap_or(HeaderValue::from_static("application/octet-stream"));
+    Ok((
+        [
+            (CONTENT_TYPE, content_type),
+            (CONTENT_DISPOSITION, HeaderValue::from_static("attachment")),
+            (X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff")),
+        ],
+        Body::from(attachment.data),
+    )
+        .into_response())
+}
+
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use super::*;
+
+    #[test]
+    fn large_throttle_is_clamped() {
+        let params = StreamParams {
+            throttle: Some(u64::MAX),
+        };
+        let max = Duration::from_secs(1);
+        assert_eq!(max, params.throttle(max));
+    }
+
+    #[test]
+    fn small_throttle_is_kept() {
+        let params = StreamParams { throttle: Some(10) };
+        let max = Duration::from_secs(1);
+        assert_eq!(Duration::from_millis(10), params.throttle(max));
+    }
+}
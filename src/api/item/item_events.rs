@@ -0,0 +1,64 @@
+//! Notifications about changes to items.
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use super::item_repository::Item;
+
+/// The number of events a slow subscriber may fall behind before missing some.
+const CAPACITY: usize = 64;
+
+/// Something that happened to an item.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", content = "item", rename_all = "snake_case")]
+pub enum ItemEvent {
+    /// An item was created.
+    ItemCreated(Item),
+    /// An item was changed.
+    ItemUpdated(Item),
+    /// An item was deleted.
+    ItemDeleted {
+        /// The id of the deleted item.
+        id: i32,
+    },
+}
+
+impl ItemEvent {
+    /// The names of all events, as used in the `event` field.
+    pub const NAMES: [&'static str; 3] = ["item_created", "item_updated", "item_deleted"];
+
+    /// The name of the event, as used in the `event` field.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ItemEvent::ItemCreated(_) => "item_created",
+            ItemEvent::ItemUpdated(_) => "item_updated",
+            ItemEvent::ItemDeleted { .. } => "item_deleted",
+        }
+    }
+}
+
+/// A channel for publishing and subscribing to [`ItemEvent`]s.
+#[derive(Clone, Debug)]
+pub struct ItemEvents {
+    sender: broadcast::Sender<ItemEvent>,
+}
+
+impl Default for ItemEvents {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(CAPACITY);
+        Self { sender }
+    }
+}
+
+impl ItemEvents {
+    /// Publish an event to all current subscribers.
+    pub fn publish(&self, event: ItemEvent) {
+        // No subscribers is not an error, nobody is listening
+        let _ = self.sender.send(event);
+    }
+
+    /// Subscribe to events published from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<ItemEvent> {
+        self.sender.subscribe()
+    }
+}
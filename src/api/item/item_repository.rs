@@ -2,33 +2,37 @@
 
 use crate::infra::{
     database::{DbConnection, Tx},
-    error::{ApiResult, ClientError},
+    error::{ApiError, ApiResult, ClientError},
     pagination::PaginationParams,
-    validation::Valid,
+    security::User,
+    validation::{trimmed, trimmed_option, Valid},
 };
-use async_stream::try_stream;
 use futures::{Stream, StreamExt};
 use serde::{Deserialize, Serialize};
 use std::time::Duration;
+use time::OffsetDateTime;
 use tracing::{instrument, Instrument};
 use utoipa::ToSchema;
 use validator::Validate;
 
 /// A new item.
-#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema, Validate)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema, Validate)]
 pub struct NewItem {
     /// The item's name.
     #[schema(example = "MyItem")]
-    #[validate(length(min = 1))]
+    #[serde(deserialize_with = "trimmed")]
+    #[validate(length(min = 1, max = 255))]
     pub name: String,
     /// The item's description.
     #[schema(example = "A very interesting item")]
-    #[validate(length(min = 1))]
+    #[serde(default, deserialize_with = "trimmed_option")]
+    #[validate(length(min = 1, max = 4096))]
     pub description: Option<String>,
 }
 
 /// An existing item.
-#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
 pub struct Item {
     /// The item's id.
     pub id: i32,
@@ -38,22 +42,36 @@ pub struct Item {
     #[schema(example = "A very interesting item")]
     /// The item's description.
     pub description: Option<String>,
+    /// The user who created the item.
+    #[schema(example = "1")]
+    pub created_by: Option<i32>,
+    /// The user who last modified the item.
+    #[schema(example = "2")]
+    pub updated_by: Option<i32>,
+    /// The time the item was created or last modified.
+    #[schema(example = "2021-01-01T00:00:00Z")]
+    pub updated_at: OffsetDateTime,
 }
 
 /// Creates a new item.
 #[instrument(skip(tx))]
-pub async fn create_item(tx: &mut Tx, new_item: Valid<NewItem>) -> ApiResult<Item> {
+pub async fn create_item<R>(
+    tx: &mut Tx,
+    new_item: Valid<NewItem>,
+    user: User<R>,
+) -> ApiResult<Item> {
     let new_item = new_item.into_inner();
     tracing::info!("Creating item {:?}", new_item);
     let item = sqlx::query_as!(
         Item,
         r#"
-        INSERT INTO items (name, description)
-        VALUES ($1, $2)
+        INSERT INTO items (name, description, created_by)
+        VALUES ($1, $2, $3)
         RETURNING *
         "#,
         new_item.name,
-        new_item.description
+        new_item.description,
+        user.id()
     )
     .fetch_one(tx.as_mut())
     .await?;
@@ -81,22 +99,44 @@ pub async fn fetch_item(tx: &mut Tx, id: i32) -> ApiResult<Option<Item>> {
 }
 
 /// Updates an item.
+///
+/// With `unmodified_since`, the item is only updated if it has not been modified
+/// after that time, at the one second precision of HTTP dates.
 #[instrument(skip(tx))]
-pub async fn update_item(tx: &mut Tx, id: i32, new_item: Valid<NewItem>) -> ApiResult<Item> {
+pub async fn update_item<R>(
+    tx: &mut Tx,
+    id: i32,
+    new_item: Valid<NewItem>,
+    user: User<R>,
+    unmodified_since: Option<OffsetDateTime>,
+) -> ApiResult<Item> {
     let new_item = new_item.into_inner();
     tracing::info!("Updating item {:?}", new_item);
     let item = sqlx::query_as!(
         Item,
         r#"
         UPDATE items
-        SET name = $1, description = $2
+        SET name = $1, description = $2, updated_by = $3, updated_at = NOW()
+        WHERE id = $4
+        AND ($5::TIMESTAMPTZ IS NULL OR date_trunc('second', updated_at) <= $5)
         RETURNING *
         "#,
         new_item.name,
-        new_item.description
+        new_item.description,
+        user.id(),
+        id,
+        unmodified_since
     )
-    .fetch_one(tx.as_mut())
+    .fetch_optional(tx.as_mut())
     .await?;
+    let item = match item {
+        Some(item) => item,
+        None if fetch_item(tx, id).await?.is_some() => {
+            tracing::warn!("Item was modified since {:?}", unmodified_since);
+            return Err(ClientError::PreconditionFailed)?;
+        }
+        None => return Err(ClientError::NotFound)?,
+    };
     tracing::info!("Updated item {:?}", item);
     Ok(item)
 }
@@ -126,6 +166,26 @@ pub async fn delete_item(tx: &mut Tx, id: i32) -> ApiResult<()> {
     Ok(())
 }
 
+/// Assigns all items without an owner to `owner`.
+///
+/// Items that already have an owner are left untouched,
+/// so running this again only picks up new orphans.
+#[instrument(skip(tx))]
+pub async fn assign_orphans(tx: &mut Tx, owner: i32) -> ApiResult<u64> {
+    tracing::info!("Assigning orphaned items");
+    let rows = sqlx::query!(
+        r#"
+        UPDATE items SET created_by = $1
+        WHERE created_by IS NULL
+        "#,
+        owner
+    )
+    .execute(tx.as_mut())
+    .await?;
+    tracing::info!("Assigned {} orphaned items", rows.rows_affected());
+    Ok(rows.rows_affected())
+}
+
 /// Lists all items.
 #[instrument(skip_all)]
 pub async fn list_items(tx: &mut Tx, params: &PaginationParams) -> ApiResult<Vec<Item>> {
@@ -147,36 +207,142 @@ pub async fn list_items(tx: &mut Tx, params: &PaginationParams) -> ApiResult<Vec
     Ok(items)
 }
 
+/// Counts all items.
+#[instrument(skip_all)]
+pub async fn count_items(tx: &mut Tx) -> ApiResult<i64> {
+    tracing::info!("Counting items");
+    let count = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "count!" FROM items
+        "#
+    )
+    .fetch_one(tx.as_mut())
+    .await?;
+    tracing::info!("Counted {} items", count);
+    Ok(count)
+}
+
+/// Finds items whose name resembles `query`, most similar first.
+///
+/// Names match when their trigram similarity to `query` is at least `threshold`.
+/// If the `pg_trgm` extension is not installed, names containing `query` match instead.
+#[instrument(skip(tx, params))]
+pub async fn search_items(
+    tx: &mut Tx,
+    query: &str,
+    threshold: f32,
+    params: &PaginationParams,
+) -> ApiResult<Vec<Item>> {
+    tracing::info!("Searching items");
+    let trigrams = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS (SELECT 1 FROM pg_extension WHERE extname = 'pg_trgm') AS "installed!"
+        "#
+    )
+    .fetch_one(tx.as_mut())
+    .await?;
+    let items = if trigrams {
+        // The `%` operator can use the trigram index, but only takes its threshold from this setting
+        sqlx::query_scalar!(
+            r#"
+            SELECT set_config('pg_trgm.similarity_threshold', $1, true)
+            "#,
+            threshold.to_string()
+        )
+        .fetch_one(tx.as_mut())
+        .await?;
+        sqlx::query_as!(
+            Item,
+            r#"
+            SELECT * FROM items
+            WHERE name % $1
+            ORDER BY similarity(name, $1) DESC, id
+            LIMIT $2
+            OFFSET $3
+            "#,
+            query,
+            params.limit(),
+            params.offset()
+        )
+        .fetch_all(tx.as_mut())
+        .await?
+    } else {
+        tracing::warn!("pg_trgm is not installed, falling back to substring search");
+        sqlx::query_as!(
+            Item,
+            r#"
+            SELECT * FROM items
+            WHERE name ILIKE '%' || $1 || '%'
+            ORDER BY name, id
+            LIMIT $2
+            OFFSET $3
+            "#,
+            escape_like(query),
+            params.limit(),
+            params.offset()
+        )
+        .fetch_all(tx.as_mut())
+        .await?
+    };
+    tracing::info!("Found {} items", items.len());
+    Ok(items)
+}
+
+/// Escapes the wildcards of a `LIKE` pattern, so that it only matches itself.
+fn escape_like(pattern: &str) -> String {
+    pattern
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
 /// Streams all items.
+///
+/// Rows are fetched by a background task, which stops once `max_duration` has passed
+/// and releases the connection, even if the consumer has stopped reading.
 #[allow(clippy::let_with_type_underscore)]
 #[instrument(skip(conn))]
 pub fn stream_items(
     mut conn: DbConnection,
     params: PaginationParams,
     throttle: Duration,
+    max_duration: Duration,
 ) -> impl Stream<Item = ApiResult<Item>> {
     tracing::info!("Streaming items");
-    let items = try_stream! {
-        let mut items = sqlx::query_as!(
-            Item,
-            r#"
-                SELECT * FROM items
-                LIMIT $1
-                OFFSET $2
-            "#,
-            params.limit(),
-            params.offset()
-        )
-        .fetch(conn.as_mut());
-        let mut total = 0;
-        while let Some(item) = items.next().await {
-            yield item?;
-            total += 1;
-            tokio::time::sleep(throttle).await;
+    let deadline = tokio::time::Instant::now() + max_duration;
+    let (sender, mut receiver) = tokio::sync::mpsc::channel(1);
+    tokio::spawn(
+        async move {
+            let mut total = 0;
+            let produce = async {
+                let mut items = sqlx::query_as!(
+                    Item,
+                    r#"
+                        SELECT * FROM items
+                        LIMIT $1
+                        OFFSET $2
+                    "#,
+                    params.limit(),
+                    params.offset()
+                )
+                .fetch(conn.as_mut());
+                while let Some(item) = items.next().await {
+                    if sender.send(item.map_err(ApiError::from)).await.is_err() {
+                        // The consumer is gone
+                        break;
+                    }
+                    total += 1;
+                    tokio::time::sleep(throttle).await;
+                }
+            };
+            if tokio::time::timeout_at(deadline, produce).await.is_err() {
+                tracing::warn!("Stream exceeded {:?}, stopping early", max_duration);
+            }
+            tracing::info!("Streamed {} items", total);
         }
-        tracing::info!("Streamed {} items", total);
-    };
-    Box::pin(items)
+        .in_current_span(),
+    );
+    futures::stream::poll_fn(move |cx| receiver.poll_recv(cx))
 }
 
 #[cfg(test)]
@@ -184,11 +350,63 @@ mod tests {
     use crate::infra::pagination::PaginationParams;
 
     use super::*;
+    use crate::infra::database::TestTx;
     use sqlx::PgPool;
+    use std::time::Instant;
+
+    #[test]
+    fn item_uses_camel_case_and_round_trips() {
+        let item = Item {
+            id: 1,
+            name: "item".to_string(),
+            description: None,
+            created_by: Some(1),
+            updated_by: Some(2),
+            updated_at: OffsetDateTime::UNIX_EPOCH,
+        };
+        let json = serde_json::to_value(&item).unwrap();
+        assert_eq!(serde_json::json!(1), json["createdBy"]);
+        assert_eq!(serde_json::json!(2), json["updatedBy"]);
+        assert!(json.get("created_by").is_none(), "{json}");
+        assert_eq!(item, serde_json::from_value(json).unwrap());
+    }
+
+    #[sqlx::test]
+    async fn assign_orphans_only_touches_ownerless_items(db: PgPool) {
+        let mut tx = TestTx::begin(&db).await;
+        sqlx::query(
+            "INSERT INTO items (name, created_by) VALUES ('orphan', NULL), ('owned', 1), ('lost', NULL)",
+        )
+        .execute(tx.as_mut())
+        .await
+        .unwrap();
+
+        assert_eq!(2, assign_orphans(&mut tx, 2).await.unwrap());
+        let owners: Vec<(String, Option<i32>)> =
+            sqlx::query_as("SELECT name, created_by FROM items ORDER BY name")
+                .fetch_all(tx.as_mut())
+                .await
+                .unwrap();
+        assert_eq!(
+            vec![
+                ("lost".to_string(), Some(2)),
+                ("orphan".to_string(), Some(2)),
+                ("owned".to_string(), Some(1)),
+            ],
+            owners
+        );
+
+        // Nothing left to do
+        assert_eq!(0, assign_orphans(&mut tx, 2).await.unwrap());
+    }
 
     #[sqlx::test]
     async fn create_then_list_returns_item(db: PgPool) {
-        let mut tx = db.begin().await.unwrap();
+        let mut tx = TestTx::begin(&db).await;
+        let user =
+            crate::infra::security::authenticate(&mut tx, "user", "user", bcrypt::DEFAULT_COST)
+                .await
+                .unwrap();
         let item = create_item(
             &mut tx,
             Valid::new(NewItem {
@@ -196,6 +414,7 @@ mod tests {
                 description: None,
             })
             .unwrap(),
+            user,
         )
         .await
         .unwrap();
@@ -205,6 +424,9 @@ mod tests {
                 id: 1,
                 name: "Foo".to_string(),
                 description: None,
+                created_by: Some(1),
+                updated_by: None,
+                updated_at: item.updated_at,
             },
             item,
         );
@@ -214,4 +436,239 @@ mod tests {
             .unwrap();
         assert_eq!(&item, items.last().unwrap());
     }
+
+    async fn create_items(tx: &mut Tx, names: &[&str]) {
+        let user = crate::infra::security::authenticate(tx, "user", "user", bcrypt::DEFAULT_COST)
+            .await
+            .unwrap();
+        for name in names {
+            let new_item = NewItem {
+                name: name.to_string(),
+                description: None,
+            };
+            create_item(tx, Valid::new(new_item).unwrap(), user.clone())
+                .await
+                .unwrap();
+        }
+    }
+
+    fn names(items: Vec<Item>) -> Vec<String> {
+        items.into_iter().map(|item| item.name).collect()
+    }
+
+    #[sqlx::test]
+    async fn fuzzy_search_tolerates_typos(db: PgPool) {
+        let mut tx = TestTx::begin(&db).await;
+        create_items(
+            &mut tx,
+            &["Blueberry muffin", "Blueberry pie", "Chocolate cake"],
+        )
+        .await;
+        let params = PaginationParams::default();
+
+        let found = search_items(&mut tx, "bluebery mufin", 0.3, &params)
+            .await
+            .unwrap();
+        assert_eq!(vec!["Blueberry muffin", "Blueberry pie"], names(found));
+
+        let found = search_items(&mut tx, "chocolat", 0.3, &params)
+            .await
+            .unwrap();
+        assert_eq!(vec!["Chocolate cake"], names(found));
+
+        let found = search_items(&mut tx, "xylophone", 0.3, &params)
+            .await
+            .unwrap();
+        assert!(found.is_empty(), "{found:?}");
+    }
+
+    #[sqlx::test]
+    async fn fuzzy_search_respects_threshold(db: PgPool) {
+        let mut tx = TestTx::begin(&db).await;
+        create_items(&mut tx, &["Blueberry muffin", "Blueberry pie"]).await;
+        let params = PaginationParams::default();
+
+        let found = search_items(&mut tx, "bluebery mufin", 0.6, &params)
+            .await
+            .unwrap();
+        assert_eq!(vec!["Blueberry muffin"], names(found));
+    }
+
+    #[sqlx::test]
+    async fn search_falls_back_to_substrings_without_pg_trgm(db: PgPool) {
+        let mut tx = TestTx::begin(&db).await;
+        sqlx::query("DROP EXTENSION IF EXISTS pg_trgm CASCADE")
+            .execute(tx.as_mut())
+            .await
+            .unwrap();
+        create_items(
+            &mut tx,
+            &["Blueberry muffin", "Chocolate cake", "100% cocoa"],
+        )
+        .await;
+        let params = PaginationParams::default();
+
+        let found = search_items(&mut tx, "BERRY", 0.3, &params).await.unwrap();
+        assert_eq!(vec!["Blueberry muffin"], names(found));
+        let found = search_items(&mut tx, "%", 0.3, &params).await.unwrap();
+        assert_eq!(vec!["100% cocoa"], names(found));
+        let found = search_items(&mut tx, "xylophone", 0.3, &params)
+            .await
+            .unwrap();
+        assert!(found.is_empty(), "{found:?}");
+    }
+
+    #[sqlx::test]
+    async fn update_records_acting_user(db: PgPool) {
+        let mut tx = TestTx::begin(&db).await;
+        let user =
+            crate::infra::security::authenticate(&mut tx, "user", "user", bcrypt::DEFAULT_COST)
+                .await
+                .unwrap();
+        let admin =
+            crate::infra::security::authenticate(&mut tx, "admin", "admin", bcrypt::DEFAULT_COST)
+                .await
+                .unwrap();
+        let item = create_item(
+            &mut tx,
+            Valid::new(NewItem {
+                name: "Foo".to_string(),
+                description: None,
+            })
+            .unwrap(),
+            user,
+        )
+        .await
+        .unwrap();
+
+        let updated = update_item(
+            &mut tx,
+            item.id,
+            Valid::new(NewItem {
+                name: "Bar".to_string(),
+                description: None,
+            })
+            .unwrap(),
+            admin,
+            None,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(Some(1), updated.created_by);
+        assert_eq!(Some(2), updated.updated_by);
+    }
+
+    #[sqlx::test]
+    async fn conditional_update_fails_if_modified_since(db: PgPool) {
+        let mut tx = TestTx::begin(&db).await;
+        sqlx::query(
+            "INSERT INTO items (name, updated_at) VALUES ('Foo', '2024-01-01T12:00:00.5Z')",
+        )
+        .execute(tx.as_mut())
+        .await
+        .unwrap();
+        let user =
+            crate::infra::security::authenticate(&mut tx, "user", "user", bcrypt::DEFAULT_COST)
+                .await
+                .unwrap();
+        let new_item = || {
+            Valid::new(NewItem {
+                name: "Bar".to_string(),
+                description: None,
+            })
+            .unwrap()
+        };
+        let noon = OffsetDateTime::from_unix_timestamp(1_704_110_400).unwrap();
+
+        let result = update_item(
+            &mut tx,
+            1,
+            new_item(),
+            user.clone(),
+            Some(noon - Duration::from_secs(1)),
+        )
+        .await;
+        assert!(matches!(
+            result,
+            Err(crate::infra::error::ApiError::ClientError(
+                ClientError::PreconditionFailed
+            ))
+        ));
+        let result = update_item(&mut tx, 2, new_item(), user.clone(), Some(noon)).await;
+        assert!(matches!(
+            result,
+            Err(crate::infra::error::ApiError::ClientError(
+                ClientError::NotFound
+            ))
+        ));
+
+        // Sub-second precision is ignored, like in HTTP dates
+        let updated = update_item(&mut tx, 1, new_item(), user, Some(noon))
+            .await
+            .unwrap();
+        assert_eq!("Bar", updated.name);
+        assert!(updated.updated_at > noon);
+    }
+
+    #[sqlx::test]
+    async fn stream_stops_after_max_duration(db: PgPool) {
+        let mut tx = db.begin().await.unwrap();
+        let user =
+            crate::infra::security::authenticate(&mut tx, "user", "user", bcrypt::DEFAULT_COST)
+                .await
+                .unwrap();
+        for name in ["Foo", "Bar", "Baz"] {
+            let new_item = NewItem {
+                name: name.to_string(),
+                description: None,
+            };
+            create_item(&mut tx, Valid::new(new_item).unwrap(), user.clone())
+                .await
+                .unwrap();
+        }
+        tx.commit().await.unwrap();
+
+        let conn = db.acquire().await.unwrap();
+        let start = Instant::now();
+        let items: Vec<_> = stream_items(
+            conn,
+            PaginationParams::default(),
+            Duration::from_secs(1),
+            Duration::from_millis(50),
+        )
+        .collect()
+        .await;
+
+        assert_eq!(1, items.len());
+        assert!(start.elapsed() < Duration::from_millis(500));
+    }
+
+    #[sqlx::test]
+    async fn stalled_reader_releases_connection_after_max_duration(db: PgPool) {
+        let mut tx = db.begin().await.unwrap();
+        create_items(&mut tx, &["Foo", "Bar", "Baz"]).await;
+        tx.commit().await.unwrap();
+        let pool = sqlx::pool::PoolOptions::new()
+            .max_connections(1)
+            .acquire_timeout(Duration::from_secs(5))
+            .connect_with(db.connect_options().as_ref().clone())
+            .await
+            .unwrap();
+
+        let conn = pool.acquire().await.unwrap();
+        let mut items = stream_items(
+            conn,
+            PaginationParams::default(),
+            Duration::ZERO,
+            Duration::from_millis(100),
+        );
+        // Read a single item, then stop reading without dropping the stream
+        items.next().await.unwrap().unwrap();
+
+        let start = Instant::now();
+        pool.acquire().await.unwrap();
+        assert!(start.elapsed() < Duration::from_secs(1));
+        drop(items);
+    }
 }
@@ -1,28 +1,46 @@
 //! A service for interacting with items.
 
 use crate::{
-    api::item::item_repository::{self, Item, NewItem},
+    api::{
+        item::{
+            attachment_repository::{self, Attachment, NewAttachment},
+            item_repository::{self, Item, NewItem},
+        },
+        user::user_repository,
+    },
     infra::{
         database::{DbConnection, Tx},
-        error::ApiResult,
+        error::{ApiResult, ClientError, InternalError},
         pagination::PaginationParams,
+        security::{KnownRole, User},
         validation::Valid,
     },
 };
 use futures::Stream;
 use std::time::Duration;
+use time::OffsetDateTime;
 use tracing::instrument;
 
 /// Creates a new item.
 #[instrument(skip(tx))]
-pub async fn create_item(tx: &mut Tx, new_item: Valid<NewItem>) -> ApiResult<Item> {
-    item_repository::create_item(tx, new_item).await
+pub async fn create_item<R>(
+    tx: &mut Tx,
+    new_item: Valid<NewItem>,
+    user: User<R>,
+) -> ApiResult<Item> {
+    item_repository::create_item(tx, new_item, user).await
 }
 
-/// Updates an item.
+/// Updates an item, unless it has been modified since `unmodified_since`.
 #[instrument(skip(tx))]
-pub async fn update_item(tx: &mut Tx, id: i32, new_item: Valid<NewItem>) -> ApiResult<Item> {
-    item_repository::update_item(tx, id, new_item).await
+pub async fn update_item<R>(
+    tx: &mut Tx,
+    id: i32,
+    new_item: Valid<NewItem>,
+    user: User<R>,
+    unmodified_since: Option<OffsetDateTime>,
+) -> ApiResult<Item> {
+    item_repository::update_item(tx, id, new_item, user, unmodified_since).await
 }
 
 /// Read an item.
@@ -37,12 +55,65 @@ pub async fn delete_item(tx: &mut Tx, id: i32) -> ApiResult<()> {
     item_repository::delete_item(tx, id).await
 }
 
+/// Assigns all items without an owner to the user named `owner`.
+///
+/// Returns the number of items that were assigned.
+#[instrument(skip(tx))]
+pub async fn assign_orphans(tx: &mut Tx, owner: &str) -> ApiResult<u64> {
+    let owner = user_repository::fetch_user_id(tx, owner)
+        .await?
+        .ok_or_else(|| InternalError::Other(format!("default owner {owner} does not exist")))?;
+    item_repository::assign_orphans(tx, owner).await
+}
+
 /// Lists all items.
 #[instrument(skip_all)]
 pub async fn list_items(tx: &mut Tx, params: &PaginationParams) -> ApiResult<Vec<Item>> {
     item_repository::list_items(tx, params).await
 }
 
+/// Finds items with names similar to `query`, most similar first.
+#[instrument(skip(tx, params))]
+pub async fn search_items(
+    tx: &mut Tx,
+    query: &str,
+    threshold: f32,
+    params: &PaginationParams,
+) -> ApiResult<Vec<Item>> {
+    item_repository::search_items(tx, query, threshold, params).await
+}
+
+/// Counts all items.
+#[instrument(skip_all)]
+pub async fn count_items(tx: &mut Tx) -> ApiResult<i64> {
+    item_repository::count_items(tx).await
+}
+
+/// Attaches a file to an existing item.
+///
+/// Only the item's creator or an administrator may do so.
+#[instrument(skip(tx))]
+pub async fn attach_file<R>(
+    tx: &mut Tx,
+    id: i32,
+    new_attachment: NewAttachment,
+    user: User<R>,
+) -> ApiResult<Attachment> {
+    let item = item_repository::fetch_item(tx, id)
+        .await?
+        .ok_or(ClientError::NotFound)?;
+    if item.created_by != Some(user.id()) && user.known_role() != KnownRole::Admin {
+        return Err(ClientError::Forbidden)?;
+    }
+    attachment_repository::upsert_attachment(tx, id, new_attachment, user).await
+}
+
+/// Read an item's attachment.
+#[instrument(skip(tx))]
+pub async fn read_attachment(tx: &mut Tx, id: i32) -> ApiResult<Option<Attachment>> {
+    attachment_repository::fetch_attachment(tx, id).await
+}
+
 /// Streams all items.
 #[allow(clippy::let_with_type_underscore)]
 #[instrument(skip(conn))]
@@ -50,6 +121,7 @@ pub fn stream_items(
     conn: DbConnection,
     params: PaginationParams,
     throttle: Duration,
+    max_duration: Duration,
 ) -> impl Stream<Item = ApiResult<Item>> {
-    item_repository::stream_items(conn, params, throttle)
+    item_repository::stream_items(conn, params, throttle, max_duration)
 }
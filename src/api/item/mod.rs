@@ -1,3 +1,5 @@
+pub mod attachment_repository;
 pub mod item_api;
+pub mod item_events;
 pub mod item_repository;
 pub mod item_service;
@@ -1,21 +1,33 @@
 use axum::Router;
 
-use crate::infra::state::AppState;
+use crate::infra::{config::Feature, state::AppState};
 
 pub mod hello;
 pub mod info;
 pub mod item;
 pub mod request;
+pub mod stats;
 pub mod url;
 pub mod user;
+pub mod webhook;
 
 /// Constructs the full REST API including middleware.
+///
+/// Routes belonging to a disabled [`Feature`] are not mounted.
 pub fn api(state: AppState) -> Router {
-    Router::new()
+    let mut router = Router::new()
         .merge(info::info_api::routes())
         .merge(hello::hello_api::routes())
         .merge(item::item_api::routes())
         .merge(user::user_api::routes())
-        .merge(url::url_api::routes())
-        .with_state(state)
+        .merge(stats::stats_api::routes())
+        .merge(request::request_api::routes())
+        .merge(webhook::webhook_api::routes());
+    if state.is_enabled(Feature::Attachments) {
+        router = router.merge(item::item_api::attachment_routes());
+    }
+    if state.is_enabled(Feature::Urls) {
+        router = router.merge(url::url_api::routes());
+    }
+    router.with_state(state)
 }
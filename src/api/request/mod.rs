@@ -1,3 +1,4 @@
 //! Modules for interacting with requests.
 
+pub mod request_api;
 pub mod request_repository;
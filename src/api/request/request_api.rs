@@ -0,0 +1,175 @@
+//! The request log API implementation.
+
+use crate::{
+    api::request::request_repository::{self, Request},
+    infra::{
+        database::DbPool,
+        error::{ApiResult, ClientError},
+        extract::Query,
+        security::{Admin, User},
+        state::AppState,
+    },
+};
+use axum::{
+    body::Body,
+    extract::State,
+    response::{IntoResponse, Response},
+    Router,
+};
+use axum_extra::routing::{RouterExt, TypedPath};
+use chrono::{DateTime, SecondsFormat, Utc};
+use futures::{stream, StreamExt};
+use http::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tracing::instrument;
+use utoipa::IntoParams;
+
+/// The request log API endpoints.
+pub fn routes() -> Router<AppState> {
+    Router::new().typed_get(export_requests)
+}
+
+#[derive(Deserialize, TypedPath)]
+#[typed_path("/requests/export.csv", rejection(ClientError))]
+struct RequestsExport;
+
+/// The columns of the exported csv.
+const CSV_HEADER: &str =
+    "id,timestamp,host,method,uri,status,response_size,request_body,response_body\r\n";
+
+/// Which requests to export.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, IntoParams)]
+pub struct ExportParams {
+    /// Only include requests made at or after this time.
+    #[param(value_type = Option<String>, example = "2024-01-01T00:00:00Z")]
+    from: Option<DateTime<Utc>>,
+    /// Only include requests made before this time.
+    #[param(value_type = Option<String>, example = "2024-02-01T00:00:00Z")]
+    to: Option<DateTime<Utc>>,
+}
+
+/// Exports the request log as csv.
+///
+/// The rows are streamed from the database, oldest first.
+#[utoipa::path(
+    get,
+    path = "/api/requests/export.csv",
+    tag = "requests",
+    params(ExportParams),
+    responses(
+        (status = 200, description = "One request per row", body = String, content_type = "text/csv"),
+        (status = 400, description = "Bad Request", body = ErrorBody),
+        (status = 401, description = "Unauthorized", body = ErrorBody),
+        (status = 403, description = "Forbidden", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody),
+    ),
+    security(
+        ("basic" = [])
+    )
+)]
+#[instrument(skip_all, fields(from = ?params.from, to = ?params.to))]
+async fn export_requests(
+    RequestsExport: RequestsExport,
+    State(db): State<DbPool>,
+    _admin: User<Admin>,
+    Query(params): Query<ExportParams>,
+) -> ApiResult<Response> {
+    if let (Some(from), Some(to)) = (params.from, params.to) {
+        if from > to {
+            return Err(ClientError::BadRequest(
+                "`from` must not be after `to`".to_string(),
+            ))?;
+        }
+    }
+    let from = params.from.map(to_offset_date_time).transpose()?;
+    let to = params.to.map(to_offset_date_time).transpose()?;
+    let conn = db.acquire().await?;
+    let rows = request_repository::stream_requests(conn, from, to)
+        .map(|request| request.map(|request| csv_row(&request)));
+    let body = stream::once(async { Ok(CSV_HEADER.to_string()) }).chain(rows);
+    Ok((
+        [
+            (CONTENT_TYPE, "text/csv; charset=utf-8"),
+            (CONTENT_DISPOSITION, "attachment; filename=\"requests.csv\""),
+        ],
+        Body::from_stream(body),
+    )
+        .into_response())
+}
+
+/// Converts a timestamp from a query to the type stored in the database.
+fn to_offset_date_time(timestamp: DateTime<Utc>) -> Result<OffsetDateTime, ClientError> {
+    let nanos = timestamp
+        .timestamp_nanos_opt()
+        .ok_or_else(|| ClientError::BadRequest(format!("{timestamp} is out of range")))?;
+    OffsetDateTime::from_unix_timestamp_nanos(nanos.into())
+        .map_err(|_| ClientError::BadRequest(format!("{timestamp} is out of range")))
+}
+
+/// Formats a request as a csv row.
+fn csv_row(request: &Request) -> String {
+    let timestamp = DateTime::<Utc>::from_timestamp(
+        request.timestamp.unix_timestamp(),
+        request.timestamp.nanosecond(),
+    )
+    .map(|t| t.to_rfc3339_opts(SecondsFormat::Micros, true))
+    .unwrap_or_default();
+    let fields = [
+        request.id.to_string(),
+        timestamp,
+        csv_field(&request.host),
+        csv_field(&request.method),
+        csv_field(&request.uri),
+        request.status.to_string(),
+        request
+            .response_size
+            .map(|n| n.to_string())
+            .unwrap_or_default(),
+        request
+            .request_body
+            .as_deref()
+            .map(csv_field)
+            .unwrap_or_default(),
+        request
+            .response_body
+            .as_deref()
+            .map(csv_field)
+            .unwrap_or_default(),
+    ];
+    format!("{}\r\n", fields.join(","))
+}
+
+/// Quotes a csv field if it contains separators, quotes or line breaks.
+///
+/// Fields that a spreadsheet would read as a formula are prefixed with `'` and quoted.
+fn csv_field(value: &str) -> String {
+    if value.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("\"'{}\"", value.replace('"', "\"\""))
+    } else if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields_are_quoted_when_needed() {
+        assert_eq!("plain", csv_field("plain"));
+        assert_eq!("\"a,b\"", csv_field("a,b"));
+        assert_eq!("\"say \"\"hi\"\"\"", csv_field("say \"hi\""));
+        assert_eq!("\"two\nlines\"", csv_field("two\nlines"));
+        assert_eq!("\"'=1+1\"", csv_field("=1+1"));
+        assert_eq!("\"'+1\"", csv_field("+1"));
+        assert_eq!("\"'-1\"", csv_field("-1"));
+        assert_eq!("\"'@SUM(A1)\"", csv_field("@SUM(A1)"));
+        assert_eq!("\"'\t=1\"", csv_field("\t=1"));
+        assert_eq!("\"'\r=1\"", csv_field("\r=1"));
+        assert_eq!("\"'=HYPERLINK(\"\"x\"\")\"", csv_field("=HYPERLINK(\"x\")"));
+        assert_eq!("a=b", csv_field("a=b"));
+    }
+}
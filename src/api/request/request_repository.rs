@@ -1,6 +1,11 @@
 //! Types and functions for storing and loading requests from the database.
 
-use crate::infra::{database::Tx, error::ApiResult};
+use crate::infra::{
+    database::{DbConnection, Tx},
+    error::ApiResult,
+};
+use async_stream::try_stream;
+use futures::{Stream, StreamExt};
 use serde::{Deserialize, Serialize};
 use time::OffsetDateTime;
 use tracing::instrument;
@@ -20,6 +25,8 @@ pub struct NewRequest {
     pub response_body: Option<String>,
     /// The response status.
     pub status: i32,
+    /// The number of bytes in the response body.
+    pub response_size: Option<i64>,
 }
 
 /// A request.
@@ -41,6 +48,8 @@ pub struct Request {
     pub timestamp: OffsetDateTime,
     /// The response status.
     pub status: i32,
+    /// The number of bytes in the response body.
+    pub response_size: Option<i64>,
 }
 
 /// Creates a new item.
@@ -50,8 +59,8 @@ pub async fn log_request(tx: &mut Tx, new_req: &NewRequest) -> ApiResult<Request
     let req = sqlx::query_as!(
         Request,
         r#"
-        INSERT INTO requests (host, method, uri, request_body, response_body, status)
-        VALUES ($1, $2, $3, $4, $5, $6)
+        INSERT INTO requests (host, method, uri, request_body, response_body, status, response_size)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
         RETURNING *
         "#,
         new_req.host,
@@ -59,7 +68,8 @@ pub async fn log_request(tx: &mut Tx, new_req: &NewRequest) -> ApiResult<Request
         new_req.uri,
         new_req.request_body,
         new_req.response_body,
-        new_req.status
+        new_req.status,
+        new_req.response_size
     )
     .fetch_one(tx.as_mut())
     .await?;
@@ -67,15 +77,50 @@ pub async fn log_request(tx: &mut Tx, new_req: &NewRequest) -> ApiResult<Request
     Ok(req)
 }
 
+/// Streams the requests made in `[from, to)`, oldest first.
+///
+/// Rows are read from the database as the stream is consumed,
+/// so the log does not have to fit in memory.
+#[instrument(skip(conn))]
+pub fn stream_requests(
+    mut conn: DbConnection,
+    from: Option<OffsetDateTime>,
+    to: Option<OffsetDateTime>,
+) -> impl Stream<Item = ApiResult<Request>> {
+    tracing::info!("Streaming requests");
+    let requests = try_stream! {
+        let mut requests = sqlx::query_as!(
+            Request,
+            r#"
+            SELECT * FROM requests
+            WHERE ($1::timestamptz IS NULL OR timestamp >= $1)
+              AND ($2::timestamptz IS NULL OR timestamp < $2)
+            ORDER BY id
+            "#,
+            from,
+            to
+        )
+        .fetch(conn.as_mut());
+        let mut total = 0;
+        while let Some(request) = requests.next().await {
+            yield request?;
+            total += 1;
+        }
+        tracing::info!("Streamed {} requests", total);
+    };
+    Box::pin(requests)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::infra::database::TestTx;
     use sqlx::PgPool;
 
     #[sqlx::test]
     async fn create_works(db: PgPool) {
         tracing_subscriber::fmt().init();
-        let mut tx = db.begin().await.unwrap();
+        let mut tx = TestTx::begin(&db).await;
         let req = log_request(
             &mut tx,
             &NewRequest {
@@ -85,11 +130,13 @@ mod tests {
                 request_body: None,
                 response_body: Some(r#"{"foo": "bar"}"#.to_string()),
                 status: 200,
+                response_size: Some(14),
             },
         )
         .await
         .unwrap();
 
         assert_eq!(req.uri, "/foo/bar");
+        assert_eq!(req.response_size, Some(14));
     }
 }
@@ -0,0 +1,4 @@
+//! Modules for summarizing application data.
+
+pub mod stats_api;
+pub mod stats_repository;
@@ -0,0 +1,51 @@
+//! The stats API implementation.
+
+use crate::{
+    api::stats::stats_repository::{self, Stats},
+    infra::{
+        database::DbPool,
+        error::ApiResult,
+        extract::Json,
+        security::{Admin, User},
+        state::AppState,
+    },
+};
+use axum::{extract::State, routing::get, Router};
+use cached::proc_macro::cached;
+use tracing::instrument;
+
+/// The stats API endpoints.
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/stats", get(stats))
+}
+
+/// Returns a summary of the application's data.
+///
+/// The result is cached for a few seconds to avoid repeatedly counting large tables.
+#[utoipa::path(
+    get,
+    path = "/api/stats",
+    tag = "stats",
+    responses(
+        (status = 200, description = "Ok", body = Stats),
+        (status = 401, description = "Unauthorized", body = ErrorBody),
+        (status = 403, description = "Forbidden", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody),
+    ),
+    security(
+        ("basic" = [])
+    )
+)]
+#[instrument(skip(db))]
+pub async fn stats(db: State<DbPool>, _admin: User<Admin>) -> ApiResult<Json<Stats>> {
+    Ok(Json(cached_stats(&db).await?))
+}
+
+/// Fetches stats, reusing the previous result if it is recent.
+#[cached(time = 10, key = "()", convert = "{}", result = true)]
+async fn cached_stats(db: &DbPool) -> ApiResult<Stats> {
+    let mut tx = db.begin().await?;
+    let stats = stats_repository::fetch_stats(&mut tx).await?;
+    tx.commit().await?;
+    Ok(stats)
+}
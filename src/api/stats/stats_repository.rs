@@ -0,0 +1,86 @@
+//! Functions for aggregating statistics from the database.
+
+use crate::infra::{database::Tx, error::ApiResult};
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+use utoipa::ToSchema;
+
+/// A summary of the application's data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Stats {
+    /// The number of items.
+    #[schema(example = 42)]
+    pub items: i64,
+    /// The number of short URLs.
+    #[schema(example = 7)]
+    pub short_urls: i64,
+    /// The number of logged requests.
+    #[schema(example = 1000)]
+    pub requests: i64,
+    /// The number of logged requests in the last hour.
+    #[schema(example = 12)]
+    pub requests_last_hour: i64,
+}
+
+/// Counts items, short URLs and logged requests.
+#[instrument(skip(tx))]
+pub async fn fetch_stats(tx: &mut Tx) -> ApiResult<Stats> {
+    tracing::info!("Fetching stats");
+    let stats = sqlx::query_as!(
+        Stats,
+        r#"
+        SELECT
+            (SELECT COUNT(*) FROM items) AS "items!",
+            (SELECT COUNT(*) FROM short_urls) AS "short_urls!",
+            (SELECT COUNT(*) FROM requests) AS "requests!",
+            (
+                SELECT COUNT(*) FROM requests
+                WHERE timestamp > NOW() - INTERVAL '1 hour'
+            ) AS "requests_last_hour!"
+        "#
+    )
+    .fetch_one(tx.as_mut())
+    .await?;
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::PgPool;
+
+    #[sqlx::test]
+    async fn counts_seeded_data(db: PgPool) {
+        sqlx::query("INSERT INTO items (name) VALUES ('a'), ('b')")
+            .execute(&db)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO short_urls (name, target, created_by) VALUES ('a', 'https://example.com/', 1)")
+            .execute(&db)
+            .await
+            .unwrap();
+        sqlx::query(
+            r#"
+            INSERT INTO requests (host, method, uri, status, timestamp) VALUES
+                ('localhost', 'GET', '/api/hello', 200, NOW()),
+                ('localhost', 'GET', '/api/hello', 200, NOW() - INTERVAL '2 hours')
+            "#,
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+
+        let mut tx = db.begin().await.unwrap();
+        let stats = fetch_stats(&mut tx).await.unwrap();
+        assert_eq!(
+            Stats {
+                items: 2,
+                short_urls: 1,
+                requests: 2,
+                requests_last_hour: 1,
+            },
+            stats
+        );
+    }
+}
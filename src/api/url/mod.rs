@@ -1,2 +1,3 @@
 pub mod url_api;
 pub mod url_repository;
+pub mod url_service;
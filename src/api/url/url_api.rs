@@ -1,26 +1,42 @@
 //! The url API implementation.
 
 use crate::infra::{
-    database::DbPool,
-    error::{ApiResult, ClientError},
-    extract::Json,
+    config::{Config, PaginationConfig, UrlsConfig},
+    database::{with_retry_on_serialization, DbPool},
+    error::{ApiResult, ClientError, InternalError},
+    extract::{ClientIp, Json, Query},
+    pagination::{Page, PageLinks, PaginationParams},
+    rate_limit::VisitLimiter,
     security::User,
     state::AppState,
     validation::Valid,
 };
-use axum::{extract::State, Router};
+use axum::{
+    extract::{OriginalUri, State},
+    response::{IntoResponse, Response},
+    Router,
+};
 use axum_extra::routing::{RouterExt, TypedPath};
 use http::{HeaderMap, HeaderName, HeaderValue, StatusCode};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use tracing::instrument;
+use utoipa::{IntoParams, ToSchema};
 
-use super::url_repository::{self, NewShortUrl, ShortUrl};
+use super::{
+    url_repository::{self, NewShortUrl, ShortUrl, UpdateShortUrl},
+    url_service::{self, PreviewClient},
+};
 
 /// The url API endpoints.
 pub fn routes() -> Router<AppState> {
     Router::new()
         .typed_post(create_url)
+        .typed_post(import_urls)
         .typed_get(visit_url)
+        .typed_get(preview_url)
+        .typed_put(update_url)
+        .typed_post(disable_url)
+        .typed_post(enable_url)
         .typed_delete(delete_url)
         .typed_get(list_urls)
 }
@@ -29,18 +45,62 @@ pub fn routes() -> Router<AppState> {
 #[typed_path("/urls", rejection(ClientError))]
 struct Urls;
 
+#[derive(Deserialize, TypedPath)]
+#[typed_path("/urls/bulk", rejection(ClientError))]
+struct UrlsBulk;
+
 #[derive(Deserialize, TypedPath)]
 #[typed_path("/urls/:id", rejection(ClientError))]
 struct UrlsId(String);
 
+#[derive(Deserialize, TypedPath)]
+#[typed_path("/urls/:id/preview", rejection(ClientError))]
+struct UrlsIdPreview(String);
+
+#[derive(Deserialize, TypedPath)]
+#[typed_path("/urls/:id/disable", rejection(ClientError))]
+struct UrlsIdDisable(String);
+
+#[derive(Deserialize, TypedPath)]
+#[typed_path("/urls/:id/enable", rejection(ClientError))]
+struct UrlsIdEnable(String);
+
+/// The maximum number of URLs in a single import.
+const MAX_IMPORT_SIZE: usize = 1000;
+
+/// The outcome of importing a single URL.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "status", rename_all = "camelCase")]
+pub enum ImportResult {
+    /// The URL was created.
+    Created {
+        /// The created URL.
+        url: ShortUrl,
+    },
+    /// The name was already taken.
+    Conflict {
+        /// The name of the URL.
+        name: String,
+    },
+    /// The URL was not valid.
+    Invalid {
+        /// The name of the URL.
+        name: String,
+        /// What was wrong with it.
+        message: String,
+    },
+}
+
 /// Shortens a new URL.
 #[utoipa::path(
     post,
     path = "/api/urls",
+    tag = "urls",
     request_body = NewShortUrl,
     responses(
         (status = 201, description = "Created", body = ShortUrl),
         (status = 409, description = "Conflict", body = ErrorBody),
+        (status = 422, description = "Unprocessable Entity", body = ErrorBody),
         (status = 500, description = "Internal Server Error", body = ErrorBody),
     ),
     security(
@@ -51,23 +111,97 @@ struct UrlsId(String);
 async fn create_url(
     Urls: Urls,
     db: State<DbPool>,
+    config: State<Config>,
     user: User,
     Json(new_url): Json<NewShortUrl>,
 ) -> ApiResult<(StatusCode, Json<ShortUrl>)> {
     let new_url = Valid::new(new_url)?;
+    url_service::check_scheme(&new_url.inner().target, &config.urls.allowed_schemes)?;
+    let attempts = config.database.transaction_attempts;
+    let url = with_retry_on_serialization(&db, attempts, |tx| {
+        Box::pin(url_repository::create_url(
+            tx,
+            new_url.clone(),
+            user.clone(),
+        ))
+    })
+    .await?;
+    Ok((StatusCode::CREATED, Json(url)))
+}
+
+/// Shortens many URLs at once.
+///
+/// Every URL is attempted, and the result for each is returned in order.
+/// Taken names and invalid URLs do not prevent the others from being created.
+#[utoipa::path(
+    post,
+    path = "/api/urls/bulk",
+    tag = "urls",
+    request_body = [NewShortUrl],
+    responses(
+        (status = 200, description = "Ok", body = [ImportResult]),
+        (status = 401, description = "Unauthorized", body = ErrorBody),
+        (status = 422, description = "Unprocessable Entity", body = ErrorBody),
+        (status = 500, description = "Internal Server Error", body = ErrorBody),
+    ),
+    security(
+        ("basic" = [])
+    )
+)]
+#[instrument(skip_all)]
+async fn import_urls(
+    UrlsBulk: UrlsBulk,
+    db: State<DbPool>,
+    urls: State<UrlsConfig>,
+    user: User,
+    Json(new_urls): Json<Vec<NewShortUrl>>,
+) -> ApiResult<Json<Vec<ImportResult>>> {
+    if new_urls.len() > MAX_IMPORT_SIZE {
+        return Err(ClientError::UnprocessableEntity(format!(
+            "at most {MAX_IMPORT_SIZE} urls can be imported at once"
+        )))?;
+    }
     let mut tx = db.begin().await?;
-    let url = url_repository::create_url(&mut tx, new_url, user).await?;
+    let mut results = Vec::with_capacity(new_urls.len());
+    for new_url in new_urls {
+        let name = new_url.name.clone();
+        let new_url = match Valid::new(new_url) {
+            Ok(new_url) => new_url,
+            Err(e) => {
+                let message = e.to_string();
+                results.push(ImportResult::Invalid { name, message });
+                continue;
+            }
+        };
+        if let Err(e) = url_service::check_scheme(&new_url.inner().target, &urls.allowed_schemes) {
+            let message = e.to_string();
+            results.push(ImportResult::Invalid { name, message });
+            continue;
+        }
+        let result =
+            match url_repository::create_url_if_absent(&mut tx, new_url, user.clone()).await? {
+                Some(url) => ImportResult::Created { url },
+                None => ImportResult::Conflict { name },
+            };
+        results.push(result);
+    }
     tx.commit().await?;
-    Ok((StatusCode::CREATED, Json(url)))
+    Ok(Json(results))
 }
 
 /// Gets a shortened URL.
+///
+/// Disabled URLs give `410` instead of redirecting,
+/// and URLs visited too often by one client within `urls.visit_limit` give `429`.
 #[utoipa::path(
     get,
     path = "/api/urls/{name}",
+    tag = "urls",
     responses(
         (status = 303, description = "See Other", body = ShortUrl),
         (status = 404, description = "Not Found", body = ErrorBody),
+        (status = 410, description = "Gone", body = ErrorBody),
+        (status = 429, description = "Too Many Requests", body = ErrorBody),
         (status = 500, description = "Internal Server Error", body = ErrorBody),
     )
 )]
@@ -75,26 +209,223 @@ async fn create_url(
 async fn visit_url(
     UrlsId(name): UrlsId,
     db: State<DbPool>,
+    urls: State<UrlsConfig>,
+    limiter: State<VisitLimiter>,
+    ClientIp(ip): ClientIp,
 ) -> ApiResult<(StatusCode, HeaderMap, Json<ShortUrl>)> {
     let mut tx = db.begin().await?;
     let url = url_repository::fetch_url(&mut tx, &name)
         .await?
         .ok_or(ClientError::NotFound)?;
     tx.commit().await?;
+    // Only existing urls are counted, so that probing for names does not fill the limiter
+    limiter.visit(ip, &name, &urls.visit_limit)?;
+    if !url.enabled {
+        return Err(ClientError::Gone)?;
+    }
     let mut hm = HeaderMap::new();
     hm.append(
         HeaderName::from_static("location"),
-        HeaderValue::from_str(&url.target).expect("invalid url"),
+        HeaderValue::from_str(&url.target).map_err(|e| {
+            InternalError::Other(format!("short url {name} has an invalid target: {e}"))
+        })?,
     );
     Ok((StatusCode::SEE_OTHER, hm, Json(url)))
 }
 
+/// Options for previewing a shortened URL.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, IntoParams)]
+pub struct PreviewParams {
+    /// Follow the target to find where it ends up and the title of the page.
+    #[serde(default)]
+    resolve: bool,
+}
+
+/// Where a shortened URL leads.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct UrlPreview {
+    /// The URL the short URL redirects to.
+    #[schema(example = "https://example.com")]
+    pub target: String,
+    /// Where the target ends up after redirects, if resolved.
+    #[schema(example = "https://www.example.com/")]
+    pub resolved_target: Option<String>,
+    /// The title of the page the target ends up at, if resolved.
+    #[schema(example = "Example Domain")]
+    pub title: Option<String>,
+}
+
+/// Shows where a shortened URL leads, without redirecting.
+///
+/// With `resolve`, the target is fetched to find where it ends up and the page title.
+/// If the target cannot be reached, those are left out.
+/// Targets that are not on public addresses are refused with `422`.
+#[utoipa::path(
+    get,
+    path = "/api/urls/{name}/preview",
+    tag = "urls",
+    params(PreviewParams),
+    responses(
+        (status = 200, description = "Ok", body = UrlPreview),
+        (status = 401, description = "Unauthorized", body = ErrorBody),
+        (status = 404, description = "Not Found", body = ErrorBody),
+        (status = 422, description = "Unprocessable Entity", body = ErrorBody),
+        (status = 500, description = "Internal Server Error", body = ErrorBody),
+    ),
+    security(
+        ("basic" = [])
+    )
+)]
+#[instrument(skip_all, fields(id))]
+async fn preview_url(
+    UrlsIdPreview(name): UrlsIdPreview,
+    db: State<DbPool>,
+    client: State<PreviewClient>,
+    _user: User,
+    Query(params): Query<PreviewParams>,
+) -> ApiResult<Json<UrlPreview>> {
+    let mut tx = db.begin().await?;
+    let url = url_repository::fetch_url(&mut tx, &name)
+        .await?
+        .ok_or(ClientError::NotFound)?;
+    tx.commit().await?;
+    let resolved = if params.resolve {
+        client.check_target(&url.target).await?;
+        url_service::resolve(&client, &url.target)
+            .await
+            .inspect_err(|e| tracing::warn!("Failed to resolve {}: {}", url.target, e))
+            .ok()
+    } else {
+        None
+    };
+    let (resolved_target, title) = match resolved {
+        Some(resolved) => (Some(resolved.url), resolved.title),
+        None => (None, None),
+    };
+    Ok(Json(UrlPreview {
+        target: url.target,
+        resolved_target,
+        title,
+    }))
+}
+
+/// Changes the target of a shortened URL.
+///
+/// Only the creator may update a URL, others get `404`.
+#[utoipa::path(
+    put,
+    path = "/api/urls/{name}",
+    tag = "urls",
+    request_body = UpdateShortUrl,
+    responses(
+        (status = 200, description = "Ok", body = ShortUrl),
+        (status = 401, description = "Unauthorized", body = ErrorBody),
+        (status = 404, description = "Not Found", body = ErrorBody),
+        (status = 422, description = "Unprocessable Entity", body = ErrorBody),
+        (status = 500, description = "Internal Server Error", body = ErrorBody),
+    ),
+    security(
+        ("basic" = [])
+    )
+)]
+#[instrument(skip_all, fields(id))]
+async fn update_url(
+    UrlsId(name): UrlsId,
+    db: State<DbPool>,
+    config: State<Config>,
+    user: User,
+    Json(update): Json<UpdateShortUrl>,
+) -> ApiResult<Json<ShortUrl>> {
+    let update = Valid::new(update)?;
+    url_service::check_scheme(&update.inner().target, &config.urls.allowed_schemes)?;
+    let attempts = config.database.transaction_attempts;
+    let url = with_retry_on_serialization(&db, attempts, |tx| {
+        let (name, update, user) = (name.clone(), update.clone(), user.clone());
+        Box::pin(async move { url_repository::update_url(tx, &name, update, user).await })
+    })
+    .await?;
+    Ok(Json(url))
+}
+
+/// Disables a shortened URL, so that visiting it no longer redirects.
+///
+/// The URL is kept and can be enabled again.
+/// Only the creator may disable a URL, others get `404`.
+#[utoipa::path(
+    post,
+    path = "/api/urls/{name}/disable",
+    tag = "urls",
+    responses(
+        (status = 200, description = "Ok", body = ShortUrl),
+        (status = 401, description = "Unauthorized", body = ErrorBody),
+        (status = 404, description = "Not Found", body = ErrorBody),
+        (status = 500, description = "Internal Server Error", body = ErrorBody),
+    ),
+    security(
+        ("basic" = [])
+    )
+)]
+#[instrument(skip_all, fields(id))]
+async fn disable_url(
+    UrlsIdDisable(name): UrlsIdDisable,
+    db: State<DbPool>,
+    config: State<Config>,
+    user: User,
+) -> ApiResult<Json<ShortUrl>> {
+    set_url_enabled(&db, &config, name, false, user).await
+}
+
+/// Enables a disabled shortened URL, so that visiting it redirects again.
+///
+/// Only the creator may enable a URL, others get `404`.
+#[utoipa::path(
+    post,
+    path = "/api/urls/{name}/enable",
+    tag = "urls",
+    responses(
+        (status = 200, description = "Ok", body = ShortUrl),
+        (status = 401, description = "Unauthorized", body = ErrorBody),
+        (status = 404, description = "Not Found", body = ErrorBody),
+        (status = 500, description = "Internal Server Error", body = ErrorBody),
+    ),
+    security(
+        ("basic" = [])
+    )
+)]
+#[instrument(skip_all, fields(id))]
+async fn enable_url(
+    UrlsIdEnable(name): UrlsIdEnable,
+    db: State<DbPool>,
+    config: State<Config>,
+    user: User,
+) -> ApiResult<Json<ShortUrl>> {
+    set_url_enabled(&db, &config, name, true, user).await
+}
+
+async fn set_url_enabled(
+    db: &DbPool,
+    config: &Config,
+    name: String,
+    enabled: bool,
+    user: User,
+) -> ApiResult<Json<ShortUrl>> {
+    let attempts = config.database.transaction_attempts;
+    let url = with_retry_on_serialization(db, attempts, |tx| {
+        let (name, user) = (name.clone(), user.clone());
+        Box::pin(async move { url_repository::set_url_enabled(tx, &name, enabled, user).await })
+    })
+    .await?;
+    Ok(Json(url))
+}
+
 /// Deletes a shortened URL.
 #[utoipa::path(
     delete,
     path = "/api/urls/{id}",
+    tag = "urls",
     responses(
-        (status = 200, description = "Ok", body = ShortUrl),
+        (status = 204, description = "No Content"),
         (status = 404, description = "Not Found", body = ErrorBody),
         (status = 500, description = "Internal Server Error", body = ErrorBody),
     ),
@@ -103,19 +434,38 @@ async fn visit_url(
     )
 )]
 #[instrument(skip_all, fields(id))]
-async fn delete_url(UrlsId(id): UrlsId, db: State<DbPool>, user: User) -> ApiResult<StatusCode> {
-    let mut tx = db.begin().await?;
-    url_repository::delete_url(&mut tx, &id, user).await?;
-    tx.commit().await?;
+async fn delete_url(
+    UrlsId(id): UrlsId,
+    db: State<DbPool>,
+    config: State<Config>,
+    user: User,
+) -> ApiResult<StatusCode> {
+    let attempts = config.database.transaction_attempts;
+    with_retry_on_serialization(&db, attempts, |tx| {
+        let (id, user) = (id.clone(), user.clone());
+        Box::pin(async move { url_repository::delete_url(tx, &id, user).await })
+    })
+    .await?;
     Ok(StatusCode::NO_CONTENT)
 }
 
+/// Filters for listing shortened URLs.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+struct UrlFilter {
+    /// Only list URLs whose target is on this domain or one of its subdomains.
+    domain: Option<String>,
+}
+
 /// Lists all shortened URLs.
 #[utoipa::path(
     get,
     path = "/api/urls",
+    tag = "urls",
+    params(PaginationParams, UrlFilter),
     responses(
-        (status = 200, description = "Success", body = [ShortUrl]),
+        (status = 200, description = "Success", body = [ShortUrl],
+            headers(("link" = String, description = "Links to the first, previous, next and last pages"))),
+        (status = 400, description = "Bad Request", body = ErrorBody),
         (status = 500, description = "Internal error", body = ErrorBody),
     ),
     security(
@@ -123,10 +473,32 @@ async fn delete_url(UrlsId(id): UrlsId, db: State<DbPool>, user: User) -> ApiRes
     )
 )]
 #[instrument(skip_all)]
-async fn list_urls(Urls: Urls, db: State<DbPool>, user: User) -> ApiResult<Json<Vec<ShortUrl>>> {
+async fn list_urls(
+    Urls: Urls,
+    OriginalUri(uri): OriginalUri,
+    db: State<DbPool>,
+    pagination: State<PaginationConfig>,
+    user: User,
+    Query(params): Query<PaginationParams>,
+    Query(filter): Query<UrlFilter>,
+) -> ApiResult<Response> {
+    let params = params.clamp(&pagination);
+    let domain = filter
+        .domain
+        .map(|domain| {
+            url_service::normalize_domain(&domain)
+                .ok_or_else(|| ClientError::BadRequest(format!("invalid domain: {domain}")))
+        })
+        .transpose()?;
+    let domain = domain.as_deref();
     let mut tx = db.begin().await?;
-    let urls = url_repository::list_urls(&mut tx, user).await?;
-    Ok(Json(urls))
+    let urls = url_repository::list_urls(&mut tx, &params, domain, &user).await?;
+    let total = url_repository::count_urls(&mut tx, domain, &user).await?;
+    let links = PageLinks::new(&uri, &params, total);
+    if params.envelope() {
+        return Ok((links, Page::new(urls, total, &params)).into_response());
+    }
+    Ok((links, Json(urls)).into_response())
 }
 
 #[cfg(test)]
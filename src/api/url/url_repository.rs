@@ -3,17 +3,19 @@
 use crate::infra::{
     database::Tx,
     error::{ApiResult, ClientError},
+    pagination::PaginationParams,
     security::User,
     validation::Valid,
 };
+use http::HeaderValue;
 use serde::{Deserialize, Serialize};
 use time::OffsetDateTime;
 use tracing::{instrument, Instrument};
 use utoipa::ToSchema;
-use validator::Validate;
+use validator::{Validate, ValidationError};
 
 /// A new URL to shorten.
-#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema, Validate)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema, Validate)]
 pub struct NewShortUrl {
     /// The name of the shortened URL.
     #[schema(example = "example")]
@@ -21,12 +23,29 @@ pub struct NewShortUrl {
     pub name: String,
     /// The URL to shorten.
     #[schema(example = "https://example.com")]
-    #[validate(url)]
+    #[validate(url, custom(function = "valid_header_value"))]
     pub target: String,
 }
 
+/// A new target for an existing shortened URL.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema, Validate)]
+pub struct UpdateShortUrl {
+    /// The URL to redirect to.
+    #[schema(example = "https://example.com")]
+    #[validate(url, custom(function = "valid_header_value"))]
+    pub target: String,
+}
+
+/// Checks that a target can be used as a `location` header when visited.
+fn valid_header_value(target: &str) -> Result<(), ValidationError> {
+    HeaderValue::from_str(target)
+        .map(|_| ())
+        .map_err(|_| ValidationError::new("header_value"))
+}
+
 /// An existing shortened URL.
 #[derive(Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
 pub struct ShortUrl {
     /// The URL's id.
     #[schema(example = "1")]
@@ -43,6 +62,12 @@ pub struct ShortUrl {
     /// The time the URL was created.
     #[schema(example = "2021-01-01T00:00:00Z")]
     pub created_at: OffsetDateTime,
+    /// The user who last modified the URL.
+    #[schema(example = "2")]
+    pub updated_by: Option<i32>,
+    /// Whether visiting the URL redirects to its target.
+    #[schema(example = true)]
+    pub enabled: bool,
 }
 
 /// Shortens a new URL.
@@ -71,6 +96,33 @@ pub async fn create_url<R>(
     Ok(url)
 }
 
+/// Shortens a new URL, unless the name is already taken.
+#[instrument(skip(tx))]
+pub async fn create_url_if_absent<R>(
+    tx: &mut Tx,
+    new_url: Valid<NewShortUrl>,
+    user: User<R>,
+) -> ApiResult<Option<ShortUrl>> {
+    let new_url = new_url.into_inner();
+    tracing::info!("Creating url {:?} if absent", new_url);
+    let url = sqlx::query_as!(
+        ShortUrl,
+        r#"
+        INSERT INTO short_urls (name, target, created_by)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (name) DO NOTHING
+        RETURNING *
+        "#,
+        new_url.name,
+        new_url.target,
+        user.id()
+    )
+    .fetch_optional(tx.as_mut())
+    .await?;
+    tracing::info!("Created url {:?}", url);
+    Ok(url)
+}
+
 /// Read a shortened URL.
 #[instrument(skip(tx))]
 pub async fn fetch_url(tx: &mut Tx, name: &str) -> ApiResult<Option<ShortUrl>> {
@@ -90,6 +142,69 @@ pub async fn fetch_url(tx: &mut Tx, name: &str) -> ApiResult<Option<ShortUrl>> {
     Ok(item)
 }
 
+/// Changes the target of a shortened URL owned by `user`.
+#[instrument(skip(tx))]
+pub async fn update_url<R>(
+    tx: &mut Tx,
+    name: &str,
+    update: Valid<UpdateShortUrl>,
+    user: User<R>,
+) -> ApiResult<ShortUrl> {
+    let update = update.into_inner();
+    tracing::info!("Updating url {:?}", name);
+    let url = sqlx::query_as!(
+        ShortUrl,
+        r#"
+        UPDATE short_urls
+        SET target = $1, updated_by = $3
+        WHERE name = $2 AND created_by = $3
+        RETURNING *
+        "#,
+        update.target,
+        name,
+        user.id()
+    )
+    .fetch_optional(tx.as_mut())
+    .await?
+    .ok_or_else(|| {
+        tracing::warn!("Url not found");
+        ClientError::NotFound
+    })?;
+    tracing::info!("Updated url {:?}", url);
+    Ok(url)
+}
+
+/// Enables or disables a shortened URL owned by `user`.
+#[instrument(skip(tx))]
+pub async fn set_url_enabled<R>(
+    tx: &mut Tx,
+    name: &str,
+    enabled: bool,
+    user: User<R>,
+) -> ApiResult<ShortUrl> {
+    tracing::info!("Setting url {:?} enabled to {}", name, enabled);
+    let url = sqlx::query_as!(
+        ShortUrl,
+        r#"
+        UPDATE short_urls
+        SET enabled = $1, updated_by = $3
+        WHERE name = $2 AND created_by = $3
+        RETURNING *
+        "#,
+        enabled,
+        name,
+        user.id()
+    )
+    .fetch_optional(tx.as_mut())
+    .await?
+    .ok_or_else(|| {
+        tracing::warn!("Url not found");
+        ClientError::NotFound
+    })?;
+    tracing::info!("Updated url {:?}", url);
+    Ok(url)
+}
+
 /// Deletes a shortened URL.
 #[instrument(skip(tx))]
 pub async fn delete_url<R>(tx: &mut Tx, name: &str, user: User<R>) -> ApiResult<()> {
@@ -116,24 +231,55 @@ pub async fn delete_url<R>(tx: &mut Tx, name: &str, user: User<R>) -> ApiResult<
     Ok(())
 }
 
-/// Lists all shortened urls.
+/// Lists the shortened urls created by `user`.
 #[instrument(skip(tx))]
-pub async fn list_urls<R>(tx: &mut Tx, user: User<R>) -> ApiResult<Vec<ShortUrl>> {
+pub async fn list_urls<R>(
+    tx: &mut Tx,
+    params: &PaginationParams,
+    domain: Option<&str>,
+    user: &User<R>,
+) -> ApiResult<Vec<ShortUrl>> {
     tracing::info!("Listing urls");
     let urls = sqlx::query_as!(
         ShortUrl,
         r#"
-        SELECT * FROM short_urls WHERE created_by = $1
+        SELECT * FROM short_urls
+        WHERE created_by = $1
+        AND ($4::TEXT IS NULL OR url_host(target) = $4 OR url_host(target) LIKE '%.' || $4)
+        ORDER BY id
+        LIMIT $2
+        OFFSET $3
         "#,
         user.id(),
+        params.limit(),
+        params.offset(),
+        domain
     )
     .fetch_all(tx.as_mut())
     .instrument(tracing::info_span!("fetch_all"))
     .await?;
-    tracing::info!("Listed {} items", urls.len());
+    tracing::info!("Listed {} urls", urls.len());
     Ok(urls)
 }
 
+/// Counts the shortened urls created by `user`.
+#[instrument(skip(tx))]
+pub async fn count_urls<R>(tx: &mut Tx, domain: Option<&str>, user: &User<R>) -> ApiResult<i64> {
+    let count = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "count!" FROM short_urls
+        WHERE created_by = $1
+        AND ($2::TEXT IS NULL OR url_host(target) = $2 OR url_host(target) LIKE '%.' || $2)
+        "#,
+        user.id(),
+        domain,
+    )
+    .fetch_one(tx.as_mut())
+    .await?;
+    tracing::info!("Counted {} urls", count);
+    Ok(count)
+}
+
 #[cfg(test)]
 mod tests {
     use sqlx::PgPool;
@@ -141,17 +287,48 @@ mod tests {
     use crate::{
         api::url::url_repository::NewShortUrl,
         infra::{
+            database::TestTx,
             error::{ApiError, ClientError},
+            pagination::PaginationParams,
             validation::Valid,
         },
     };
 
+    #[test]
+    fn short_url_uses_camel_case_and_round_trips() {
+        let url = super::ShortUrl {
+            id: 1,
+            name: "example".to_string(),
+            target: "https://example.com".to_string(),
+            created_by: 1,
+            created_at: time::OffsetDateTime::UNIX_EPOCH,
+            updated_by: None,
+            enabled: true,
+        };
+        let json = serde_json::to_value(&url).unwrap();
+        for key in ["createdBy", "createdAt", "updatedBy"] {
+            assert!(json.get(key).is_some(), "{key} missing from {json}");
+        }
+        assert_eq!(url, serde_json::from_value(json).unwrap());
+    }
+
+    #[test]
+    fn target_that_is_not_a_valid_header_is_rejected() {
+        let new_url = NewShortUrl {
+            name: "example".to_string(),
+            target: "https://example.com/a\nb".to_string(),
+        };
+        let errors = Valid::new(new_url).unwrap_err();
+        assert!(errors.to_string().contains("header_value"), "{errors}");
+    }
+
     #[sqlx::test]
     async fn creating_url_works(db: PgPool) {
-        let mut tx = db.begin().await.unwrap();
-        let user = crate::infra::security::authenticate(&mut tx, "user", "user")
-            .await
-            .unwrap();
+        let mut tx = TestTx::begin(&db).await;
+        let user =
+            crate::infra::security::authenticate(&mut tx, "user", "user", bcrypt::DEFAULT_COST)
+                .await
+                .unwrap();
         let new_url = NewShortUrl {
             name: "example".to_string(),
             target: "https://example.com".to_string(),
@@ -162,10 +339,11 @@ mod tests {
 
     #[sqlx::test]
     async fn fetching_url_works(db: PgPool) {
-        let mut tx = db.begin().await.unwrap();
-        let user = crate::infra::security::authenticate(&mut tx, "user", "user")
-            .await
-            .unwrap();
+        let mut tx = TestTx::begin(&db).await;
+        let user =
+            crate::infra::security::authenticate(&mut tx, "user", "user", bcrypt::DEFAULT_COST)
+                .await
+                .unwrap();
         let new_url = NewShortUrl {
             name: "example".to_string(),
             target: "https://example.com".to_string(),
@@ -180,7 +358,7 @@ mod tests {
 
     #[sqlx::test]
     async fn fetching_nonexistent_url_returns_none(db: PgPool) {
-        let mut tx = db.begin().await.unwrap();
+        let mut tx = TestTx::begin(&db).await;
         let result = super::fetch_url(&mut tx, "nonexistent").await;
         assert!(result.is_ok());
         assert!(result.unwrap().is_none());
@@ -188,10 +366,11 @@ mod tests {
 
     #[sqlx::test]
     async fn deleting_url_works(db: PgPool) {
-        let mut tx = db.begin().await.unwrap();
-        let user = crate::infra::security::authenticate(&mut tx, "user", "user")
-            .await
-            .unwrap();
+        let mut tx = TestTx::begin(&db).await;
+        let user =
+            crate::infra::security::authenticate(&mut tx, "user", "user", bcrypt::DEFAULT_COST)
+                .await
+                .unwrap();
         let new_url = NewShortUrl {
             name: "example".to_string(),
             target: "https://example.com".to_string(),
@@ -205,10 +384,11 @@ mod tests {
 
     #[sqlx::test]
     async fn deleting_nonexistent_url_returns_not_found(db: PgPool) {
-        let mut tx = db.begin().await.unwrap();
-        let user = crate::infra::security::authenticate(&mut tx, "user", "user")
-            .await
-            .unwrap();
+        let mut tx = TestTx::begin(&db).await;
+        let user =
+            crate::infra::security::authenticate(&mut tx, "user", "user", bcrypt::DEFAULT_COST)
+                .await
+                .unwrap();
         let result = super::delete_url(&mut tx, "nonexistent", user).await;
         assert!(matches!(
             result,
@@ -218,10 +398,11 @@ mod tests {
 
     #[sqlx::test]
     async fn listing_urls_works(db: PgPool) {
-        let mut tx = db.begin().await.unwrap();
-        let user = crate::infra::security::authenticate(&mut tx, "user", "user")
-            .await
-            .unwrap();
+        let mut tx = TestTx::begin(&db).await;
+        let user =
+            crate::infra::security::authenticate(&mut tx, "user", "user", bcrypt::DEFAULT_COST)
+                .await
+                .unwrap();
         let new_url = NewShortUrl {
             name: "example".to_string(),
             target: "https://example.com".to_string(),
@@ -229,8 +410,48 @@ mod tests {
         super::create_url(&mut tx, Valid::new(new_url).unwrap(), user.clone())
             .await
             .unwrap();
-        let result = super::list_urls(&mut tx, user).await;
+        let result = super::list_urls(&mut tx, &PaginationParams::default(), None, &user).await;
         assert!(result.is_ok());
         assert_eq!(result.unwrap().len(), 1);
     }
+
+    #[sqlx::test]
+    async fn listing_urls_filters_by_domain(db: PgPool) {
+        let mut tx = TestTx::begin(&db).await;
+        let user =
+            crate::infra::security::authenticate(&mut tx, "user", "user", bcrypt::DEFAULT_COST)
+                .await
+                .unwrap();
+        let targets = [
+            ("exact", "https://Example.com/a"),
+            ("sub", "http://user@docs.example.com:8080/b?c"),
+            ("lookalike", "https://notexample.com/"),
+            ("other", "https://example.org/example.com"),
+        ];
+        for (name, target) in targets {
+            let new_url = NewShortUrl {
+                name: name.to_string(),
+                target: target.to_string(),
+            };
+            super::create_url(&mut tx, Valid::new(new_url).unwrap(), user.clone())
+                .await
+                .unwrap();
+        }
+
+        let params = PaginationParams::default();
+        let urls = super::list_urls(&mut tx, &params, Some("example.com"), &user)
+            .await
+            .unwrap();
+        let names: Vec<_> = urls.iter().map(|url| url.name.as_str()).collect();
+        assert_eq!(vec!["exact", "sub"], names);
+        let count = super::count_urls(&mut tx, Some("example.com"), &user)
+            .await
+            .unwrap();
+        assert_eq!(2, count);
+
+        let urls = super::list_urls(&mut tx, &params, Some("docs.example.com"), &user)
+            .await
+            .unwrap();
+        assert_eq!(1, urls.len());
+    }
 }
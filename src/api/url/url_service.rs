@@ -0,0 +1,382 @@
+//! A service for previewing where shortened URLs lead.
+
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use cached::proc_macro::cached;
+use reqwest::{
+    dns::{Addrs, Name, Resolve, Resolving},
+    redirect::Policy,
+    Client, Url,
+};
+use tracing::instrument;
+
+use crate::infra::{config::UrlsConfig, error::ClientError};
+
+/// The most of a page that is read when looking for its title.
+const MAX_PREVIEW_SIZE: usize = 64 * 1024;
+
+/// How long to wait for a target to respond.
+const PREVIEW_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The most redirects followed when resolving a target.
+const MAX_REDIRECTS: usize = 10;
+
+/// An http client for fetching the targets of short URLs.
+///
+/// Anyone can create a short URL, so unless `urls.allow_private_targets` is set,
+/// the client refuses to connect to loopback, private and link-local addresses,
+/// both for the target and for every redirect.
+#[derive(Clone, Debug)]
+pub struct PreviewClient {
+    client: Client,
+    allow_private: bool,
+}
+
+impl PreviewClient {
+    /// Constructs a client following `config`.
+    pub fn new(config: &UrlsConfig) -> Self {
+        let allow_private = config.allow_private_targets;
+        let mut builder = Client::builder().redirect(Policy::custom(move |attempt| {
+            if attempt.previous().len() >= MAX_REDIRECTS {
+                return attempt.error("too many redirects");
+            }
+            match check_host(attempt.url(), allow_private) {
+                Ok(()) => attempt.follow(),
+                Err(e) => attempt.error(e.to_string()),
+            }
+        }));
+        if !allow_private {
+            builder = builder.dns_resolver(Arc::new(PublicResolver));
+        }
+        let client = builder.build().expect("failed to build preview client");
+        Self {
+            client,
+            allow_private,
+        }
+    }
+
+    /// Checks that `target` points at a public address, before connecting to it.
+    pub async fn check_target(&self, target: &str) -> Result<(), ClientError> {
+        let url = Url::parse(target)
+            .map_err(|e| ClientError::UnprocessableEntity(format!("invalid target: {e}")))?;
+        check_host(&url, self.allow_private)?;
+        if self.allow_private {
+            return Ok(());
+        }
+        if let Some(domain) = url.host_str().filter(|host| ip_literal(host).is_none()) {
+            let port = url.port_or_known_default().unwrap_or(80);
+            let addrs = tokio::net::lookup_host((domain, port)).await.map_err(|e| {
+                ClientError::UnprocessableEntity(format!("target host cannot be resolved: {e}"))
+            })?;
+            for addr in addrs {
+                if !is_public(addr.ip()) {
+                    return Err(not_public(domain));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Checks the parts of a URL that can be checked without DNS: the scheme and ip literals.
+fn check_host(url: &Url, allow_private: bool) -> Result<(), ClientError> {
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(ClientError::UnprocessableEntity(format!(
+            "target scheme `{}` cannot be previewed",
+            url.scheme()
+        )));
+    }
+    if allow_private {
+        return Ok(());
+    }
+    let public = match url.host_str() {
+        Some(host) => match ip_literal(host) {
+            Some(ip) => is_public(ip),
+            None => {
+                let domain = host.trim_end_matches('.').to_ascii_lowercase();
+                domain != "localhost" && !domain.ends_with(".localhost")
+            }
+        },
+        None => false,
+    };
+    if public {
+        Ok(())
+    } else {
+        Err(not_public(url.host_str().unwrap_or_default()))
+    }
+}
+
+/// Parses a URL host as an ip address, if it is one.
+fn ip_literal(host: &str) -> Option<IpAddr> {
+    host.trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse()
+        .ok()
+}
+
+fn not_public(host: &str) -> ClientError {
+    ClientError::UnprocessableEntity(format!("target host `{host}` is not a public address"))
+}
+
+/// Whether an address is reachable on the public internet,
+/// as opposed to loopback, private, link-local or unspecified addresses.
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_v4(ip),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    // 100.64.0.0/10 is shared address space for carrier-grade NAT
+    let shared = a == 100 && (b & 0xc0) == 64;
+    !(ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        || shared)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    let unique_local = (first & 0xfe00) == 0xfc00;
+    let link_local = (first & 0xffc0) == 0xfe80;
+    !(ip.is_loopback() || ip.is_unspecified() || ip.is_multicast() || unique_local || link_local)
+}
+
+/// Resolves host names, but fails if any of their addresses is not public.
+///
+/// Checking at connection time also covers hosts that resolve differently
+/// than when the target was checked.
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> =
+                tokio::net::lookup_host((name.as_str(), 0)).await?.collect();
+            if let Some(addr) = addrs.iter().find(|addr| !is_public(addr.ip())) {
+                let message = format!("{} resolves to {}", name.as_str(), addr.ip());
+                return Err(io::Error::new(io::ErrorKind::PermissionDenied, message).into());
+            }
+            let addrs: Addrs = Box::new(addrs.into_iter());
+            Ok(addrs)
+        })
+    }
+}
+
+/// Where a URL ended up after following redirects.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Resolved {
+    /// The final URL.
+    pub url: String,
+    /// The title of the page, if it has one.
+    pub title: Option<String>,
+}
+
+/// Follows redirects from `target` and reads the title of the page it ends up at.
+///
+/// Successful lookups are cached for five minutes.
+#[cached(
+    size = 100,
+    time = 300,
+    key = "String",
+    convert = r#"{ target.to_string() }"#,
+    result = true
+)]
+#[instrument(skip(client))]
+pub async fn resolve(client: &PreviewClient, target: &str) -> Result<Resolved, reqwest::Error> {
+    let mut response = client
+        .client
+        .get(target)
+        .timeout(PREVIEW_TIMEOUT)
+        .send()
+        .await?;
+    let url = response.url().to_string();
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        body.extend_from_slice(&chunk);
+        if body.len() >= MAX_PREVIEW_SIZE {
+            break;
+        }
+    }
+    let title = title_of(&String::from_utf8_lossy(&body));
+    Ok(Resolved { url, title })
+}
+
+/// Checks that `target` uses one of the `allowed` schemes.
+///
+/// Keeps targets like `javascript:` and `data:` out of redirects.
+pub fn check_scheme(target: &str, allowed: &[String]) -> Result<(), ClientError> {
+    let url = Url::parse(target)
+        .map_err(|e| ClientError::UnprocessableEntity(format!("invalid target: {e}")))?;
+    if allowed
+        .iter()
+        .any(|scheme| scheme.eq_ignore_ascii_case(url.scheme()))
+    {
+        Ok(())
+    } else {
+        Err(ClientError::UnprocessableEntity(format!(
+            "target scheme `{}` is not allowed",
+            url.scheme()
+        )))
+    }
+}
+
+/// Normalizes a domain used to filter URLs by their host, e.g. `Example.com.` to `example.com`.
+///
+/// Returns `None` unless it is a valid hostname.
+pub fn normalize_domain(domain: &str) -> Option<String> {
+    let domain = domain.trim().trim_end_matches('.').to_ascii_lowercase();
+    let valid_label = |label: &str| {
+        (1..=63).contains(&label.len())
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+    };
+    (domain.len() <= 253 && domain.split('.').all(valid_label)).then_some(domain)
+}
+
+/// Extracts the contents of the `<title>` element of an html page.
+fn title_of(html: &str) -> Option<String> {
+    // Lowercasing ascii keeps byte offsets intact
+    let lower = html.to_ascii_lowercase();
+    let start = lower.find("<title")?;
+    let start = start + lower[start..].find('>')? + 1;
+    let end = start + lower[start..].find("</title>")?;
+    let title = html[start..end]
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    (!title.is_empty()).then_some(title)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn https_targets_are_fetched_over_tls() {
+        use tokio::io::AsyncReadExt;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target = format!("https://{}/", listener.local_addr().unwrap());
+        let config = UrlsConfig {
+            allow_private_targets: true,
+            ..UrlsConfig::default()
+        };
+        let client = PreviewClient::new(&config);
+        let request = tokio::spawn(async move { client.client.get(target).send().await });
+
+        // The connection starts with a TLS handshake record
+        let (mut socket, _) = tokio::time::timeout(Duration::from_secs(5), listener.accept())
+            .await
+            .expect("https target was never connected to")
+            .unwrap();
+        let mut first = [0; 1];
+        socket.read_exact(&mut first).await.unwrap();
+        assert_eq!(0x16, first[0]);
+        drop(socket);
+        assert!(request.await.unwrap().is_err());
+    }
+
+    #[test]
+    fn title_is_extracted_and_trimmed() {
+        let html = "<html><head><TITLE lang=\"en\">\n  Example\n  Domain </TITLE></head></html>";
+        assert_eq!(Some("Example Domain".to_string()), title_of(html));
+    }
+
+    #[test]
+    fn only_allowed_schemes_are_accepted() {
+        let allowed = ["http".to_string(), "https".to_string()];
+        assert!(check_scheme("https://example.com", &allowed).is_ok());
+        assert!(check_scheme("HTTP://example.com", &allowed).is_ok());
+        for (target, scheme) in [
+            ("javascript:alert(1)", "javascript"),
+            ("data:text/html,<script>alert(1)</script>", "data"),
+            ("ftp://example.com", "ftp"),
+        ] {
+            match check_scheme(target, &allowed) {
+                Err(ClientError::UnprocessableEntity(message)) => {
+                    assert_eq!(format!("target scheme `{scheme}` is not allowed"), message)
+                }
+                other => panic!("expected {target} to be rejected, got {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn only_public_addresses_are_public() {
+        for ip in ["93.184.216.34", "2606:2800:220:1::1"] {
+            assert!(is_public(ip.parse().unwrap()), "{ip}");
+        }
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[tokio::test]
+    async fn private_targets_are_refused() {
+        let client = PreviewClient::new(&UrlsConfig::default());
+        for target in [
+            "http://127.0.0.1/admin",
+            "http://[::1]:8080/",
+            "http://169.254.169.254/latest/meta-data/",
+            "http://localhost/",
+            "file:///etc/passwd",
+        ] {
+            match client.check_target(target).await {
+                Err(ClientError::UnprocessableEntity(_)) => {}
+                other => panic!("expected {target} to be refused, got {other:?}"),
+            }
+        }
+        assert!(client.check_target("http://93.184.216.34/").await.is_ok());
+    }
+
+    #[test]
+    fn domains_are_normalized() {
+        assert_eq!(
+            Some("example.com".to_string()),
+            normalize_domain(" Example.COM. ")
+        );
+        assert_eq!(Some("localhost".to_string()), normalize_domain("localhost"));
+        for invalid in [
+            "", ".", "a..b", "-a.com", "a-.com", "a_b.com", "%.com", "a.com/x",
+        ] {
+            assert_eq!(None, normalize_domain(invalid), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn missing_or_empty_title_gives_none() {
+        assert_eq!(None, title_of("<html><body>hi</body></html>"));
+        assert_eq!(None, title_of("<title> </title>"));
+        assert_eq!(None, title_of("<title>unterminated"));
+    }
+}
@@ -1 +1,2 @@
 pub mod user_api;
+pub mod user_repository;
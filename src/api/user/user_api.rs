@@ -1,26 +1,47 @@
 //! The user API implementation.
 
-use crate::infra::{
-    error::ApiResult,
-    extract::Json,
-    security::{Admin, Role, User},
-    state::AppState,
+use crate::{
+    api::user::user_repository::{self, Impersonation},
+    infra::{
+        config::{Config, SecurityConfig},
+        database::DbPool,
+        error::{ApiResult, ClientError},
+        extract::{AuthenticatedJson, Json, Query},
+        security::{self, Admin, KnownRole, Role, User},
+        state::AppState,
+        validation::trimmed,
+    },
 };
-use axum::{routing::get, Router};
+use axum::{
+    extract::{Path, State},
+    routing::{get, post, put},
+    Router,
+};
+use http::StatusCode;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
 use tracing::instrument;
+use utoipa::{IntoParams, ToSchema};
+use validator::{Validate, ValidationError};
 
 /// The user API endpoints.
 pub fn routes() -> Router<AppState> {
     Router::new()
+        .route("/me", get(me).put(update_me).delete(delete_me))
         .route("/user", get(user))
         .route("/admin", get(admin))
         .route("/custom", get(custom))
+        .route("/user/password", put(change_password))
+        .route("/users/:id/role", put(update_role))
+        .route("/admin/impersonate/:user_id", post(impersonate))
+        .route("/admin/cache/invalidate", post(invalidate_cache))
 }
 
 /// Authenticates a user.
 #[utoipa::path(
     get,
     path = "/api/user",
+    tag = "users",
     responses(
         (status = 200, description = "Ok", body = i32),
         (status = 401, description = "Unauthorized", body = ErrorBody),
@@ -36,10 +57,137 @@ pub async fn user(user: User) -> ApiResult<Json<i32>> {
     Ok(Json(user.id()))
 }
 
+/// The profile of an authenticated user.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Profile {
+    /// The id of the user.
+    #[schema(example = 1)]
+    pub id: i32,
+    /// The username of the user.
+    #[schema(example = "user")]
+    pub username: String,
+    /// The role of the user.
+    #[schema(example = "user")]
+    pub role: String,
+    /// The administrator acting as the user, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(example = 2)]
+    pub impersonated_by: Option<i32>,
+}
+
+/// Returns the profile of the calling user.
+#[utoipa::path(
+    get,
+    path = "/api/me",
+    tag = "users",
+    responses(
+        (status = 200, description = "Ok", body = Profile),
+        (status = 401, description = "Unauthorized", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody),
+    ),
+    security(
+        ("basic" = [])
+    )
+)]
+#[instrument]
+pub async fn me(user: User) -> ApiResult<Json<Profile>> {
+    Ok(Json(Profile {
+        id: user.id(),
+        username: user.username().to_string(),
+        role: user.role().to_string(),
+        impersonated_by: user.impersonated_by(),
+    }))
+}
+
+/// Changes to the calling user's profile.
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema, Validate)]
+pub struct UpdateProfile {
+    /// The new username.
+    #[schema(example = "user")]
+    #[serde(deserialize_with = "trimmed")]
+    #[validate(length(min = 1, max = 100), custom(function = "valid_username"))]
+    pub username: String,
+}
+
+/// Checks that a username can be sent with basic auth,
+/// which separates the username from the password with a colon.
+fn valid_username(username: &str) -> Result<(), ValidationError> {
+    if username.chars().any(|c| c == ':' || c.is_control()) {
+        return Err(ValidationError::new("username_characters"));
+    }
+    Ok(())
+}
+
+/// Updates the profile of the calling user.
+///
+/// Existing sessions keep working, but basic auth must use the new username.
+#[utoipa::path(
+    put,
+    path = "/api/me",
+    tag = "users",
+    request_body = UpdateProfile,
+    responses(
+        (status = 200, description = "Ok", body = Profile),
+        (status = 401, description = "Unauthorized", body = ErrorBody),
+        (status = 409, description = "Conflict", body = ErrorBody),
+        (status = 422, description = "Unprocessable Entity", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody),
+    ),
+    security(
+        ("basic" = [])
+    )
+)]
+#[instrument(skip(db))]
+pub async fn update_me(
+    db: State<DbPool>,
+    AuthenticatedJson { user, value }: AuthenticatedJson<UpdateProfile>,
+) -> ApiResult<Json<Profile>> {
+    let update = value.into_inner();
+    let mut tx = db.begin().await?;
+    user_repository::update_username(&mut tx, user.id(), &update.username).await?;
+    tx.commit().await?;
+    // Cached credentials still carry the old username
+    security::invalidate_user_id_auth_cache(user.id()).await;
+    Ok(Json(Profile {
+        id: user.id(),
+        username: update.username,
+        role: user.role().to_string(),
+        impersonated_by: user.impersonated_by(),
+    }))
+}
+
+/// Deletes the calling user's account along with their short urls and items.
+///
+/// Existing sessions are rejected on next use, since the user no longer exists.
+#[utoipa::path(
+    delete,
+    path = "/api/me",
+    tag = "users",
+    responses(
+        (status = 204, description = "No Content"),
+        (status = 401, description = "Unauthorized", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody),
+    ),
+    security(
+        ("basic" = [])
+    )
+)]
+#[instrument(skip(db))]
+pub async fn delete_me(db: State<DbPool>, user: User) -> ApiResult<StatusCode> {
+    let mut tx = db.begin().await?;
+    user_repository::delete_user(&mut tx, user.id()).await?;
+    tx.commit().await?;
+    security::invalidate_user_id_auth_cache(user.id()).await;
+    security::invalidate_role_cache(user.id()).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
 /// Authenticates an admin user.
 #[utoipa::path(
     get,
     path = "/api/admin",
+    tag = "users",
     responses(
         (status = 200, description = "Ok", body = i32),
         (status = 401, description = "Unauthorized", body = ErrorBody),
@@ -70,6 +218,7 @@ impl Role for CustomRole {
 #[utoipa::path(
     get,
     path = "/api/custom",
+    tag = "users",
     responses(
         (status = 200, description = "Ok", body = i32),
         (status = 401, description = "Unauthorized", body = ErrorBody),
@@ -85,3 +234,167 @@ pub async fn custom(user: User<CustomRole>) -> ApiResult<Json<i32>> {
     tracing::info!("Custom user logged in");
     Ok(Json(user.id()))
 }
+
+/// A new password.
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct NewPassword {
+    /// The new password.
+    #[schema(example = "Str0ng!pass")]
+    pub password: String,
+}
+
+impl std::fmt::Debug for NewPassword {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NewPassword").finish_non_exhaustive()
+    }
+}
+
+/// Changes the password of the calling user.
+#[utoipa::path(
+    put,
+    path = "/api/user/password",
+    tag = "users",
+    request_body = NewPassword,
+    responses(
+        (status = 204, description = "No Content"),
+        (status = 401, description = "Unauthorized", body = ErrorBody),
+        (status = 422, description = "Unprocessable Entity", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody),
+    ),
+    security(
+        ("basic" = [])
+    )
+)]
+#[instrument(skip(db, config))]
+pub async fn change_password(
+    db: State<DbPool>,
+    config: State<Config>,
+    user: User,
+    Json(new_password): Json<NewPassword>,
+) -> ApiResult<StatusCode> {
+    security::validate_password(&config.password_policy, &new_password.password)?;
+    let mut tx = db.begin().await?;
+    let cost = config.security.bcrypt_cost;
+    security::change_password(&mut tx, user.id(), &new_password.password, cost).await?;
+    tx.commit().await?;
+    security::invalidate_user_id_auth_cache(user.id()).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// A role to assign to a user.
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct NewRole {
+    /// The name of the role.
+    #[schema(example = "admin")]
+    pub role: String,
+}
+
+/// Assigns a role to a user.
+#[utoipa::path(
+    put,
+    path = "/api/users/{id}/role",
+    tag = "users",
+    request_body = NewRole,
+    responses(
+        (status = 204, description = "No Content"),
+        (status = 401, description = "Unauthorized", body = ErrorBody),
+        (status = 403, description = "Forbidden", body = ErrorBody),
+        (status = 404, description = "Not Found", body = ErrorBody),
+        (status = 422, description = "Unprocessable Entity", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody),
+    ),
+    security(
+        ("basic" = [])
+    )
+)]
+#[instrument(skip(db))]
+pub async fn update_role(
+    db: State<DbPool>,
+    admin: User<Admin>,
+    Path(id): Path<i32>,
+    Json(new_role): Json<NewRole>,
+) -> ApiResult<StatusCode> {
+    let role: KnownRole = new_role
+        .role
+        .parse()
+        .map_err(ClientError::UnprocessableEntity)?;
+    let mut tx = db.begin().await?;
+    let username = user_repository::update_role(&mut tx, id, role.as_str()).await?;
+    tx.commit().await?;
+    security::invalidate_user_auth_cache(&username).await;
+    security::invalidate_role_cache(id).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Lets an administrator act as another user.
+///
+/// The returned token is accepted as a bearer token until it expires.
+/// Requests made with it are read-only, any other method is forbidden.
+#[utoipa::path(
+    post,
+    path = "/api/admin/impersonate/{user_id}",
+    tag = "users",
+    responses(
+        (status = 201, description = "Created", body = Impersonation),
+        (status = 401, description = "Unauthorized", body = ErrorBody),
+        (status = 403, description = "Forbidden", body = ErrorBody),
+        (status = 404, description = "Not Found", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody),
+    ),
+    security(
+        ("basic" = [])
+    )
+)]
+#[instrument(skip(db, security))]
+pub async fn impersonate(
+    db: State<DbPool>,
+    security: State<SecurityConfig>,
+    admin: User<Admin>,
+    Path(user_id): Path<i32>,
+) -> ApiResult<(StatusCode, Json<Impersonation>)> {
+    let expires_at = OffsetDateTime::now_utc() + security.impersonation_duration;
+    let mut tx = db.begin().await?;
+    let impersonation =
+        user_repository::create_impersonation(&mut tx, admin.id(), user_id, expires_at).await?;
+    tx.commit().await?;
+    tracing::info!("Admin {} is impersonating user {}", admin.id(), user_id);
+    Ok((StatusCode::CREATED, Json(impersonation)))
+}
+
+/// Whose cached credentials to clear.
+#[derive(Clone, Debug, Serialize, Deserialize, IntoParams)]
+pub struct CacheInvalidation {
+    /// The user to clear cached credentials for. Clears everyone's if missing.
+    #[param(example = "user")]
+    pub username: Option<String>,
+}
+
+/// Clears cached credentials, so they are verified against the database again.
+///
+/// Useful after changing passwords or roles directly in the database.
+#[utoipa::path(
+    post,
+    path = "/api/admin/cache/invalidate",
+    tag = "users",
+    params(CacheInvalidation),
+    responses(
+        (status = 204, description = "No Content"),
+        (status = 401, description = "Unauthorized", body = ErrorBody),
+        (status = 403, description = "Forbidden", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody),
+    ),
+    security(
+        ("basic" = [])
+    )
+)]
+#[instrument]
+pub async fn invalidate_cache(
+    admin: User<Admin>,
+    Query(invalidation): Query<CacheInvalidation>,
+) -> ApiResult<StatusCode> {
+    match invalidation.username {
+        Some(username) => security::invalidate_user_auth_cache(&username).await,
+        None => security::invalidate_auth_cache().await,
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
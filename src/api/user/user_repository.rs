@@ -0,0 +1,240 @@
+//! Types and functions for storing and loading users from the database.
+
+use crate::infra::{
+    database::Tx,
+    error::{ApiResult, ClientError},
+};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tracing::instrument;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// Fetches the id of the user with the given username.
+#[instrument(skip(tx))]
+pub async fn fetch_user_id(tx: &mut Tx, username: &str) -> ApiResult<Option<i32>> {
+    let id = sqlx::query_scalar!(
+        r#"
+        SELECT id FROM users
+        WHERE username = $1
+        "#,
+        username
+    )
+    .fetch_optional(tx.as_mut())
+    .await?;
+    Ok(id)
+}
+
+/// Sets the role of a user, and returns their username.
+#[instrument(skip(tx))]
+pub async fn update_role(tx: &mut Tx, id: i32, role: &str) -> ApiResult<String> {
+    tracing::info!("Updating role");
+    let username = sqlx::query_scalar!(
+        r#"
+        UPDATE users SET role = $1
+        WHERE id = $2
+        RETURNING username
+        "#,
+        role,
+        id
+    )
+    .fetch_optional(tx.as_mut())
+    .await?
+    .ok_or_else(|| {
+        tracing::warn!("User not found");
+        ClientError::NotFound
+    })?;
+
+    tracing::info!("Updated role");
+    Ok(username)
+}
+
+/// Changes the username of a user.
+#[instrument(skip(tx))]
+pub async fn update_username(tx: &mut Tx, id: i32, username: &str) -> ApiResult<()> {
+    tracing::info!("Updating username");
+    let rows = sqlx::query!(
+        r#"
+        UPDATE users SET username = $1
+        WHERE id = $2
+        "#,
+        username,
+        id
+    )
+    .execute(tx.as_mut())
+    .await?;
+
+    if rows.rows_affected() == 0 {
+        tracing::warn!("User not found");
+        return Err(ClientError::NotFound)?;
+    }
+
+    tracing::info!("Updated username");
+    Ok(())
+}
+
+/// Deletes a user together with the short urls, items and attachments they created.
+///
+/// References to the user on rows created by others are cleared.
+/// Impersonations by or of the user are kept as audit entries, but stop being accepted.
+#[instrument(skip(tx))]
+pub async fn delete_user(tx: &mut Tx, id: i32) -> ApiResult<()> {
+    tracing::info!("Deleting user");
+    sqlx::query!(
+        r#"
+        DELETE FROM attachments
+        WHERE created_by = $1
+        "#,
+        id
+    )
+    .execute(tx.as_mut())
+    .await?;
+    sqlx::query!(
+        r#"
+        DELETE FROM items
+        WHERE created_by = $1
+        "#,
+        id
+    )
+    .execute(tx.as_mut())
+    .await?;
+    sqlx::query!(
+        r#"
+        UPDATE items SET updated_by = NULL
+        WHERE updated_by = $1
+        "#,
+        id
+    )
+    .execute(tx.as_mut())
+    .await?;
+    sqlx::query!(
+        r#"
+        DELETE FROM short_urls
+        WHERE created_by = $1
+        "#,
+        id
+    )
+    .execute(tx.as_mut())
+    .await?;
+    sqlx::query!(
+        r#"
+        UPDATE short_urls SET updated_by = NULL
+        WHERE updated_by = $1
+        "#,
+        id
+    )
+    .execute(tx.as_mut())
+    .await?;
+    sqlx::query!(
+        r#"
+        UPDATE impersonations SET expires_at = NOW()
+        WHERE (admin_id = $1 OR user_id = $1) AND expires_at > NOW()
+        "#,
+        id
+    )
+    .execute(tx.as_mut())
+    .await?;
+    let rows = sqlx::query!(
+        r#"
+        DELETE FROM users
+        WHERE id = $1
+        "#,
+        id
+    )
+    .execute(tx.as_mut())
+    .await?;
+
+    if rows.rows_affected() == 0 {
+        tracing::warn!("User not found");
+        return Err(ClientError::NotFound)?;
+    }
+
+    tracing::info!("Deleted user");
+    Ok(())
+}
+
+/// A token that lets an administrator act as another user.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Impersonation {
+    /// The bearer token to authenticate with.
+    pub token: Uuid,
+    /// The user being impersonated.
+    #[schema(example = 1)]
+    pub user_id: i32,
+    /// When the token stops being accepted.
+    #[schema(value_type = String, example = "2021-01-01T00:00:00Z")]
+    pub expires_at: OffsetDateTime,
+}
+
+/// Lets `admin_id` act as `user_id` until `expires_at`.
+///
+/// The stored row doubles as an audit entry of who impersonated whom.
+#[instrument(skip(tx))]
+pub async fn create_impersonation(
+    tx: &mut Tx,
+    admin_id: i32,
+    user_id: i32,
+    expires_at: OffsetDateTime,
+) -> ApiResult<Impersonation> {
+    tracing::info!("Creating impersonation");
+    let impersonation = sqlx::query_as!(
+        Impersonation,
+        r#"
+        INSERT INTO impersonations (token, admin_id, user_id, expires_at)
+        SELECT $1, $2, id, $4 FROM users
+        WHERE id = $3
+        RETURNING token, user_id AS "user_id!", expires_at
+        "#,
+        Uuid::new_v4(),
+        admin_id,
+        user_id,
+        expires_at
+    )
+    .fetch_optional(tx.as_mut())
+    .await?
+    .ok_or(ClientError::NotFound)?;
+    tracing::info!("Created impersonation");
+    Ok(impersonation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infra::{
+        error::ApiError,
+        security::{authenticate, fetch_role},
+    };
+    use sqlx::PgPool;
+
+    #[sqlx::test]
+    async fn update_role_changes_role(db: PgPool) {
+        let mut tx = db.begin().await.unwrap();
+        let user = authenticate(&mut tx, "user", "user", bcrypt::DEFAULT_COST)
+            .await
+            .unwrap();
+        update_role(&mut tx, user.id(), "admin").await.unwrap();
+        let role = fetch_role(&mut tx, user.id()).await.unwrap();
+        assert_eq!(Some("admin".to_string()), role);
+    }
+
+    #[sqlx::test]
+    async fn delete_nonexistent_user_returns_not_found(db: PgPool) {
+        let mut tx = db.begin().await.unwrap();
+        let result = delete_user(&mut tx, 999).await;
+        assert!(matches!(
+            result,
+            Err(ApiError::ClientError(ClientError::NotFound))
+        ));
+    }
+
+    #[sqlx::test]
+    async fn update_role_of_nonexistent_user_returns_not_found(db: PgPool) {
+        let mut tx = db.begin().await.unwrap();
+        let result = update_role(&mut tx, 999, "admin").await;
+        assert!(matches!(
+            result,
+            Err(ApiError::ClientError(ClientError::NotFound))
+        ));
+    }
+}
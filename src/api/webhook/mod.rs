@@ -0,0 +1,3 @@
+pub mod webhook_api;
+pub mod webhook_repository;
+pub mod webhook_service;
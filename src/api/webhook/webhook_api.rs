@@ -0,0 +1,153 @@
+//! The webhook API implementation.
+
+use crate::infra::{
+    database::DbPool,
+    error::{ApiResult, ClientError},
+    extract::{AuthenticatedJson, Json},
+    security::{Admin, User},
+    state::AppState,
+};
+use axum::{extract::State, Router};
+use axum_extra::routing::{RouterExt, TypedPath};
+use http::StatusCode;
+use serde::Deserialize;
+use tracing::instrument;
+
+use super::webhook_repository::{self, NewWebhook, Webhook};
+
+/// The webhook API endpoints.
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .typed_post(create_webhook)
+        .typed_get(list_webhooks)
+        .typed_put(update_webhook)
+        .typed_delete(delete_webhook)
+}
+
+#[derive(Deserialize, TypedPath)]
+#[typed_path("/admin/webhooks", rejection(ClientError))]
+struct Webhooks;
+
+#[derive(Deserialize, TypedPath)]
+#[typed_path("/admin/webhooks/:id", rejection(ClientError))]
+struct WebhooksId(i32);
+
+/// Subscribes a URL to item events.
+///
+/// Each event is POSTed as JSON, signed with the secret in the `x-webhook-signature` header.
+#[utoipa::path(
+    post,
+    path = "/api/admin/webhooks",
+    tag = "webhooks",
+    request_body = NewWebhook,
+    responses(
+        (status = 201, description = "Created", body = Webhook),
+        (status = 401, description = "Unauthorized", body = ErrorBody),
+        (status = 403, description = "Forbidden", body = ErrorBody),
+        (status = 422, description = "Unprocessable Entity", body = ErrorBody),
+        (status = 500, description = "Internal Server Error", body = ErrorBody),
+    ),
+    security(
+        ("basic" = [])
+    )
+)]
+#[instrument(skip_all)]
+async fn create_webhook(
+    Webhooks: Webhooks,
+    db: State<DbPool>,
+    AuthenticatedJson {
+        value: new_webhook, ..
+    }: AuthenticatedJson<NewWebhook, Admin>,
+) -> ApiResult<(StatusCode, Json<Webhook>)> {
+    let mut tx = db.begin().await?;
+    let webhook = webhook_repository::create_webhook(&mut tx, new_webhook).await?;
+    tx.commit().await?;
+    Ok((StatusCode::CREATED, Json(webhook)))
+}
+
+/// Lists all webhooks.
+#[utoipa::path(
+    get,
+    path = "/api/admin/webhooks",
+    tag = "webhooks",
+    responses(
+        (status = 200, description = "Ok", body = [Webhook]),
+        (status = 401, description = "Unauthorized", body = ErrorBody),
+        (status = 403, description = "Forbidden", body = ErrorBody),
+        (status = 500, description = "Internal Server Error", body = ErrorBody),
+    ),
+    security(
+        ("basic" = [])
+    )
+)]
+#[instrument(skip_all)]
+async fn list_webhooks(
+    Webhooks: Webhooks,
+    db: State<DbPool>,
+    _admin: User<Admin>,
+) -> ApiResult<Json<Vec<Webhook>>> {
+    let mut tx = db.begin().await?;
+    let webhooks = webhook_repository::list_webhooks(&mut tx).await?;
+    tx.commit().await?;
+    Ok(Json(webhooks))
+}
+
+/// Replaces a webhook.
+#[utoipa::path(
+    put,
+    path = "/api/admin/webhooks/{id}",
+    tag = "webhooks",
+    request_body = NewWebhook,
+    responses(
+        (status = 200, description = "Ok", body = Webhook),
+        (status = 401, description = "Unauthorized", body = ErrorBody),
+        (status = 403, description = "Forbidden", body = ErrorBody),
+        (status = 404, description = "Not Found", body = ErrorBody),
+        (status = 422, description = "Unprocessable Entity", body = ErrorBody),
+        (status = 500, description = "Internal Server Error", body = ErrorBody),
+    ),
+    security(
+        ("basic" = [])
+    )
+)]
+#[instrument(skip_all, fields(id))]
+async fn update_webhook(
+    WebhooksId(id): WebhooksId,
+    db: State<DbPool>,
+    AuthenticatedJson {
+        value: new_webhook, ..
+    }: AuthenticatedJson<NewWebhook, Admin>,
+) -> ApiResult<Json<Webhook>> {
+    let mut tx = db.begin().await?;
+    let webhook = webhook_repository::update_webhook(&mut tx, id, new_webhook).await?;
+    tx.commit().await?;
+    Ok(Json(webhook))
+}
+
+/// Deletes a webhook.
+#[utoipa::path(
+    delete,
+    path = "/api/admin/webhooks/{id}",
+    tag = "webhooks",
+    responses(
+        (status = 204, description = "No Content"),
+        (status = 401, description = "Unauthorized", body = ErrorBody),
+        (status = 403, description = "Forbidden", body = ErrorBody),
+        (status = 404, description = "Not Found", body = ErrorBody),
+        (status = 500, description = "Internal Server Error", body = ErrorBody),
+    ),
+    security(
+        ("basic" = [])
+    )
+)]
+#[instrument(skip_all, fields(id))]
+async fn delete_webhook(
+    WebhooksId(id): WebhooksId,
+    db: State<DbPool>,
+    _admin: User<Admin>,
+) -> ApiResult<StatusCode> {
+    let mut tx = db.begin().await?;
+    webhook_repository::delete_webhook(&mut tx, id).await?;
+    tx.commit().await?;
+    Ok(StatusCode::NO_CONTENT)
+}
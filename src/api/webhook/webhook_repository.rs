@@ -0,0 +1,253 @@
+//! Types and functions for storing and loading webhooks from the database.
+
+use crate::{
+    api::{item::item_events::ItemEvent, url::url_service},
+    infra::{
+        database::Tx,
+        error::{ApiResult, ClientError},
+        validation::Valid,
+    },
+};
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+use utoipa::ToSchema;
+use validator::{Validate, ValidationError};
+
+/// A new webhook subscription.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema, Validate)]
+pub struct NewWebhook {
+    /// The URL to POST events to.
+    #[schema(example = "https://example.com/hooks/items")]
+    #[validate(url, custom(function = "deliverable_url"))]
+    pub url: String,
+    /// The secret used to sign payloads.
+    #[schema(example = "0123456789abcdef")]
+    #[validate(length(min = 16, max = 255))]
+    pub secret: String,
+    /// The events to subscribe to.
+    #[schema(example = json!(["item_created"]))]
+    #[validate(length(min = 1), custom(function = "known_events"))]
+    pub events: Vec<String>,
+}
+
+impl std::fmt::Debug for NewWebhook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NewWebhook")
+            .field("url", &self.url)
+            .field("events", &self.events)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Checks that events can be delivered to the URL, which must use http or https.
+fn deliverable_url(url: &str) -> Result<(), ValidationError> {
+    let schemes = ["http".to_string(), "https".to_string()];
+    url_service::check_scheme(url, &schemes).map_err(|_| ValidationError::new("unsupported_scheme"))
+}
+
+/// Checks that every event is one that is published.
+fn known_events(events: &[String]) -> Result<(), ValidationError> {
+    if events
+        .iter()
+        .all(|event| ItemEvent::NAMES.contains(&event.as_str()))
+    {
+        Ok(())
+    } else {
+        Err(ValidationError::new("unknown_event"))
+    }
+}
+
+/// An existing webhook subscription.
+///
+/// The secret is write-only, so it is not part of this type.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Webhook {
+    /// The webhook's id.
+    #[schema(example = 1)]
+    pub id: i32,
+    /// The URL to POST events to.
+    #[schema(example = "https://example.com/hooks/items")]
+    pub url: String,
+    /// The events subscribed to.
+    #[schema(example = json!(["item_created"]))]
+    pub events: Vec<String>,
+}
+
+/// A webhook to deliver an event to, including the secret to sign it with.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Subscriber {
+    /// The webhook's id.
+    pub id: i32,
+    /// The URL to POST events to.
+    pub url: String,
+    /// The secret used to sign payloads.
+    pub secret: String,
+}
+
+impl std::fmt::Debug for Subscriber {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Subscriber")
+            .field("id", &self.id)
+            .field("url", &self.url)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Creates a new webhook.
+#[instrument(skip(tx))]
+pub async fn create_webhook(tx: &mut Tx, new_webhook: Valid<NewWebhook>) -> ApiResult<Webhook> {
+    let new_webhook = new_webhook.into_inner();
+    tracing::info!("Creating webhook");
+    let webhook = sqlx::query_as!(
+        Webhook,
+        r#"
+        INSERT INTO webhooks (url, secret, events)
+        VALUES ($1, $2, $3)
+        RETURNING id, url, events
+        "#,
+        new_webhook.url,
+        new_webhook.secret,
+        &new_webhook.events
+    )
+    .fetch_one(tx.as_mut())
+    .await?;
+    tracing::info!("Created webhook {}", webhook.id);
+    Ok(webhook)
+}
+
+/// Lists all webhooks.
+#[instrument(skip_all)]
+pub async fn list_webhooks(tx: &mut Tx) -> ApiResult<Vec<Webhook>> {
+    tracing::info!("Listing webhooks");
+    let webhooks = sqlx::query_as!(
+        Webhook,
+        r#"
+        SELECT id, url, events FROM webhooks
+        ORDER BY id
+        "#
+    )
+    .fetch_all(tx.as_mut())
+    .await?;
+    tracing::info!("Listed {} webhooks", webhooks.len());
+    Ok(webhooks)
+}
+
+/// Lists the webhooks subscribed to an event.
+#[instrument(skip(tx))]
+pub async fn list_subscribers(tx: &mut Tx, event: &str) -> ApiResult<Vec<Subscriber>> {
+    let webhooks = sqlx::query_as!(
+        Subscriber,
+        r#"
+        SELECT id, url, secret FROM webhooks
+        WHERE $1 = ANY(events)
+        ORDER BY id
+        "#,
+        event
+    )
+    .fetch_all(tx.as_mut())
+    .await?;
+    Ok(webhooks)
+}
+
+/// Replaces a webhook.
+#[instrument(skip(tx))]
+pub async fn update_webhook(
+    tx: &mut Tx,
+    id: i32,
+    new_webhook: Valid<NewWebhook>,
+) -> ApiResult<Webhook> {
+    let new_webhook = new_webhook.into_inner();
+    tracing::info!("Updating webhook");
+    let webhook = sqlx::query_as!(
+        Webhook,
+        r#"
+        UPDATE webhooks
+        SET url = $1, secret = $2, events = $3
+        WHERE id = $4
+        RETURNING id, url, events
+        "#,
+        new_webhook.url,
+        new_webhook.secret,
+        &new_webhook.events,
+        id
+    )
+    .fetch_optional(tx.as_mut())
+    .await?
+    .ok_or(ClientError::NotFound)?;
+    tracing::info!("Updated webhook");
+    Ok(webhook)
+}
+
+/// Deletes a webhook.
+#[instrument(skip(tx))]
+pub async fn delete_webhook(tx: &mut Tx, id: i32) -> ApiResult<()> {
+    tracing::info!("Deleting webhook");
+    let rows = sqlx::query!(
+        r#"
+        DELETE FROM webhooks
+        WHERE id = $1
+        "#,
+        id
+    )
+    .execute(tx.as_mut())
+    .await?;
+
+    if rows.rows_affected() == 0 {
+        tracing::warn!("Webhook not found");
+        return Err(ClientError::NotFound)?;
+    }
+
+    tracing::info!("Deleted webhook");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::PgPool;
+
+    fn new_webhook(events: &[&str]) -> NewWebhook {
+        NewWebhook {
+            url: "https://example.com/hook".to_string(),
+            secret: "0123456789abcdef".to_string(),
+            events: events.iter().map(|e| e.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn only_http_urls_are_valid() {
+        let with_url = |url: &str| NewWebhook {
+            url: url.to_string(),
+            ..new_webhook(&["item_created"])
+        };
+        assert!(Valid::new(with_url("https://example.com/hook")).is_ok());
+        assert!(Valid::new(with_url("http://example.com/hook")).is_ok());
+        assert!(Valid::new(with_url("ftp://example.com/hook")).is_err());
+        assert!(Valid::new(with_url("file:///etc/passwd")).is_err());
+    }
+
+    #[test]
+    fn unknown_events_are_invalid() {
+        assert!(Valid::new(new_webhook(&["item_created"])).is_ok());
+        assert!(Valid::new(new_webhook(&["item_exploded"])).is_err());
+        assert!(Valid::new(new_webhook(&[])).is_err());
+    }
+
+    #[sqlx::test]
+    async fn subscribers_are_filtered_by_event(db: PgPool) {
+        let mut tx = db.begin().await.unwrap();
+        let created = Valid::new(new_webhook(&["item_created"])).unwrap();
+        let created = create_webhook(&mut tx, created).await.unwrap();
+        let deleted = Valid::new(new_webhook(&["item_deleted"])).unwrap();
+        create_webhook(&mut tx, deleted).await.unwrap();
+
+        let subscribers = list_subscribers(&mut tx, "item_created").await.unwrap();
+        let ids: Vec<i32> = subscribers.iter().map(|s| s.id).collect();
+        assert_eq!(vec![created.id], ids);
+        assert_eq!("0123456789abcdef", subscribers[0].secret);
+        assert!(list_subscribers(&mut tx, "item_updated")
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
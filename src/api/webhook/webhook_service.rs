@@ -0,0 +1,134 @@
+//! Delivery of item events to webhook subscribers.
+
+use std::time::Duration;
+
+use crate::{
+    api::{item::item_events::ItemEvent, webhook::webhook_repository},
+    infra::{
+        database::DbPool,
+        error::{ApiResult, InternalError},
+    },
+};
+use hmac::{Hmac, Mac};
+use http::header::CONTENT_TYPE;
+use reqwest::Client;
+use sha2::Sha256;
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+
+use super::webhook_repository::Subscriber;
+
+/// The header carrying the signature of a webhook payload.
+pub const SIGNATURE_HEADER: &str = "x-webhook-signature";
+
+/// How many times to try delivering an event to a webhook.
+const MAX_ATTEMPTS: u32 = 3;
+
+/// How long to wait for a webhook to respond.
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Signs a payload with a webhook's secret.
+///
+/// The signature is a hex encoded HMAC-SHA256 of the body, prefixed with `sha256=`.
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("hmac accepts keys of any size");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Delivers item events to the webhooks subscribed to them.
+///
+/// Runs until the event channel is closed.
+pub async fn dispatch(db: DbPool, client: Client, mut events: Receiver<ItemEvent>) {
+    loop {
+        match events.recv().await {
+            Ok(event) => {
+                if let Err(e) = notify(&db, &client, &event).await {
+                    tracing::error!("Failed to dispatch {} to webhooks: {}", event.name(), e);
+                }
+            }
+            Err(RecvError::Lagged(n)) => {
+                tracing::warn!("Webhook dispatch fell behind and missed {} events", n)
+            }
+            Err(RecvError::Closed) => break,
+        }
+    }
+}
+
+/// Starts delivering an event to each of its subscribers.
+async fn notify(db: &DbPool, client: &Client, event: &ItemEvent) -> ApiResult<()> {
+    let mut tx = db.begin().await?;
+    let webhooks = webhook_repository::list_subscribers(&mut tx, event.name()).await?;
+    tx.commit().await?;
+    let body = serde_json::to_vec(event).map_err(InternalError::from)?;
+    for webhook in webhooks {
+        tokio::spawn(deliver(client.clone(), webhook, body.clone()));
+    }
+    Ok(())
+}
+
+/// Posts a payload to a webhook, retrying failed attempts.
+#[tracing::instrument(skip_all, fields(webhook = webhook.id))]
+async fn deliver(client: Client, webhook: Subscriber, body: Vec<u8>) {
+    let signature = sign(&webhook.secret, &body);
+    for attempt in 1..=MAX_ATTEMPTS {
+        let result = client
+            .post(&webhook.url)
+            .timeout(DELIVERY_TIMEOUT)
+            .header(CONTENT_TYPE, "application/json")
+            .header(SIGNATURE_HEADER, &signature)
+            .body(body.clone())
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        match result {
+            Ok(_) => {
+                tracing::info!("Delivered event to webhook {}", webhook.id);
+                return;
+            }
+            Err(e) => {
+                tracing::warn!(
+                    "Failed to deliver event to webhook {} (attempt {}): {}",
+                    webhook.id,
+                    attempt,
+                    e
+                );
+                if attempt < MAX_ATTEMPTS {
+                    tokio::time::sleep(Duration::from_secs(attempt.into())).await;
+                }
+            }
+        }
+    }
+    tracing::error!(
+        "Giving up on delivering event to webhook {} after {} attempts",
+        webhook.id,
+        MAX_ATTEMPTS
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    #[tokio::test]
+    async fn https_webhooks_are_delivered_over_tls() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let webhook = Subscriber {
+            id: 1,
+            url: format!("https://{}/hook", listener.local_addr().unwrap()),
+            secret: "0123456789abcdef".to_string(),
+        };
+        let delivery = tokio::spawn(deliver(Client::new(), webhook, b"{}".to_vec()));
+
+        // The connection starts with a TLS handshake record
+        let (mut socket, _) = tokio::time::timeout(Duration::from_secs(5), listener.accept())
+            .await
+            .expect("https webhook was never connected to")
+            .unwrap();
+        let mut first = [0; 1];
+        socket.read_exact(&mut first).await.unwrap();
+        assert_eq!(0x16, first[0]);
+        delivery.abort();
+    }
+}
@@ -29,15 +29,20 @@
 use std::iter;
 use std::time::Duration;
 
+use crate::api::webhook::webhook_service;
 use crate::infra::database::DbPool;
 use crate::infra::error::PanicHandler;
 use crate::infra::middleware::MakeRequestIdSpan;
-use crate::infra::openapi::ApiDoc;
+use crate::infra::openapi::api_doc;
+use crate::infra::startup::Startup;
 use crate::infra::{config::Config, state::AppState};
 use axum::Router;
+use color_eyre::eyre::eyre;
 use http::header::AUTHORIZATION;
+use http::StatusCode;
 use sqlx::PgPool;
 use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
 use tower::limit::ConcurrencyLimitLayer;
 use tower_http::catch_panic::CatchPanicLayer;
 use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
@@ -47,21 +52,30 @@ use tower_http::trace::{DefaultOnRequest, DefaultOnResponse, TraceLayer};
 use tower_sessions::ExpiredDeletion;
 use tower_sessions_sqlx_store::PostgresStore;
 use tracing::Level;
-use utoipa::OpenApi;
 use utoipa_rapidoc::RapiDoc;
 use utoipa_redoc::{Redoc, Servable};
 use utoipa_swagger_ui::SwaggerUi;
 
 /// Constructs the full axum application.
 pub fn app(state: AppState, config: Config, store: PostgresStore) -> Router {
-    Router::new()
+    let openapi = api_doc(&config.server);
+    let routes = Router::new()
         .nest("/", crate::views::views(state.clone(), config, store))
-        .merge(SwaggerUi::new("/api/swagger-ui").url("/api/openapi.json", ApiDoc::openapi()))
-        .merge(Redoc::with_url("/api/redoc", ApiDoc::openapi()))
+        .merge(SwaggerUi::new("/api/swagger-ui").url("/api/openapi.json", openapi.clone()))
+        .merge(Redoc::with_url("/api/redoc", openapi))
         .merge(RapiDoc::new("/api/openapi.json").path("/api/rapidoc"))
-        .nest("/api", crate::api::api(state.clone()))
-        // Layers
+        .nest("/api", crate::api::api(state.clone()));
+    with_layers(routes, state)
+}
+
+/// Wraps routes in the middleware shared by the whole application.
+fn with_layers(routes: Router, state: AppState) -> Router {
+    routes
         .layer(TimeoutLayer::new(Duration::from_secs(10)))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            crate::infra::middleware::deprecation_headers,
+        ))
         .layer(axum::middleware::from_fn_with_state(
             state.clone(),
             crate::infra::middleware::log_request_response,
@@ -73,41 +87,116 @@ pub fn app(state: AppState, config: Config, store: PostgresStore) -> Router {
                 .on_response(DefaultOnResponse::new().level(Level::INFO))
                 .on_failure(()),
         )
+        // Inside the request id layers, so that panic responses carry the id too
+        .layer(CatchPanicLayer::custom(PanicHandler))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            crate::infra::middleware::access_log,
+        ))
         .layer(PropagateRequestIdLayer::x_request_id())
         .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+        .layer(axum::middleware::from_fn(
+            crate::infra::middleware::canonical_request_id,
+        ))
         .layer(SetSensitiveRequestHeadersLayer::new(iter::once(
             AUTHORIZATION,
         )))
         .layer(ConcurrencyLimitLayer::new(100))
-        .layer(CatchPanicLayer::custom(PanicHandler))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            crate::infra::middleware::count_requests,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            crate::infra::middleware::track_in_flight,
+        ))
 }
 
 /// Starts the axum server.
+///
+/// The listener answers `503 Service Unavailable` until the session store
+/// migrations have completed, and then serves the full application.
 pub async fn run_app(addr: TcpListener, db: PgPool) -> color_eyre::Result<()> {
-    let state = AppState::new(db.clone());
     let config = crate::infra::config::load_config()?;
+    let state = AppState::new(db.clone(), config.clone());
+    let in_flight = state.in_flight().clone();
+    let request_log = state.request_log().clone();
+    let shutdown_timeout = config.server.shutdown_timeout;
 
-    // Set up session store
-    let store = tower_sessions_sqlx_store::PostgresStore::new(db.clone());
+    let shutdown = crate::infra::shutdown::shutdown_token();
 
-    // Run session store migrations
-    while let Err(e) = store.migrate().await {
-        tracing::error!("Failed to run session store migrations: {}", e);
-        tokio::time::sleep(Duration::from_secs(30)).await;
-    }
-    tracing::info!("Completed session store migrations");
+    // Deliver item events to webhooks
+    let dispatch = webhook_service::dispatch(
+        db.clone(),
+        state.http().clone(),
+        state.item_events().subscribe(),
+    );
+    tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move { shutdown.run_until_cancelled(dispatch).await }
+    });
+
+    // Log metrics for deployments without other monitoring
+    let snapshots = crate::infra::metrics::log_snapshots(
+        config.logging.metrics_interval,
+        state.request_counts().clone(),
+        in_flight.clone(),
+        db.clone(),
+    );
+    tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move { shutdown.run_until_cancelled(snapshots).await }
+    });
+
+    let startup = Startup::default();
+    tokio::spawn({
+        let startup = startup.clone();
+        let shutdown = shutdown.clone();
+        async move {
+            // Set up session store
+            let store = tower_sessions_sqlx_store::PostgresStore::new(db.clone());
 
-    // Spawn a task to delete expired sessions
-    let sixty_secs = Duration::from_secs(60);
-    tokio::task::spawn(store.clone().continuously_delete_expired(sixty_secs));
+            // Run session store migrations
+            while let Err(e) = store.migrate().await {
+                tracing::error!("Failed to run session store migrations: {}", e);
+                tokio::select! {
+                    _ = tokio::time::sleep(Duration::from_secs(30)) => {}
+                    _ = shutdown.cancelled() => return,
+                }
+            }
+            tracing::info!("Completed session store migrations");
 
-    let app = app(state, config, store).into_make_service();
+            // Spawn a task to delete expired sessions
+            let sixty_secs = Duration::from_secs(60);
+            let deletion = store.clone().continuously_delete_expired(sixty_secs);
+            tokio::task::spawn(async move { shutdown.run_until_cancelled(deletion).await });
+
+            startup.ready(app(state, config, store));
+        }
+    });
 
     // Run server
     tracing::info!("Starting axum on {}", addr.local_addr().unwrap());
-    let exit_result = axum::serve(addr, app)
-        .with_graceful_shutdown(crate::infra::shutdown::shutdown_signal())
-        .await;
+    let exit_result = crate::infra::shutdown::serve_until(
+        addr,
+        startup.router(),
+        shutdown.cancelled_owned(),
+        in_flight,
+        shutdown_timeout,
+    )
+    .await;
+
+    // Let the request log catch up before exiting
+    request_log.close();
+    if tokio::time::timeout(shutdown_timeout, request_log.wait())
+        .await
+        .is_err()
+    {
+        tracing::warn!(
+            "Abandoned {} requests that were not yet stored in the request log",
+            request_log.len()
+        );
+    }
 
     match exit_result {
         Ok(_) => tracing::info!("Successfully shut down"),
@@ -125,12 +214,55 @@ pub async fn spawn_app() -> String {
 }
 
 /// Spawn a server on a random port with a custom database.
+///
+/// Panics if the server fails to start, see [`try_spawn_app_with_db`].
 pub async fn spawn_app_with_db(db: DbPool) -> String {
+    try_spawn_app_with_db(db)
+        .await
+        .expect("failed to start app")
+        .url
+}
+
+/// A server running in the background.
+#[derive(Debug)]
+pub struct SpawnedApp {
+    /// The url of the API.
+    pub url: String,
+    /// The server task, which completes with the reason the server stopped.
+    pub handle: JoinHandle<color_eyre::Result<()>>,
+}
+
+/// Spawn a server on a random port with a custom database,
+/// and wait until it has started, or return why it could not.
+pub async fn try_spawn_app_with_db(db: DbPool) -> color_eyre::Result<SpawnedApp> {
     let address = "127.0.0.1";
-    let listener = TcpListener::bind(format!("{address}:0")).await.unwrap();
-    let port = listener.local_addr().unwrap().port();
-    tokio::spawn(run_app(listener, db));
-    format!("http://{address}:{port}/api")
+    let listener = TcpListener::bind(format!("{address}:0")).await?;
+    let port = listener.local_addr()?.port();
+    let handle = tokio::spawn(run_app(listener, db));
+    let url = format!("http://{address}:{port}/api");
+    wait_until_started(url, handle).await
+}
+
+/// Wait until a spawned server answers requests, or fail if it stops first.
+async fn wait_until_started(
+    url: String,
+    handle: JoinHandle<color_eyre::Result<()>>,
+) -> color_eyre::Result<SpawnedApp> {
+    loop {
+        if handle.is_finished() {
+            return Err(match handle.await {
+                Ok(Ok(())) => eyre!("app stopped during startup"),
+                Ok(Err(e)) => e.wrap_err("app failed to start"),
+                Err(e) => eyre!(e).wrap_err("app task failed"),
+            });
+        }
+        match reqwest::get(format!("{url}/hello")).await {
+            Ok(r) if r.status() != StatusCode::SERVICE_UNAVAILABLE => {
+                return Ok(SpawnedApp { url, handle });
+            }
+            _ => tokio::time::sleep(Duration::from_millis(10)).await,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -139,9 +271,28 @@ mod tests {
     use crate::{
         api::{
             hello::hello_api::Greeting,
-            item::item_repository::{Item, NewItem},
+            info::info_api::{MigrationStatus, Readiness},
+            item::{
+                item_api::ItemCount,
+                item_events::ItemEvent,
+                item_repository::{Item, NewItem},
+            },
+            stats::stats_repository::Stats,
+            url::{
+                url_api::{ImportResult, UrlPreview},
+                url_repository::ShortUrl,
+            },
+            user::{user_api::Profile, user_repository::Impersonation},
+            webhook::{webhook_repository::Webhook, webhook_service::SIGNATURE_HEADER},
+        },
+        infra::{
+            config::{Dependency, DeprecatedRoute},
+            database::DbPool,
+            error::ErrorBody,
+            pagination::Page,
+            state::AppState,
+            timestamped::Timestamped,
         },
-        infra::{database::DbPool, error::ErrorBody, state::AppState},
         views::login::LoginParams,
     };
     use axum::{body::Body, Router};
@@ -153,9 +304,13 @@ mod tests {
     use tower::ServiceExt;
 
     fn test_app(db: DbPool) -> Router {
-        let store = PostgresStore::new(db.clone());
         let config = crate::infra::config::load_config().unwrap();
-        let state = AppState::new(db);
+        test_app_with_config(db, config)
+    }
+
+    fn test_app_with_config(db: DbPool, config: Config) -> Router {
+        let store = PostgresStore::new(db.clone());
+        let state = AppState::new(db, config.clone());
         app(state, config, store)
     }
 
@@ -176,7 +331,7 @@ mod tests {
         let url = spawn_app_with_db(db).await;
         let client = reqwest::ClientBuilder::default().build().unwrap();
         let response: ErrorBody = client
-            .get(&format!("{url}/user"))
+            .get(format!("{url}/user"))
             .basic_auth("notuser", Some("user"))
             .send()
             .await
@@ -192,7 +347,7 @@ mod tests {
         let url = spawn_app_with_db(db).await;
         let client = reqwest::ClientBuilder::default().build().unwrap();
         let response: i32 = client
-            .get(&format!("{url}/user"))
+            .get(format!("{url}/user"))
             .basic_auth("user", Some("user"))
             .send()
             .await
@@ -203,12 +358,179 @@ mod tests {
         assert_eq!(1, response);
     }
 
+    #[sqlx::test]
+    fn me_gives_profile_of_current_user(db: DbPool) {
+        let url = spawn_app_with_db(db).await;
+        let client = reqwest::ClientBuilder::default().build().unwrap();
+        let response: Profile = client
+            .get(format!("{url}/me"))
+            .basic_auth("admin", Some("admin"))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let expected = Profile {
+            id: 2,
+            username: "admin".to_string(),
+            role: "admin".to_string(),
+            impersonated_by: None,
+        };
+        assert_eq!(expected, response);
+    }
+
+    #[sqlx::test]
+    fn admin_can_impersonate_user_read_only(db: DbPool) {
+        let url = spawn_app_with_db(db.clone()).await;
+        let client = reqwest::ClientBuilder::default().build().unwrap();
+
+        // Only admins may impersonate
+        let response = client
+            .post(format!("{url}/admin/impersonate/1"))
+            .basic_auth("user", Some("user"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(reqwest::StatusCode::FORBIDDEN, response.status());
+
+        let response = client
+            .post(format!("{url}/admin/impersonate/1"))
+            .basic_auth("admin", Some("admin"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(reqwest::StatusCode::CREATED, response.status());
+        let impersonation: Impersonation = response.json().await.unwrap();
+
+        // Acts as the target user
+        let profile: Profile = client
+            .get(format!("{url}/me"))
+            .bearer_auth(impersonation.token)
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let expected = Profile {
+            id: 1,
+            username: "user".to_string(),
+            role: "user".to_string(),
+            impersonated_by: Some(2),
+        };
+        assert_eq!(expected, profile);
+
+        // Destructive actions are forbidden
+        let response = client
+            .delete(format!("{url}/me"))
+            .bearer_auth(impersonation.token)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(reqwest::StatusCode::FORBIDDEN, response.status());
+
+        // The audit log records the admin
+        let admin_id: i32 =
+            sqlx::query_scalar("SELECT admin_id FROM impersonations WHERE user_id = 1")
+                .fetch_one(&db)
+                .await
+                .unwrap();
+        assert_eq!(2, admin_id);
+    }
+
+    #[sqlx::test]
+    fn impersonation_audit_outlives_deleted_user(db: DbPool) {
+        let id = insert_user(&db, "leaving", "leaving", "user").await;
+        let url = spawn_app_with_db(db.clone()).await;
+        let client = reqwest::ClientBuilder::default().build().unwrap();
+        let impersonation: Impersonation = client
+            .post(format!("{url}/admin/impersonate/{id}"))
+            .basic_auth("admin", Some("admin"))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        let response = client
+            .delete(format!("{url}/me"))
+            .basic_auth("leaving", Some("leaving"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(reqwest::StatusCode::NO_CONTENT, response.status());
+
+        // The token no longer works, but the audit entry is kept
+        let response = client
+            .get(format!("{url}/me"))
+            .bearer_auth(impersonation.token)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(reqwest::StatusCode::UNAUTHORIZED, response.status());
+        let audit: (Option<i32>, Option<i32>) =
+            sqlx::query_as("SELECT admin_id, user_id FROM impersonations WHERE token = $1")
+                .bind(impersonation.token)
+                .fetch_one(&db)
+                .await
+                .unwrap();
+        assert_eq!((Some(2), None), audit);
+    }
+
+    #[sqlx::test]
+    fn unknown_impersonation_token_is_unauthorized(db: DbPool) {
+        let url = spawn_app_with_db(db).await;
+        let client = reqwest::ClientBuilder::default().build().unwrap();
+        let response = client
+            .get(format!("{url}/me"))
+            .bearer_auth(uuid::Uuid::new_v4())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(reqwest::StatusCode::UNAUTHORIZED, response.status());
+    }
+
+    #[sqlx::test]
+    fn me_without_credentials_gives_401(db: DbPool) {
+        let url = spawn_app_with_db(db).await;
+        let response = reqwest::get(format!("{url}/me")).await.unwrap();
+        assert_eq!(reqwest::StatusCode::UNAUTHORIZED, response.status());
+    }
+
+    #[sqlx::test]
+    fn exhausted_pool_gives_503(
+        pool_opts: sqlx::postgres::PgPoolOptions,
+        connect_opts: sqlx::postgres::PgConnectOptions,
+    ) {
+        let db = pool_opts
+            .max_connections(1)
+            .acquire_timeout(Duration::from_millis(200))
+            .connect_with(connect_opts)
+            .await
+            .unwrap();
+        let app = test_app(db.clone());
+
+        // Hold the only connection
+        let _conn = db.acquire().await.unwrap();
+
+        let auth = base64::engine::general_purpose::STANDARD.encode("user:user");
+        let req = Request::get("/api/items")
+            .header("Authorization", format!("Basic {}", &auth))
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, res.status());
+        assert_eq!("5", res.headers()["retry-after"]);
+    }
+
     #[sqlx::test]
     fn user_with_wrong_password_gives_401(db: DbPool) {
         let url = spawn_app_with_db(db).await;
         let client = reqwest::ClientBuilder::default().build().unwrap();
         let response: ErrorBody = client
-            .get(&format!("{url}/user"))
+            .get(format!("{url}/user"))
             .basic_auth("user", Some("notuser"))
             .send()
             .await
@@ -224,7 +546,7 @@ mod tests {
         let url = spawn_app_with_db(db).await;
         let client = reqwest::ClientBuilder::default().build().unwrap();
         let response: ErrorBody = client
-            .get(&format!("{url}/admin"))
+            .get(format!("{url}/admin"))
             .basic_auth("user", Some("user"))
             .send()
             .await
@@ -240,7 +562,7 @@ mod tests {
         let url = spawn_app_with_db(db).await;
         let client = reqwest::ClientBuilder::default().build().unwrap();
         let response: i32 = client
-            .get(&format!("{url}/admin"))
+            .get(format!("{url}/admin"))
             .basic_auth("admin", Some("admin"))
             .send()
             .await
@@ -256,7 +578,7 @@ mod tests {
         let url = spawn_app_with_db(db).await;
         let client = reqwest::ClientBuilder::default().build().unwrap();
         let response: i32 = client
-            .get(&format!("{url}/user"))
+            .get(format!("{url}/user"))
             .basic_auth("admin", Some("admin"))
             .send()
             .await
@@ -272,7 +594,7 @@ mod tests {
         let url = spawn_app_with_db(db).await;
         let client = reqwest::ClientBuilder::default().build().unwrap();
         let response: ErrorBody = client
-            .get(&format!("{url}/admin"))
+            .get(format!("{url}/admin"))
             .basic_auth("admin", Some("notadmin"))
             .send()
             .await
@@ -284,122 +606,1461 @@ mod tests {
     }
 
     #[sqlx::test]
-    fn swagger_ui_oneshot(db: DbPool) {
-        let app = test_app(db);
-        let req = Request::get("/api/swagger-ui/index.html")
-            .body(Body::empty())
+    fn weak_password_change_gives_422(db: DbPool) {
+        let url = spawn_app_with_db(db).await;
+        let client = reqwest::ClientBuilder::default().build().unwrap();
+        let response = client
+            .put(format!("{url}/user/password"))
+            .basic_auth("user", Some("user"))
+            .json(&serde_json::json!({ "password": "weak" }))
+            .send()
+            .await
             .unwrap();
-        let result = app.oneshot(req).await.unwrap();
-        assert_eq!(StatusCode::OK, result.status())
+        assert_eq!(reqwest::StatusCode::UNPROCESSABLE_ENTITY, response.status());
+        let error: ErrorBody = response.json().await.unwrap();
+        assert!(error
+            .message()
+            .contains("password (length,uppercase,digit,symbol)"));
     }
 
     #[sqlx::test]
-    fn redoc_oneshot(db: DbPool) {
-        let app = test_app(db);
-        let req = Request::get("/api/redoc").body(Body::empty()).unwrap();
-        let result = app.oneshot(req).await.unwrap();
-        assert_eq!(StatusCode::OK, result.status())
+    fn strong_password_change_allows_login_with_new_password(db: DbPool) {
+        let url = spawn_app_with_db(db).await;
+        let client = reqwest::ClientBuilder::default().build().unwrap();
+        let response = client
+            .put(format!("{url}/user/password"))
+            .basic_auth("user", Some("user"))
+            .json(&serde_json::json!({ "password": "Str0ng!pass" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(reqwest::StatusCode::NO_CONTENT, response.status());
+
+        let response = client
+            .get(format!("{url}/user"))
+            .basic_auth("user", Some("Str0ng!pass"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(reqwest::StatusCode::OK, response.status());
     }
 
-    #[sqlx::test]
-    fn rapidoc_oneshot(db: DbPool) {
-        let app = test_app(db);
-        let req = Request::get("/api/rapidoc").body(Body::empty()).unwrap();
-        let result = app.oneshot(req).await.unwrap();
-        assert_eq!(StatusCode::OK, result.status())
+    /// Inserts a user with a unique username, so cached credentials are not shared between tests.
+    async fn insert_user(db: &DbPool, username: &str, password: &str, role: &str) -> i32 {
+        let hash = bcrypt::hash(password, 4).unwrap();
+        sqlx::query_scalar(
+            "INSERT INTO users (username, password, role) VALUES ($1, $2, $3) RETURNING id",
+        )
+        .bind(username)
+        .bind(hash)
+        .bind(role)
+        .fetch_one(db)
+        .await
+        .unwrap()
     }
 
     #[sqlx::test]
-    fn hello_oneshot(db: DbPool) {
-        let app = test_app(db);
-        let req = Request::get("/api/hello").body(Body::empty()).unwrap();
-        let res = app.oneshot(req).await.unwrap();
-        assert_eq!(StatusCode::OK, res.status());
-        let body: Vec<u8> = res
-            .into_body()
-            .into_data_stream()
-            .filter_map(|res| std::future::ready(res.ok().map(|b| b.to_vec())))
-            .concat()
-            .await;
-        let greeting: Greeting = serde_json::from_slice(&body).unwrap();
-        assert_eq!(Greeting::new("Hello, World!".to_string()), greeting)
+    fn deleted_user_cannot_authenticate_and_loses_urls(db: DbPool) {
+        insert_user(&db, "leaving", "leaving", "user").await;
+        let url = spawn_app_with_db(db.clone()).await;
+        let client = reqwest::ClientBuilder::default().build().unwrap();
+
+        let response = client
+            .post(format!("{url}/urls"))
+            .basic_auth("leaving", Some("leaving"))
+            .json(&serde_json::json!({ "name": "leaving", "target": "https://example.com" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(reqwest::StatusCode::CREATED, response.status());
+
+        let response = client
+            .delete(format!("{url}/me"))
+            .basic_auth("leaving", Some("leaving"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(reqwest::StatusCode::NO_CONTENT, response.status());
+
+        // Can no longer authenticate
+        let response = client
+            .get(format!("{url}/me"))
+            .basic_auth("leaving", Some("leaving"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(reqwest::StatusCode::UNAUTHORIZED, response.status());
+
+        // The url is gone
+        let urls: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM short_urls WHERE name = 'leaving'")
+                .fetch_one(&db)
+                .await
+                .unwrap();
+        assert_eq!(0, urls);
     }
 
     #[sqlx::test]
-    fn hello_oneshot2(db: DbPool) {
+    fn request_span_records_authenticated_user(db: DbPool) {
+        let logs = crate::infra::logging::CapturedLogs::default();
+        let _guard = logs.capture();
+
+        let id = insert_user(&db, "traced", "traced", "user").await;
         let app = test_app(db);
-        let req = Request::get("/api/hello?name=There")
+        let auth = base64::engine::general_purpose::STANDARD.encode("traced:traced");
+        let req = Request::get("/api/user")
+            .header("Authorization", format!("Basic {auth}"))
             .body(Body::empty())
             .unwrap();
         let res = app.oneshot(req).await.unwrap();
         assert_eq!(StatusCode::OK, res.status());
-        let body = res
-            .into_body()
-            .into_data_stream()
-            .filter_map(|res| std::future::ready(res.ok().map(|b| b.to_vec())))
-            .concat()
-            .await;
-        let greeting: Greeting = serde_json::from_slice(&body).unwrap();
-        assert_eq!(Greeting::new("Hello, There!".to_string()), greeting)
+
+        let output = logs.output();
+        let line = output
+            .lines()
+            .find(|l| l.contains("User logged in"))
+            .expect("no log line from handler");
+        assert!(line.contains(&format!("user_id={id}")), "{line}");
+        assert!(line.contains("user_role=\"user\""), "{line}");
+        assert!(!output.contains("traced"), "username was logged: {output}");
     }
 
     #[sqlx::test]
-    fn shorten_url(db: DbPool) {
-        let app = test_app(db);
+    fn validation_failures_are_logged_without_values(db: DbPool) {
+        let logs = crate::infra::logging::CapturedLogs::default();
+        let _guard = logs.capture_at(tracing::Level::DEBUG);
 
-        // Shorten a new URL
+        let app = test_app(db);
         let auth = base64::engine::general_purpose::STANDARD.encode("user:user");
-        let req: Request<Body> = Request::post("/api/urls")
-            .header("Authorization", format!("Basic {}", &auth))
+        let req = Request::post("/api/items")
+            .header("Authorization", format!("Basic {auth}"))
             .header("Content-Type", "application/json")
-            .body(r#"{"name": "example", "target": "https://example.com/"}"#.into())
-            .unwrap();
-        let res = app.clone().oneshot(req).await.unwrap();
-        assert_eq!(StatusCode::CREATED, res.status());
-
-        // Visits the shortened URL
-        let req = Request::get("/api/urls/example")
-            .body(Body::empty())
+            .body(Body::from(r#"{"name": "", "description": "secret-value"}"#))
             .unwrap();
         let res = app.oneshot(req).await.unwrap();
-        assert_eq!(StatusCode::SEE_OTHER, res.status());
-        assert_eq!("https://example.com/", res.headers()["location"]);
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+
+        let output = logs.output();
+        let line = output
+            .lines()
+            .find(|l| l.contains("Validation of"))
+            .expect("no validation log line");
+        assert!(line.contains("DEBUG"), "{line}");
+        assert!(line.contains("NewItem failed"), "{line}");
+        assert!(line.contains("name (length)"), "{line}");
+        assert!(line.contains("uri=/api/items"), "{line}");
+        assert!(
+            !output.contains("secret-value"),
+            "value was logged: {output}"
+        );
     }
 
     #[sqlx::test]
-    fn create_item_responds_with_created(db: DbPool) {
-        let api = spawn_app_with_db(db).await;
-        let client = reqwest::Client::new();
-        let res: reqwest::Response = client
-            .post(&format!("{api}/items"))
-            .basic_auth("user", Some("user"))
-            .json(&NewItem {
-                name: "example".to_string(),
-                description: None,
-            })
+    fn user_can_change_username(db: DbPool) {
+        let id = insert_user(&db, "renaming", "renaming", "user").await;
+        let url = spawn_app_with_db(db).await;
+        let client = reqwest::ClientBuilder::default().build().unwrap();
+
+        let response = client
+            .put(format!("{url}/me"))
+            .basic_auth("renaming", Some("renaming"))
+            .json(&serde_json::json!({ "username": " renamed " }))
             .send()
             .await
             .unwrap();
-        assert_eq!(reqwest::StatusCode::CREATED, res.status());
-        let item = res.json::<Item>().await.unwrap();
-        assert!(item.id > 0);
-        assert_eq!("example", item.name);
-        assert_eq!(None, item.description);
-    }
-
-    #[sqlx::test]
-    fn get_item_responds_with_ok(db: DbPool) {
-        let api = spawn_app_with_db(db).await;
+        assert_eq!(reqwest::StatusCode::OK, response.status());
+        let profile: Profile = response.json().await.unwrap();
+        assert_eq!(id, profile.id);
+        assert_eq!("renamed", profile.username);
 
-        // Create item
-        let client = reqwest::Client::new();
-        let res: reqwest::Response = client
-            .post(&format!("{api}/items"))
-            .basic_auth("user", Some("user"))
-            .json(&NewItem {
-                name: "example".to_string(),
-                description: None,
-            })
+        // The old username no longer works
+        let response = client
+            .get(format!("{url}/me"))
+            .basic_auth("renaming", Some("renaming"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(reqwest::StatusCode::UNAUTHORIZED, response.status());
+
+        let response = client
+            .get(format!("{url}/me"))
+            .basic_auth("renamed", Some("renaming"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(reqwest::StatusCode::OK, response.status());
+        let profile: Profile = response.json().await.unwrap();
+        assert_eq!(id, profile.id);
+    }
+
+    #[sqlx::test]
+    fn password_change_after_rename_rejects_old_password(db: DbPool) {
+        insert_user(&db, "oldname", "Old!pass1", "user").await;
+        let url = spawn_app_with_db(db).await;
+        let client = reqwest::ClientBuilder::default().build().unwrap();
+        let me = |username: &str, password: &str| {
+            client
+                .get(format!("{url}/me"))
+                .basic_auth(username, Some(password))
+                .send()
+        };
+
+        let response = client
+            .put(format!("{url}/me"))
+            .basic_auth("oldname", Some("Old!pass1"))
+            .json(&serde_json::json!({ "username": "newname" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(reqwest::StatusCode::OK, response.status());
+        // Caches the old password under the new username
+        let response = me("newname", "Old!pass1").await.unwrap();
+        assert_eq!(reqwest::StatusCode::OK, response.status());
+
+        let response = client
+            .put(format!("{url}/user/password"))
+            .basic_auth("newname", Some("Old!pass1"))
+            .json(&serde_json::json!({ "password": "New!pass1" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(reqwest::StatusCode::NO_CONTENT, response.status());
+
+        let response = me("newname", "Old!pass1").await.unwrap();
+        assert_eq!(reqwest::StatusCode::UNAUTHORIZED, response.status());
+        let response = me("newname", "New!pass1").await.unwrap();
+        assert_eq!(reqwest::StatusCode::OK, response.status());
+    }
+
+    #[sqlx::test]
+    fn changing_username_to_taken_one_gives_409(db: DbPool) {
+        insert_user(&db, "squatter", "squatter", "user").await;
+        insert_user(&db, "wannabe", "wannabe", "user").await;
+        let url = spawn_app_with_db(db).await;
+        let client = reqwest::ClientBuilder::default().build().unwrap();
+
+        let response = client
+            .put(format!("{url}/me"))
+            .basic_auth("wannabe", Some("wannabe"))
+            .json(&serde_json::json!({ "username": "squatter" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(reqwest::StatusCode::CONFLICT, response.status());
+        let body: ErrorBody = response.json().await.unwrap();
+        assert_eq!("username already taken", body.message());
+    }
+
+    #[sqlx::test]
+    fn username_with_colon_or_control_character_gives_422(db: DbPool) {
+        insert_user(&db, "colonist", "colonist", "user").await;
+        let url = spawn_app_with_db(db).await;
+        let client = reqwest::ClientBuilder::default().build().unwrap();
+
+        for username in ["colon:ist", "tab\tist", "null\0ist"] {
+            let response = client
+                .put(format!("{url}/me"))
+                .basic_auth("colonist", Some("colonist"))
+                .json(&serde_json::json!({ "username": username }))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(
+                reqwest::StatusCode::UNPROCESSABLE_ENTITY,
+                response.status(),
+                "{username:?}"
+            );
+        }
+    }
+
+    #[sqlx::test]
+    fn promoted_user_can_access_admin_endpoint(db: DbPool) {
+        let id = insert_user(&db, "promoted", "promoted", "user").await;
+        let url = spawn_app_with_db(db).await;
+        let client = reqwest::ClientBuilder::default().build().unwrap();
+
+        // Not an admin yet
+        let response = client
+            .get(format!("{url}/admin"))
+            .basic_auth("promoted", Some("promoted"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(reqwest::StatusCode::FORBIDDEN, response.status());
+
+        // Promote
+        let response = client
+            .put(format!("{url}/users/{id}/role"))
+            .basic_auth("admin", Some("admin"))
+            .json(&serde_json::json!({ "role": "admin" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(reqwest::StatusCode::NO_CONTENT, response.status());
+
+        // Now an admin
+        let response = client
+            .get(format!("{url}/admin"))
+            .basic_auth("promoted", Some("promoted"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(reqwest::StatusCode::OK, response.status());
+    }
+
+    #[sqlx::test]
+    fn role_change_ends_sessions(db: DbPool) {
+        // Credentials and roles are cached across tests, so use a user no other test has
+        let id = 4242;
+        sqlx::query("INSERT INTO users (id, username, password, role) VALUES ($1, $2, $3, 'user')")
+            .bind(id)
+            .bind("relogin")
+            .bind(bcrypt::hash("relogin", 4).unwrap())
+            .execute(&db)
+            .await
+            .unwrap();
+        let store = PostgresStore::new(db.clone());
+        store.migrate().await.unwrap();
+        let config = crate::infra::config::load_config().unwrap();
+        let app = app(AppState::new(db, config.clone()), config, store);
+        let req = Request::post("/login")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(Body::from("username=relogin&password=relogin"))
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::SEE_OTHER, res.status());
+        let cookie = res.headers()["set-cookie"].to_str().unwrap();
+        let cookie = cookie.split(';').next().unwrap().to_string();
+        let index = || {
+            Request::get("/")
+                .header("Cookie", &cookie)
+                .body(Body::empty())
+                .unwrap()
+        };
+        let res = app.clone().oneshot(index()).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+
+        let auth = base64::engine::general_purpose::STANDARD.encode("admin:admin");
+        let req = Request::put(format!("/api/users/{id}/role"))
+            .header("Authorization", format!("Basic {auth}"))
+            .header("Content-Type", "application/json")
+            .body(Body::from(r#"{"role": "admin"}"#))
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+
+        // The session still has the old role, so it must log in again
+        let res = app.oneshot(index()).await.unwrap();
+        assert!(res.status().is_redirection(), "{}", res.status());
+        assert_eq!("/login", res.headers()["location"]);
+    }
+
+    #[sqlx::test]
+    fn invalidated_credentials_are_verified_again(db: DbPool) {
+        let id = insert_user(&db, "invalidated", "before", "user").await;
+        let url = spawn_app_with_db(db.clone()).await;
+        let client = reqwest::ClientBuilder::default().build().unwrap();
+        let login = |password: &'static str| {
+            client
+                .get(format!("{url}/user"))
+                .basic_auth("invalidated", Some(password))
+                .send()
+        };
+
+        // Caches the credentials
+        let response = login("before").await.unwrap();
+        assert_eq!(reqwest::StatusCode::OK, response.status());
+
+        // Change the password behind the application's back
+        sqlx::query("UPDATE users SET password = $1 WHERE id = $2")
+            .bind(bcrypt::hash("after", 4).unwrap())
+            .bind(id)
+            .execute(&db)
+            .await
+            .unwrap();
+
+        // Only admins may invalidate
+        let response = client
+            .post(format!("{url}/admin/cache/invalidate?username=invalidated"))
+            .basic_auth("user", Some("user"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(reqwest::StatusCode::FORBIDDEN, response.status());
+
+        let response = client
+            .post(format!("{url}/admin/cache/invalidate?username=invalidated"))
+            .basic_auth("admin", Some("admin"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(reqwest::StatusCode::NO_CONTENT, response.status());
+
+        // The old password is checked against the database and rejected
+        let response = login("before").await.unwrap();
+        assert_eq!(reqwest::StatusCode::UNAUTHORIZED, response.status());
+        let response = login("after").await.unwrap();
+        assert_eq!(reqwest::StatusCode::OK, response.status());
+    }
+
+    #[sqlx::test]
+    fn requests_in_range_are_exported_as_csv(db: DbPool) {
+        for (uri, body, timestamp) in [
+            ("/before", None, "2020-01-01T00:00:00Z"),
+            (
+                "/first",
+                Some("{\"a\": 1, \"b\": 2}"),
+                "2020-01-02T00:00:00Z",
+            ),
+            (
+                "/second",
+                Some("line one\nline two"),
+                "2020-01-03T12:30:00Z",
+            ),
+            ("/after", None, "2020-01-04T00:00:00Z"),
+        ] {
+            sqlx::query(
+                "INSERT INTO requests (host, method, uri, request_body, status, timestamp)
+                VALUES ('self', 'POST', $1, $2, 200, $3::timestamptz)",
+            )
+            .bind(uri)
+            .bind(body)
+            .bind(timestamp)
+            .execute(&db)
+            .await
+            .unwrap();
+        }
+        let url = spawn_app_with_db(db).await;
+        let client = reqwest::ClientBuilder::default().build().unwrap();
+        let export =
+            format!("{url}/requests/export.csv?from=2020-01-02T00:00:00Z&to=2020-01-04T00:00:00Z");
+
+        let response = client
+            .get(&export)
+            .basic_auth("user", Some("user"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(reqwest::StatusCode::FORBIDDEN, response.status());
+
+        let response = client
+            .get(&export)
+            .basic_auth("admin", Some("admin"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(reqwest::StatusCode::OK, response.status());
+        assert_eq!(
+            "text/csv; charset=utf-8",
+            response.headers()[http::header::CONTENT_TYPE]
+        );
+        let csv = response.text().await.unwrap();
+        let rows: Vec<&str> = csv.split("\r\n").collect();
+        assert_eq!(
+            vec![
+                "id,timestamp,host,method,uri,status,response_size,request_body,response_body",
+                "2,2020-01-02T00:00:00.000000Z,self,POST,/first,200,,\"{\"\"a\"\": 1, \"\"b\"\": 2}\",",
+                "3,2020-01-03T12:30:00.000000Z,self,POST,/second,200,,\"line one\nline two\",",
+                "",
+            ],
+            rows
+        );
+
+        // Invalid parameters are named
+        let response = client
+            .get(format!("{url}/requests/export.csv?from=yesterday"))
+            .basic_auth("admin", Some("admin"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(reqwest::StatusCode::BAD_REQUEST, response.status());
+        let body: ErrorBody = response.json().await.unwrap();
+        assert!(body.message().contains("`from`"), "{}", body.message());
+    }
+
+    #[sqlx::test]
+    fn user_cannot_update_roles(db: DbPool) {
+        let url = spawn_app_with_db(db).await;
+        let client = reqwest::ClientBuilder::default().build().unwrap();
+        let response = client
+            .put(format!("{url}/users/1/role"))
+            .basic_auth("user", Some("user"))
+            .json(&serde_json::json!({ "role": "admin" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(reqwest::StatusCode::FORBIDDEN, response.status());
+    }
+
+    #[sqlx::test]
+    fn only_admin_can_get_stats(db: DbPool) {
+        let url = spawn_app_with_db(db).await;
+        let client = reqwest::ClientBuilder::default().build().unwrap();
+        let res = client
+            .post(format!("{url}/items"))
+            .basic_auth("user", Some("user"))
+            .json(&serde_json::json!({ "name": "example" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(reqwest::StatusCode::CREATED, res.status());
+
+        let res = client
+            .get(format!("{url}/stats"))
+            .basic_auth("user", Some("user"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(reqwest::StatusCode::FORBIDDEN, res.status());
+
+        let stats: Stats = client
+            .get(format!("{url}/stats"))
+            .basic_auth("admin", Some("admin"))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(1, stats.items);
+        assert_eq!(0, stats.short_urls);
+    }
+
+    async fn readiness(db: DbPool, required: Vec<Dependency>) -> (StatusCode, Readiness) {
+        let mut config = crate::infra::config::load_config().unwrap();
+        config.mq.host = "127.0.0.1".to_string();
+        config.mq.port = 1;
+        config.readiness.required = required;
+        let app = test_app_with_config(db, config);
+        let req = Request::get("/api/ready").body(Body::empty()).unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        let status = res.status();
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[sqlx::test]
+    fn unreachable_required_mq_is_not_ready(db: DbPool) {
+        let (status, readiness) = readiness(db, vec![Dependency::Database, Dependency::Mq]).await;
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, status);
+        assert!(!readiness.ready);
+        let mq = readiness
+            .dependencies
+            .iter()
+            .find(|d| d.name == "mq")
+            .unwrap();
+        assert!(mq.required);
+        assert!(!mq.healthy);
+        assert!(mq.error.is_some());
+    }
+
+    #[sqlx::test]
+    fn unreachable_optional_mq_is_ready(db: DbPool) {
+        let (status, readiness) = readiness(db, vec![Dependency::Database]).await;
+        assert_eq!(StatusCode::OK, status);
+        assert!(readiness.ready);
+        let mq = readiness
+            .dependencies
+            .iter()
+            .find(|d| d.name == "mq")
+            .unwrap();
+        assert!(!mq.required);
+        assert!(!mq.healthy);
+        let database = readiness
+            .dependencies
+            .iter()
+            .find(|d| d.name == "database")
+            .unwrap();
+        assert!(database.required);
+        assert!(database.healthy);
+    }
+
+    #[sqlx::test]
+    fn admin_can_list_migrations(db: DbPool) {
+        let url = spawn_app_with_db(db).await;
+        let client = reqwest::ClientBuilder::default().build().unwrap();
+        let status: MigrationStatus = client
+            .get(format!("{url}/admin/migrations"))
+            .basic_auth("admin", Some("admin"))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert!(status.applied.iter().any(|m| m.description == "items"));
+        assert!(status.pending.is_empty());
+    }
+
+    #[sqlx::test]
+    fn user_cannot_list_migrations(db: DbPool) {
+        let url = spawn_app_with_db(db).await;
+        let client = reqwest::ClientBuilder::default().build().unwrap();
+        let response = client
+            .get(format!("{url}/admin/migrations"))
+            .basic_auth("user", Some("user"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(reqwest::StatusCode::FORBIDDEN, response.status());
+    }
+
+    #[sqlx::test]
+    fn admin_can_read_redacted_config(db: DbPool) {
+        let url = spawn_app_with_db(db).await;
+        let client = reqwest::ClientBuilder::default().build().unwrap();
+
+        let response = client
+            .get(format!("{url}/debug/config"))
+            .basic_auth("user", Some("user"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(reqwest::StatusCode::FORBIDDEN, response.status());
+
+        let config: serde_json::Value = client
+            .get(format!("{url}/debug/config"))
+            .basic_auth("admin", Some("admin"))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let expected = crate::infra::config::load_config().unwrap();
+        assert_eq!(expected.database.host, config["database"]["host"]);
+        assert_eq!(expected.server.http_port, config["server"]["http_port"]);
+        for section in ["database", "mq", "email"] {
+            assert_eq!("***", config[section]["password"], "{section}");
+        }
+    }
+
+    #[sqlx::test]
+    fn swagger_ui_oneshot(db: DbPool) {
+        let app = test_app(db);
+        let req = Request::get("/api/swagger-ui/index.html")
+            .body(Body::empty())
+            .unwrap();
+        let result = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, result.status())
+    }
+
+    #[sqlx::test]
+    fn redoc_oneshot(db: DbPool) {
+        let app = test_app(db);
+        let req = Request::get("/api/redoc").body(Body::empty()).unwrap();
+        let result = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, result.status())
+    }
+
+    #[sqlx::test]
+    fn rapidoc_oneshot(db: DbPool) {
+        let app = test_app(db);
+        let req = Request::get("/api/rapidoc").body(Body::empty()).unwrap();
+        let result = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, result.status())
+    }
+
+    #[sqlx::test]
+    fn hello_oneshot(db: DbPool) {
+        let app = test_app(db);
+        let req = Request::get("/api/hello").body(Body::empty()).unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let body: Vec<u8> = res
+            .into_body()
+            .into_data_stream()
+            .filter_map(|res| std::future::ready(res.ok().map(|b| b.to_vec())))
+            .concat()
+            .await;
+        let greeting: Greeting = serde_json::from_slice(&body).unwrap();
+        assert_eq!(Greeting::new("Hello, World!".to_string()), greeting)
+    }
+
+    #[sqlx::test]
+    fn hello_oneshot2(db: DbPool) {
+        let app = test_app(db);
+        let req = Request::get("/api/hello?name=There")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let body = res
+            .into_body()
+            .into_data_stream()
+            .filter_map(|res| std::future::ready(res.ok().map(|b| b.to_vec())))
+            .concat()
+            .await;
+        let greeting: Greeting = serde_json::from_slice(&body).unwrap();
+        assert_eq!(Greeting::new("Hello, There!".to_string()), greeting)
+    }
+
+    #[sqlx::test]
+    fn shorten_url(db: DbPool) {
+        let app = test_app(db);
+
+        // Shorten a new URL
+        let auth = base64::engine::general_purpose::STANDARD.encode("user:user");
+        let req: Request<Body> = Request::post("/api/urls")
+            .header("Authorization", format!("Basic {}", &auth))
+            .header("Content-Type", "application/json")
+            .body(r#"{"name": "example", "target": "https://example.com/"}"#.into())
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+
+        // Visits the shortened URL
+        let req = Request::get("/api/urls/example")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::SEE_OTHER, res.status());
+        assert_eq!("https://example.com/", res.headers()["location"]);
+    }
+
+    #[sqlx::test]
+    fn rapid_visits_to_one_url_from_one_client_are_limited(db: DbPool) {
+        sqlx::query(
+            "INSERT INTO short_urls (name, target, created_by)
+            VALUES ('busy', 'https://example.com/', 1), ('quiet', 'https://example.com/', 1)",
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+        let mut config = crate::infra::config::load_config().unwrap();
+        config.urls.visit_limit.visits = 2;
+        let app = test_app_with_config(db, config);
+        let visit = |name: &str, client: [u8; 4]| {
+            let peer = std::net::SocketAddr::from((client, 4000));
+            let req = Request::get(format!("/api/urls/{name}"))
+                .extension(axum::extract::ConnectInfo(peer))
+                .body(Body::empty())
+                .unwrap();
+            app.clone().oneshot(req)
+        };
+        let client = [203, 0, 113, 1];
+
+        // Unknown names are not counted
+        for _ in 0..3 {
+            let res = visit("missing", client).await.unwrap();
+            assert_eq!(StatusCode::NOT_FOUND, res.status());
+        }
+
+        assert_eq!(
+            StatusCode::SEE_OTHER,
+            visit("busy", client).await.unwrap().status()
+        );
+        assert_eq!(
+            StatusCode::SEE_OTHER,
+            visit("busy", client).await.unwrap().status()
+        );
+        let res = visit("busy", client).await.unwrap();
+        assert_eq!(StatusCode::TOO_MANY_REQUESTS, res.status());
+        // Until the one minute window is over
+        let retry_after: u64 = res.headers()["retry-after"]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((1..=60).contains(&retry_after), "{retry_after}");
+
+        // Other clients, other urls and the management endpoints are unaffected
+        let other = [203, 0, 113, 2];
+        assert_eq!(
+            StatusCode::SEE_OTHER,
+            visit("busy", other).await.unwrap().status()
+        );
+        assert_eq!(
+            StatusCode::SEE_OTHER,
+            visit("quiet", client).await.unwrap().status()
+        );
+        let auth = base64::engine::general_purpose::STANDARD.encode("user:user");
+        let req = Request::get("/api/urls")
+            .header("Authorization", format!("Basic {auth}"))
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(StatusCode::OK, app.oneshot(req).await.unwrap().status());
+    }
+
+    #[sqlx::test]
+    fn disabled_url_is_gone_until_enabled(db: DbPool) {
+        let api = spawn_app_with_db(db).await;
+        let client = reqwest::Client::builder()
+            .redirect(Policy::none())
+            .build()
+            .unwrap();
+        let response = client
+            .post(format!("{api}/urls"))
+            .basic_auth("user", Some("user"))
+            .json(&serde_json::json!({ "name": "paused", "target": "https://example.com/" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(reqwest::StatusCode::CREATED, response.status());
+        let visit = || client.get(format!("{api}/urls/paused")).send();
+        let toggle = |user: &str, action: &str| {
+            client
+                .post(format!("{api}/urls/paused/{action}"))
+                .basic_auth(user, Some(user))
+                .send()
+        };
+        assert_eq!(
+            reqwest::StatusCode::SEE_OTHER,
+            visit().await.unwrap().status()
+        );
+
+        // Only the owner may toggle it
+        let response = toggle("admin", "disable").await.unwrap();
+        assert_eq!(reqwest::StatusCode::NOT_FOUND, response.status());
+        assert_eq!(
+            reqwest::StatusCode::SEE_OTHER,
+            visit().await.unwrap().status()
+        );
+
+        let response = toggle("user", "disable").await.unwrap();
+        assert_eq!(reqwest::StatusCode::OK, response.status());
+        let url: ShortUrl = response.json().await.unwrap();
+        assert!(!url.enabled);
+        let response = visit().await.unwrap();
+        assert_eq!(reqwest::StatusCode::GONE, response.status());
+        assert!(response.headers().get("location").is_none());
+
+        // The link is kept while disabled
+        let response = preview(&api, "paused", "").await;
+        assert_eq!(reqwest::StatusCode::OK, response.status());
+        let urls: Vec<ShortUrl> = client
+            .get(format!("{api}/urls"))
+            .basic_auth("user", Some("user"))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(vec![url], urls);
+
+        let response = toggle("user", "enable").await.unwrap();
+        assert_eq!(reqwest::StatusCode::OK, response.status());
+        let response = visit().await.unwrap();
+        assert_eq!(reqwest::StatusCode::SEE_OTHER, response.status());
+        assert_eq!("https://example.com/", response.headers()["location"]);
+    }
+
+    async fn preview(api: &str, name: &str, query: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{api}/urls/{name}/preview{query}"))
+            .basic_auth("user", Some("user"))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    #[sqlx::test]
+    fn preview_shows_target_without_redirecting(db: DbPool) {
+        let api = spawn_app_with_db(db).await;
+        let response = reqwest::Client::new()
+            .post(format!("{api}/urls"))
+            .basic_auth("user", Some("user"))
+            .json(&serde_json::json!({ "name": "peek", "target": "https://example.com/" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(reqwest::StatusCode::CREATED, response.status());
+
+        let response = preview(&api, "peek", "").await;
+        assert_eq!(reqwest::StatusCode::OK, response.status());
+        let preview: UrlPreview = response.json().await.unwrap();
+        assert_eq!("https://example.com/", preview.target);
+        assert_eq!(None, preview.resolved_target);
+        assert_eq!(None, preview.title);
+    }
+
+    #[sqlx::test]
+    fn preview_of_unknown_url_gives_404(db: DbPool) {
+        let api = spawn_app_with_db(db).await;
+        let response = preview(&api, "nowhere", "").await;
+        assert_eq!(reqwest::StatusCode::NOT_FOUND, response.status());
+    }
+
+    #[sqlx::test]
+    fn preview_requires_login(db: DbPool) {
+        let api = spawn_app_with_db(db).await;
+        let response = reqwest::Client::new()
+            .get(format!("{api}/urls/nowhere/preview"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(reqwest::StatusCode::UNAUTHORIZED, response.status());
+    }
+
+    #[sqlx::test]
+    fn preview_of_private_target_is_refused(db: DbPool) {
+        let api = spawn_app_with_db(db).await;
+        let response = reqwest::Client::new()
+            .post(format!("{api}/urls"))
+            .basic_auth("user", Some("user"))
+            .json(&serde_json::json!({ "name": "local", "target": "http://127.0.0.1/admin" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(reqwest::StatusCode::CREATED, response.status());
+
+        let response = preview(&api, "local", "?resolve=true").await;
+        assert_eq!(reqwest::StatusCode::UNPROCESSABLE_ENTITY, response.status());
+
+        // Without resolving, nothing is fetched, so the target is shown as usual
+        let response = preview(&api, "local", "").await;
+        assert_eq!(reqwest::StatusCode::OK, response.status());
+    }
+
+    #[sqlx::test]
+    fn preview_resolves_redirects_and_title(db: DbPool) {
+        use axum::{response::Redirect, routing::get};
+
+        let target = Router::new()
+            .route("/moved", get(|| async { Redirect::permanent("/page") }))
+            .route(
+                "/page",
+                get(|| async {
+                    axum::response::Html("<html><head><title>The Page</title></head></html>")
+                }),
+            );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let host = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, target).await });
+
+        // The target runs locally, so it is only reachable when private targets are allowed
+        let mut config = crate::infra::config::load_config().unwrap();
+        config.urls.allow_private_targets = true;
+        let app = test_app_with_config(db, config);
+        let auth = base64::engine::general_purpose::STANDARD.encode("user:user");
+        let req: Request<Body> = Request::post("/api/urls")
+            .header("Authorization", format!("Basic {}", &auth))
+            .header("Content-Type", "application/json")
+            .body(
+                serde_json::json!({ "name": "moved", "target": format!("{host}/moved") })
+                    .to_string()
+                    .into(),
+            )
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+
+        let req: Request<Body> = Request::get("/api/urls/moved/preview?resolve=true")
+            .header("Authorization", format!("Basic {}", &auth))
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let preview: UrlPreview = serde_json::from_slice(&body).unwrap();
+        assert_eq!(format!("{host}/moved"), preview.target);
+        assert_eq!(Some(format!("{host}/page")), preview.resolved_target);
+        assert_eq!(Some("The Page".to_string()), preview.title);
+    }
+
+    #[sqlx::test]
+    fn shorten_url_with_invalid_header_target_gives_422(db: DbPool) {
+        let app = test_app(db);
+        let auth = base64::engine::general_purpose::STANDARD.encode("user:user");
+        let req: Request<Body> = Request::post("/api/urls")
+            .header("Authorization", format!("Basic {}", &auth))
+            .header("Content-Type", "application/json")
+            .body(r#"{"name": "example", "target": "https://example.com/a\nb"}"#.into())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+    }
+
+    #[sqlx::test]
+    fn url_targets_must_use_allowed_schemes(db: DbPool) {
+        let api = spawn_app_with_db(db).await;
+        let client = reqwest::Client::new();
+        let create = |name: &str, target: &str| {
+            client
+                .post(format!("{api}/urls"))
+                .basic_auth("user", Some("user"))
+                .json(&serde_json::json!({ "name": name, "target": target }))
+                .send()
+        };
+
+        let res = create("safe", "https://example.com/").await.unwrap();
+        assert_eq!(reqwest::StatusCode::CREATED, res.status());
+
+        let res = create("evil", "javascript:alert(1)").await.unwrap();
+        assert_eq!(reqwest::StatusCode::UNPROCESSABLE_ENTITY, res.status());
+        let error: ErrorBody = res.json().await.unwrap();
+        assert_eq!("target scheme `javascript` is not allowed", error.message());
+
+        // Updates are checked too
+        let res = client
+            .put(format!("{api}/urls/safe"))
+            .basic_auth("user", Some("user"))
+            .json(&serde_json::json!({ "target": "data:text/html,hi" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(reqwest::StatusCode::UNPROCESSABLE_ENTITY, res.status());
+        let error: ErrorBody = res.json().await.unwrap();
+        assert_eq!("target scheme `data` is not allowed", error.message());
+
+        let res = client
+            .post(format!("{api}/urls/bulk"))
+            .basic_auth("user", Some("user"))
+            .json(&serde_json::json!([{ "name": "evil", "target": "javascript:alert(1)" }]))
+            .send()
+            .await
+            .unwrap();
+        let results: Vec<ImportResult> = res.json().await.unwrap();
+        assert_eq!(
+            vec![ImportResult::Invalid {
+                name: "evil".to_string(),
+                message: "target scheme `javascript` is not allowed".to_string(),
+            }],
+            results
+        );
+    }
+
+    #[sqlx::test]
+    fn listing_urls_by_invalid_domain_gives_400(db: DbPool) {
+        let app = test_app(db);
+        let auth = base64::engine::general_purpose::STANDARD.encode("user:user");
+        let req: Request<Body> = Request::get("/api/urls?domain=exa%25mple.com")
+            .header("Authorization", format!("Basic {}", &auth))
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let error: ErrorBody = serde_json::from_slice(&body).unwrap();
+        assert_eq!("invalid domain: exa%mple.com", error.message());
+    }
+
+    async fn conflict_message(app: &Router, uri: &str, body: &'static str) -> String {
+        let auth = base64::engine::general_purpose::STANDARD.encode("user:user");
+        let mut status = StatusCode::CREATED;
+        let mut res = None;
+        for _ in 0..2 {
+            let req: Request<Body> = Request::post(uri)
+                .header("Authorization", format!("Basic {}", &auth))
+                .header("Content-Type", "application/json")
+                .body(body.into())
+                .unwrap();
+            let r = app.clone().oneshot(req).await.unwrap();
+            status = r.status();
+            res = Some(r);
+        }
+        assert_eq!(StatusCode::CONFLICT, status);
+        let body = axum::body::to_bytes(res.unwrap().into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: ErrorBody = serde_json::from_slice(&body).unwrap();
+        body.message().to_string()
+    }
+
+    #[sqlx::test]
+    fn concurrent_url_creates_with_same_name_conflict(db: DbPool) {
+        let api = spawn_app_with_db(db.clone()).await;
+        let client = reqwest::Client::new();
+        let create = || {
+            client
+                .post(format!("{api}/urls"))
+                .basic_auth("user", Some("user"))
+                .json(&serde_json::json!({ "name": "race", "target": "https://example.com" }))
+                .send()
+        };
+        let (first, second) = tokio::join!(create(), create());
+        let mut responses = [first.unwrap(), second.unwrap()];
+        responses.sort_by_key(|r| r.status());
+        let [created, conflict] = responses;
+
+        assert_eq!(reqwest::StatusCode::CREATED, created.status());
+        assert_eq!(reqwest::StatusCode::CONFLICT, conflict.status());
+        let body: ErrorBody = conflict.json().await.unwrap();
+        assert_eq!("short url name already taken", body.message());
+
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM short_urls WHERE name = 'race'")
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(1, count);
+    }
+
+    #[sqlx::test]
+    fn duplicates_give_distinct_conflict_messages(db: DbPool) {
+        let app = test_app(db);
+        let url_message = conflict_message(
+            &app,
+            "/api/urls",
+            r#"{"name": "example", "target": "https://example.com/"}"#,
+        )
+        .await;
+        let item_message = conflict_message(&app, "/api/items", r#"{"name": "example"}"#).await;
+        assert_eq!("short url name already taken", url_message);
+        assert_eq!("item name already taken", item_message);
+    }
+
+    async fn connect_ws(
+        api: &str,
+        credentials: Option<&str>,
+    ) -> Result<
+        tokio_tungstenite::WebSocketStream<
+            tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
+        >,
+        tokio_tungstenite::tungstenite::Error,
+    > {
+        use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+        let ws_url = format!("{}/ws", api.replacen("http", "ws", 1));
+        let mut req = ws_url.into_client_request().unwrap();
+        if let Some(credentials) = credentials {
+            let auth = base64::engine::general_purpose::STANDARD.encode(credentials);
+            req.headers_mut()
+                .insert("Authorization", format!("Basic {auth}").parse().unwrap());
+        }
+        tokio_tungstenite::connect_async(req)
+            .await
+            .map(|(socket, _)| socket)
+    }
+
+    #[sqlx::test]
+    fn websocket_receives_item_created_event(db: DbPool) {
+        use futures::SinkExt;
+        use tokio_tungstenite::tungstenite::Message;
+
+        let api = spawn_app_with_db(db).await;
+        let mut socket = connect_ws(&api, Some("user:user")).await.unwrap();
+
+        socket.send(Message::text("subscribe")).await.unwrap();
+        let reply = socket.next().await.unwrap().unwrap();
+        assert_eq!(Message::text("subscribed"), reply);
+
+        let item: Item = reqwest::Client::new()
+            .post(format!("{api}/items"))
+            .basic_auth("user", Some("user"))
+            .json(&NewItem {
+                name: "example".to_string(),
+                description: None,
+            })
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        let message = tokio::time::timeout(Duration::from_secs(5), socket.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        let event: ItemEvent = serde_json::from_str(message.to_text().unwrap()).unwrap();
+        assert_eq!(ItemEvent::ItemCreated(item), event);
+
+        socket.send(Message::text("ping")).await.unwrap();
+        let reply = socket.next().await.unwrap().unwrap();
+        assert_eq!(Message::text("pong"), reply);
+    }
+
+    #[sqlx::test]
+    fn webhook_secrets_are_not_stored_in_request_log(db: DbPool) {
+        let api = spawn_app_with_db(db.clone()).await;
+        let secret = "0123456789abcdef";
+        let response = reqwest::Client::new()
+            .post(format!("{api}/admin/webhooks"))
+            .basic_auth("admin", Some("admin"))
+            .json(&serde_json::json!({
+                "url": "https://example.com/hook",
+                "secret": secret,
+                "events": ["item_created"]
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(reqwest::StatusCode::CREATED, response.status());
+
+        // Requests are stored in the background
+        let mut row = None;
+        for _ in 0..50 {
+            row = sqlx::query_as::<_, (Option<String>, Option<String>)>(
+                "SELECT request_body, response_body FROM requests WHERE uri = '/api/admin/webhooks'",
+            )
+            .fetch_optional(&db)
+            .await
+            .unwrap();
+            if row.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(Some((None, None)), row);
+    }
+
+    #[sqlx::test]
+    fn creating_item_posts_signed_event_to_webhook(db: DbPool) {
+        use hmac::{Hmac, Mac};
+
+        // A webhook receiver that forwards what it gets
+        let (sender, mut received) = tokio::sync::mpsc::unbounded_channel();
+        let receiver = Router::new().route(
+            "/hook",
+            axum::routing::post(move |headers: http::HeaderMap, body: bytes::Bytes| {
+                let sender = sender.clone();
+                async move { sender.send((headers, body)).unwrap() }
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let hook = format!("http://{}/hook", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, receiver).await });
+
+        let api = spawn_app_with_db(db).await;
+        let client = reqwest::Client::new();
+        let secret = "0123456789abcdef";
+        let response = client
+            .post(format!("{api}/admin/webhooks"))
+            .basic_auth("admin", Some("admin"))
+            .json(&serde_json::json!({ "url": hook, "secret": secret, "events": ["item_created"] }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(reqwest::StatusCode::CREATED, response.status());
+        let body = response.text().await.unwrap();
+        assert!(!body.contains(secret), "secret was serialized: {body}");
+        let webhook: Webhook = serde_json::from_str(&body).unwrap();
+        assert_eq!(vec!["item_created".to_string()], webhook.events);
+
+        let item: Item = client
+            .post(format!("{api}/items"))
+            .basic_auth("user", Some("user"))
+            .json(&NewItem {
+                name: "hooked".to_string(),
+                description: None,
+            })
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        let (headers, body) = tokio::time::timeout(Duration::from_secs(5), received.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        let signature = headers[SIGNATURE_HEADER].to_str().unwrap();
+        let signature = hex::decode(signature.strip_prefix("sha256=").unwrap()).unwrap();
+        let mut mac = Hmac::<sha2::Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(&body);
+        mac.verify_slice(&signature).expect("invalid signature");
+        let event: ItemEvent = serde_json::from_slice(&body).unwrap();
+        assert_eq!(ItemEvent::ItemCreated(item), event);
+    }
+
+    #[sqlx::test]
+    fn user_cannot_register_webhook(db: DbPool) {
+        let api = spawn_app_with_db(db).await;
+        let response = reqwest::Client::new()
+            .post(format!("{api}/admin/webhooks"))
+            .basic_auth("user", Some("user"))
+            .json(&serde_json::json!({
+                "url": "https://example.com/hook",
+                "secret": "0123456789abcdef",
+                "events": ["item_created"],
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(reqwest::StatusCode::FORBIDDEN, response.status());
+    }
+
+    #[sqlx::test]
+    fn websocket_without_credentials_is_rejected(db: DbPool) {
+        let api = spawn_app_with_db(db).await;
+        let error = connect_ws(&api, None).await.unwrap_err();
+        match error {
+            tokio_tungstenite::tungstenite::Error::Http(res) => {
+                assert_eq!(StatusCode::UNAUTHORIZED, res.status())
+            }
+            e => panic!("unexpected error: {e}"),
+        }
+    }
+
+    #[sqlx::test]
+    fn create_item_responds_with_created(db: DbPool) {
+        let api = spawn_app_with_db(db).await;
+        let client = reqwest::Client::new();
+        let res: reqwest::Response = client
+            .post(format!("{api}/items"))
+            .basic_auth("user", Some("user"))
+            .json(&NewItem {
+                name: "example".to_string(),
+                description: None,
+            })
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(reqwest::StatusCode::CREATED, res.status());
+        let item = res.json::<Item>().await.unwrap();
+        assert!(item.id > 0);
+        assert_eq!("example", item.name);
+        assert_eq!(None, item.description);
+    }
+
+    #[sqlx::test]
+    fn get_item_responds_with_ok(db: DbPool) {
+        let api = spawn_app_with_db(db).await;
+
+        // Create item
+        let client = reqwest::Client::new();
+        let res: reqwest::Response = client
+            .post(format!("{api}/items"))
+            .basic_auth("user", Some("user"))
+            .json(&NewItem {
+                name: "example".to_string(),
+                description: None,
+            })
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(reqwest::StatusCode::CREATED, res.status());
+
+        let created_item = res.json::<Item>().await.unwrap();
+
+        // Get item
+        let res = client
+            .get(format!("{api}/items/{}", created_item.id))
+            .basic_auth("user", Some("user"))
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(reqwest::StatusCode::OK, res.status());
+    }
+
+    #[sqlx::test]
+    fn get_item_is_timestamped_only_when_requested(db: DbPool) {
+        let api = spawn_app_with_db(db).await;
+        let client = reqwest::Client::new();
+        let created: Item = client
+            .post(format!("{api}/items"))
+            .basic_auth("user", Some("user"))
+            .json(&NewItem {
+                name: "example".to_string(),
+                description: None,
+            })
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        // Plain by default
+        let plain: Item = get(&format!("{api}/items/{}", created.id)).await;
+        assert_eq!(created, plain);
+
+        // Wrapped when requested
+        let wrapped: Timestamped<Item> =
+            get(&format!("{api}/items/{}?timestamped=true", created.id)).await;
+        assert_eq!(created, wrapped.data);
+    }
+
+    #[sqlx::test]
+    fn unchanged_item_is_not_modified(db: DbPool) {
+        let app = test_app(db);
+        let item = create_example_item(&app).await;
+        let uri = format!("/api/items/{}", item.id);
+
+        let req = Request::get(&uri).body(Body::empty()).unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let last_modified = res.headers()["last-modified"].clone();
+
+        let req = Request::get(&uri)
+            .header("If-Modified-Since", &last_modified)
+            .body(Body::empty())
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_MODIFIED, res.status());
+        assert_eq!(last_modified, res.headers()["last-modified"]);
+
+        let req = Request::get(&uri)
+            .header("If-Modified-Since", "Sat, 01 Jan 2000 00:00:00 GMT")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+    }
+
+    #[sqlx::test]
+    fn conditional_update_of_newer_item_gives_412(db: DbPool) {
+        let app = test_app(db);
+        let item = create_example_item(&app).await;
+        let auth = base64::engine::general_purpose::STANDARD.encode("user:user");
+        let update = |since: &str| {
+            Request::put(format!("/api/items/{}", item.id))
+                .header("Authorization", format!("Basic {auth}"))
+                .header("Content-Type", "application/json")
+                .header("If-Unmodified-Since", since)
+                .body(Body::from(r#"{"name": "renamed"}"#))
+                .unwrap()
+        };
+
+        let res = app
+            .clone()
+            .oneshot(update("Sat, 01 Jan 2000 00:00:00 GMT"))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::PRECONDITION_FAILED, res.status());
+
+        let req = Request::get(format!("/api/items/{}", item.id))
+            .body(Body::empty())
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        let last_modified = res.headers()["last-modified"].to_str().unwrap().to_string();
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let unchanged: Item = serde_json::from_slice(&body).unwrap();
+        assert_eq!("example", unchanged.name);
+
+        let res = app.oneshot(update(&last_modified)).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert!(res.headers().contains_key("last-modified"));
+    }
+
+    #[sqlx::test]
+    fn get_nonexisting_item_responds_with_not_found(db: DbPool) {
+        let api = spawn_app_with_db(db).await;
+        let client = reqwest::Client::new();
+        let res = client
+            .get(format!("{api}/items/999"))
+            .basic_auth("user", Some("user"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(reqwest::StatusCode::NOT_FOUND, res.status());
+    }
+
+    #[sqlx::test]
+    fn put_item_responds_with_ok(db: DbPool) {
+        let api = spawn_app_with_db(db).await;
+
+        // Create item
+        let client = reqwest::Client::new();
+        let res: reqwest::Response = client
+            .post(format!("{api}/items"))
+            .basic_auth("user", Some("user"))
+            .json(&NewItem {
+                name: "example".to_string(),
+                description: None,
+            })
             .send()
             .await
             .unwrap();
@@ -408,23 +2069,346 @@ mod tests {
 
         let created_item = res.json::<Item>().await.unwrap();
 
-        // Get item
+        // Update item
+        let res = client
+            .put(format!("{api}/items/{}", created_item.id))
+            .basic_auth("user", Some("user"))
+            .json(&NewItem {
+                name: "modified".to_string(),
+                description: None,
+            })
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(reqwest::StatusCode::OK, res.status());
+
+        let updated_item = res.json::<Item>().await.unwrap();
+        assert_eq!(created_item.id, updated_item.id);
+        assert_eq!("modified", updated_item.name);
+    }
+
+    #[sqlx::test]
+    fn admin_update_records_admin_as_updated_by(db: DbPool) {
+        let api = spawn_app_with_db(db).await;
+
+        // Create item as user
+        let client = reqwest::Client::new();
+        let res: reqwest::Response = client
+            .post(format!("{api}/items"))
+            .basic_auth("user", Some("user"))
+            .json(&NewItem {
+                name: "example".to_string(),
+                description: None,
+            })
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(reqwest::StatusCode::CREATED, res.status());
+
+        let created_item = res.json::<Item>().await.unwrap();
+        assert_eq!(Some(1), created_item.created_by);
+        assert_eq!(None, created_item.updated_by);
+
+        // Update item as admin
+        let res = client
+            .put(format!("{api}/items/{}", created_item.id))
+            .basic_auth("admin", Some("admin"))
+            .json(&NewItem {
+                name: "modified".to_string(),
+                description: None,
+            })
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(reqwest::StatusCode::OK, res.status());
+
+        let updated_item = res.json::<Item>().await.unwrap();
+        assert_eq!(Some(1), updated_item.created_by);
+        assert_eq!(Some(2), updated_item.updated_by);
+    }
+
+    #[sqlx::test]
+    fn validate_only_does_not_create_item(db: DbPool) {
+        let api = spawn_app_with_db(db).await;
+        let client = reqwest::Client::new();
+        let validate = |body: serde_json::Value| {
+            client
+                .post(format!("{api}/items?validate_only=true"))
+                .basic_auth("user", Some("user"))
+                .json(&body)
+                .send()
+        };
+
+        let res = validate(serde_json::json!({ "name": "example" }))
+            .await
+            .unwrap();
+        assert_eq!(reqwest::StatusCode::NO_CONTENT, res.status());
+        let items: Vec<Item> = get(&format!("{api}/items")).await;
+        assert!(items.is_empty());
+
+        let res = validate(serde_json::json!({ "name": "" })).await.unwrap();
+        assert_eq!(reqwest::StatusCode::UNPROCESSABLE_ENTITY, res.status());
+        let error: ErrorBody = res.json().await.unwrap();
+        assert!(error.message().contains("name (length)"));
+    }
+
+    #[sqlx::test]
+    fn created_item_is_stored_trimmed(db: DbPool) {
+        let api = spawn_app_with_db(db).await;
+        let client = reqwest::Client::new();
+        let created: Item = client
+            .post(format!("{api}/items"))
+            .basic_auth("user", Some("user"))
+            .json(&serde_json::json!({ "name": "  padded  ", "description": " text " }))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let stored: Item = get(&format!("{api}/items/{}", created.id)).await;
+        assert_eq!("padded", stored.name);
+        assert_eq!(Some("text"), stored.description.as_deref());
+    }
+
+    #[sqlx::test]
+    fn update_item_only_changes_targeted_item(db: DbPool) {
+        let api = spawn_app_with_db(db).await;
+        let client = reqwest::Client::new();
+        let mut items = Vec::new();
+        for name in ["first", "second"] {
+            let item: Item = client
+                .post(format!("{api}/items"))
+                .basic_auth("user", Some("user"))
+                .json(&NewItem {
+                    name: name.to_string(),
+                    description: None,
+                })
+                .send()
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+            items.push(item);
+        }
+
+        // Update the first item
+        let res = client
+            .put(format!("{api}/items/{}", items[0].id))
+            .basic_auth("user", Some("user"))
+            .json(&NewItem {
+                name: "modified".to_string(),
+                description: None,
+            })
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(reqwest::StatusCode::OK, res.status());
+        assert_eq!("modified", res.json::<Item>().await.unwrap().name);
+
+        // The second item is unchanged
+        let second: Item = get(&format!("{api}/items/{}", items[1].id)).await;
+        assert_eq!(items[1], second);
+
+        // Updating a missing item gives 404
+        let res = client
+            .put(format!("{api}/items/1000"))
+            .basic_auth("user", Some("user"))
+            .json(&NewItem {
+                name: "missing".to_string(),
+                description: None,
+            })
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(reqwest::StatusCode::NOT_FOUND, res.status());
+    }
+
+    #[sqlx::test]
+    fn put_nonexisting_item_responds_with_not_found(db: DbPool) {
+        let api = spawn_app_with_db(db).await;
+        let client = reqwest::Client::new();
+        let res = client
+            .put(format!("{api}/items/999"))
+            .basic_auth("user", Some("user"))
+            .json(&NewItem {
+                name: "modified".to_string(),
+                description: None,
+            })
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(reqwest::StatusCode::NOT_FOUND, res.status());
+    }
+
+    #[sqlx::test]
+    fn delete_item_responds_with_no_content(db: DbPool) {
+        let api = spawn_app_with_db(db).await;
+
+        // Create item
+        let client = reqwest::Client::new();
+        let res: reqwest::Response = client
+            .post(format!("{api}/items"))
+            .basic_auth("user", Some("user"))
+            .json(&NewItem {
+                name: "example".to_string(),
+                description: None,
+            })
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(reqwest::StatusCode::CREATED, res.status());
+
+        let created_item = res.json::<Item>().await.unwrap();
+
+        // Delete item
+        let res = client
+            .delete(format!("{api}/items/{}", created_item.id))
+            .basic_auth("user", Some("user"))
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(reqwest::StatusCode::NO_CONTENT, res.status());
+        assert!(res.bytes().await.unwrap().is_empty());
+    }
+
+    #[sqlx::test]
+    fn bulk_import_reports_each_url(db: DbPool) {
+        let api = spawn_app_with_db(db.clone()).await;
+        let client = reqwest::Client::new();
+        let res = client
+            .post(format!("{api}/urls"))
+            .basic_auth("user", Some("user"))
+            .json(&serde_json::json!({ "name": "taken", "target": "https://example.com" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(reqwest::StatusCode::CREATED, res.status());
+
+        let batch = serde_json::json!([
+            { "name": "first", "target": "https://example.com/1" },
+            { "name": "taken", "target": "https://example.com/2" },
+            { "name": "invalid", "target": "not a url" },
+            { "name": "second", "target": "https://example.com/3" },
+            { "name": "first", "target": "https://example.com/4" },
+        ]);
+        let res = client
+            .post(format!("{api}/urls/bulk"))
+            .basic_auth("user", Some("user"))
+            .json(&batch)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(reqwest::StatusCode::OK, res.status());
+        let results: Vec<ImportResult> = res.json().await.unwrap();
+
+        assert_eq!(5, results.len());
+        assert!(matches!(&results[0], ImportResult::Created { url } if url.name == "first"));
+        assert_eq!(
+            ImportResult::Conflict {
+                name: "taken".to_string()
+            },
+            results[1]
+        );
+        assert!(matches!(&results[2], ImportResult::Invalid { name, .. } if name == "invalid"));
+        assert!(matches!(&results[3], ImportResult::Created { url } if url.name == "second"));
+        assert_eq!(
+            ImportResult::Conflict {
+                name: "first".to_string()
+            },
+            results[4]
+        );
+
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM short_urls")
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(3, count);
+    }
+
+    #[sqlx::test]
+    fn only_owner_can_update_url_target(db: DbPool) {
+        let api = spawn_app_with_db(db).await;
+        let client = reqwest::Client::new();
         let res = client
-            .get(&format!("{api}/items/{}", created_item.id))
+            .post(format!("{api}/urls"))
             .basic_auth("user", Some("user"))
+            .json(&serde_json::json!({ "name": "example", "target": "https://example.com" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(reqwest::StatusCode::CREATED, res.status());
+
+        // Someone else cannot update it
+        let update = serde_json::json!({ "target": "https://example.org" });
+        let res = client
+            .put(format!("{api}/urls/example"))
+            .basic_auth("admin", Some("admin"))
+            .json(&update)
             .send()
             .await
             .unwrap();
+        assert_eq!(reqwest::StatusCode::NOT_FOUND, res.status());
 
+        // The owner can
+        let res = client
+            .put(format!("{api}/urls/example"))
+            .basic_auth("user", Some("user"))
+            .json(&update)
+            .send()
+            .await
+            .unwrap();
         assert_eq!(reqwest::StatusCode::OK, res.status());
+        let url: ShortUrl = res.json().await.unwrap();
+        assert_eq!("https://example.org", url.target);
+        assert_eq!(Some(url.created_by), url.updated_by);
+
+        // Invalid targets are rejected
+        let res = client
+            .put(format!("{api}/urls/example"))
+            .basic_auth("user", Some("user"))
+            .json(&serde_json::json!({ "target": "not a url" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(reqwest::StatusCode::UNPROCESSABLE_ENTITY, res.status());
+    }
+
+    #[sqlx::test]
+    fn delete_url_responds_with_no_content(db: DbPool) {
+        let api = spawn_app_with_db(db).await;
+        let client = reqwest::Client::new();
+        let res = client
+            .post(format!("{api}/urls"))
+            .basic_auth("user", Some("user"))
+            .json(&serde_json::json!({ "name": "example", "target": "https://example.com" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(reqwest::StatusCode::CREATED, res.status());
+
+        let res = client
+            .delete(format!("{api}/urls/example"))
+            .basic_auth("user", Some("user"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(reqwest::StatusCode::NO_CONTENT, res.status());
+        assert!(res.bytes().await.unwrap().is_empty());
     }
 
     #[sqlx::test]
-    fn get_nonexisting_item_responds_with_not_found(db: DbPool) {
+    fn delete_nonexisting_item_responds_with_not_found(db: DbPool) {
         let api = spawn_app_with_db(db).await;
         let client = reqwest::Client::new();
         let res = client
-            .get(&format!("{api}/items/999"))
+            .delete(format!("{api}/items/999"))
             .basic_auth("user", Some("user"))
             .send()
             .await
@@ -433,112 +2417,172 @@ mod tests {
     }
 
     #[sqlx::test]
-    fn put_item_responds_with_ok(db: DbPool) {
+    fn get_items_responds_with_ok(db: DbPool) {
+        let app = test_app(db);
+        let auth = base64::engine::general_purpose::STANDARD.encode("user:user");
+        let req = Request::get("/api/items")
+            .header("Authorization", format!("Basic {}", &auth))
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+    }
+
+    #[sqlx::test]
+    fn item_count_counts_all_items(db: DbPool) {
+        sqlx::query("INSERT INTO items (name) SELECT 'item' || n FROM generate_series(1, 7) n")
+            .execute(&db)
+            .await
+            .unwrap();
         let api = spawn_app_with_db(db).await;
+        let count: ItemCount = get(&format!("{api}/items/count")).await;
+        assert_eq!(ItemCount { count: 7 }, count);
+    }
 
-        // Create item
-        let client = reqwest::Client::new();
-        let res: reqwest::Response = client
-            .post(&format!("{api}/items"))
-            .basic_auth("user", Some("user"))
-            .json(&NewItem {
-                name: "example".to_string(),
-                description: None,
-            })
-            .send()
+    #[sqlx::test]
+    fn items_can_be_listed_as_a_page(db: DbPool) {
+        sqlx::query("INSERT INTO items (name) SELECT 'item' || n FROM generate_series(1, 5) n")
+            .execute(&db)
             .await
             .unwrap();
+        let api = spawn_app_with_db(db).await;
 
-        assert_eq!(reqwest::StatusCode::CREATED, res.status());
+        let page: Page<Item> = get(&format!("{api}/items?pageSize=2&page=1&envelope=true")).await;
+        assert_eq!(2, page.items.len());
+        assert_eq!(5, page.total);
+        assert_eq!(2, page.offset);
+        assert!(page.has_more);
 
-        let created_item = res.json::<Item>().await.unwrap();
+        // Plain list without the flag
+        let items: Vec<Item> = get(&format!("{api}/items?pageSize=2")).await;
+        assert_eq!(2, items.len());
+    }
 
-        // Update item
-        let res = client
-            .put(&format!("{api}/items/{}", created_item.id))
-            .basic_auth("user", Some("user"))
-            .json(&NewItem {
-                name: "modified".to_string(),
-                description: None,
-            })
-            .send()
+    #[sqlx::test]
+    fn middle_page_of_items_links_to_next_and_prev(db: DbPool) {
+        sqlx::query("INSERT INTO items (name) SELECT 'item' || n FROM generate_series(1, 5) n")
+            .execute(&db)
             .await
             .unwrap();
+        let api = spawn_app_with_db(db).await;
 
-        assert_eq!(reqwest::StatusCode::OK, res.status());
-
-        let updated_item = res.json::<Item>().await.unwrap();
-        assert_eq!(created_item.id, updated_item.id);
-        assert_eq!("modified", updated_item.name);
+        let res = reqwest::get(format!("{api}/items?pageSize=2&page=1"))
+            .await
+            .unwrap();
+        let link = res.headers()[http::header::LINK].to_str().unwrap();
+        assert!(
+            link.contains("</api/items?page=0&pageSize=2>; rel=\"prev\""),
+            "{link}"
+        );
+        assert!(
+            link.contains("</api/items?page=2&pageSize=2>; rel=\"next\""),
+            "{link}"
+        );
     }
 
     #[sqlx::test]
-    fn put_nonexisting_item_responds_with_not_found(db: DbPool) {
+    fn middle_page_of_urls_links_to_next_and_prev(db: DbPool) {
+        sqlx::query(
+            "INSERT INTO short_urls (name, target, created_by)
+            SELECT 'url' || n, 'https://example.com', 1 FROM generate_series(1, 5) n",
+        )
+        .execute(&db)
+        .await
+        .unwrap();
         let api = spawn_app_with_db(db).await;
-        let client = reqwest::Client::new();
+        let client = reqwest::ClientBuilder::default().build().unwrap();
+
         let res = client
-            .put(&format!("{api}/items/999"))
+            .get(format!("{api}/urls?pageSize=2&page=1&envelope=true"))
             .basic_auth("user", Some("user"))
-            .json(&NewItem {
-                name: "modified".to_string(),
-                description: None,
-            })
             .send()
             .await
             .unwrap();
-        assert_eq!(reqwest::StatusCode::NOT_FOUND, res.status());
+        assert_eq!(reqwest::StatusCode::OK, res.status());
+        let link = res.headers()[http::header::LINK].to_str().unwrap();
+        assert!(
+            link.contains("</api/urls?envelope=true&page=0&pageSize=2>; rel=\"prev\""),
+            "{link}"
+        );
+        assert!(
+            link.contains("</api/urls?envelope=true&page=2&pageSize=2>; rel=\"next\""),
+            "{link}"
+        );
     }
 
     #[sqlx::test]
-    fn delete_item_responds_with_no_content(db: DbPool) {
+    fn items_can_be_searched_with_typos(db: DbPool) {
+        sqlx::query(
+            "INSERT INTO items (name) VALUES ('Blueberry muffin'), ('Chocolate cake'), ('Lemon tart')",
+        )
+        .execute(&db)
+        .await
+        .unwrap();
         let api = spawn_app_with_db(db).await;
 
-        // Create item
-        let client = reqwest::Client::new();
-        let res: reqwest::Response = client
-            .post(&format!("{api}/items"))
-            .basic_auth("user", Some("user"))
-            .json(&NewItem {
-                name: "example".to_string(),
-                description: None,
-            })
-            .send()
+        let items: Vec<Item> = get(&format!("{api}/items/search?fuzzy=choclate%20cak")).await;
+        let names: Vec<_> = items.iter().map(|item| item.name.as_str()).collect();
+        assert_eq!(vec!["Chocolate cake"], names);
+
+        let items: Vec<Item> = get(&format!("{api}/items/search?fuzzy=xylophone")).await;
+        assert!(items.is_empty(), "{items:?}");
+
+        let res = reqwest::get(format!("{api}/items/search?fuzzy=%20"))
             .await
             .unwrap();
+        assert_eq!(reqwest::StatusCode::BAD_REQUEST, res.status());
+    }
 
-        assert_eq!(reqwest::StatusCode::CREATED, res.status());
+    #[sqlx::test]
+    fn deprecated_route_is_announced_in_headers_and_spec(db: DbPool) {
+        let mut config = crate::infra::config::load_config().unwrap();
+        config.server.deprecated_routes = vec![DeprecatedRoute {
+            path: "/api/items2".to_string(),
+            sunset: Some("2027-01-01T00:00:00Z".parse().unwrap()),
+        }];
+        let app = test_app_with_config(db, config);
 
-        let created_item = res.json::<Item>().await.unwrap();
+        let req = Request::get("/api/items2").body(Body::empty()).unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!("true", res.headers()["deprecation"]);
+        assert_eq!("Fri, 01 Jan 2027 00:00:00 GMT", res.headers()["sunset"]);
 
-        // Delete item
-        let res = client
-            .delete(&format!("{api}/items/{}", created_item.id))
-            .basic_auth("user", Some("user"))
-            .send()
+        let req = Request::get("/api/items").body(Body::empty()).unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert!(res.headers().get("deprecation").is_none());
+
+        let req = Request::get("/api/openapi.json")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
             .await
             .unwrap();
-
-        assert_eq!(reqwest::StatusCode::NO_CONTENT, res.status());
+        let openapi: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            serde_json::json!(true),
+            openapi["paths"]["/api/items2"]["get"]["deprecated"]
+        );
+        assert!(openapi["paths"]["/api/items"]["get"]["deprecated"].is_null());
     }
 
     #[sqlx::test]
-    fn delete_nonexisting_item_responds_with_not_found(db: DbPool) {
-        let api = spawn_app_with_db(db).await;
-        let client = reqwest::Client::new();
-        let res = client
-            .delete(&format!("{api}/items/999"))
-            .basic_auth("user", Some("user"))
-            .send()
+    fn oversized_page_is_clamped(db: DbPool) {
+        sqlx::query("INSERT INTO items (name) SELECT 'item' || n FROM generate_series(1, 150) n")
+            .execute(&db)
             .await
             .unwrap();
-        assert_eq!(reqwest::StatusCode::NOT_FOUND, res.status());
+        let api = spawn_app_with_db(db).await;
+        let items: Vec<Item> = get(&format!("{api}/items?pageSize=1000000")).await;
+        assert_eq!(100, items.len());
     }
 
     #[sqlx::test]
-    fn get_items_responds_with_ok(db: DbPool) {
+    fn get_items2_responds_with_ok(db: DbPool) {
         let app = test_app(db);
         let auth = base64::engine::general_purpose::STANDARD.encode("user:user");
-        let req = Request::get("/api/items")
+        let req = Request::get("/api/items2")
             .header("Authorization", format!("Basic {}", &auth))
             .body(Body::empty())
             .unwrap();
@@ -546,14 +2590,146 @@ mod tests {
         assert_eq!(StatusCode::OK, res.status());
     }
 
-    #[sqlx::test]
-    fn get_items2_responds_with_ok(db: DbPool) {
-        let app = test_app(db);
+    fn multipart_request(uri: &str, content_type: &str, data: &[u8]) -> Request<Body> {
+        multipart_request_as("user:user", uri, content_type, data)
+    }
+
+    fn multipart_request_as(
+        credentials: &str,
+        uri: &str,
+        content_type: &str,
+        data: &[u8],
+    ) -> Request<Body> {
+        let auth = base64::engine::general_purpose::STANDARD.encode(credentials);
+        let boundary = "XBOUNDARYX";
+        let mut body = format!(
+            "--{boundary}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"file\"\r\nContent-Type: {content_type}\r\n\r\n"
+        )
+        .into_bytes();
+        body.extend_from_slice(data);
+        body.extend_from_slice(format!("\r\n--{boundary}--\r\n").as_bytes());
+        Request::post(uri)
+            .header("Authorization", format!("Basic {}", &auth))
+            .header(
+                "Content-Type",
+                format!("multipart/form-data; boundary={boundary}"),
+            )
+            .body(body.into())
+            .unwrap()
+    }
+
+    async fn create_example_item(app: &Router) -> Item {
         let auth = base64::engine::general_purpose::STANDARD.encode("user:user");
-        let req = Request::get("/api/items2")
+        let req: Request<Body> = Request::post("/api/items")
             .header("Authorization", format!("Basic {}", &auth))
-            .body(Body::empty())
+            .header("Content-Type", "application/json")
+            .body(r#"{"name": "example"}"#.into())
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[sqlx::test]
+    fn upload_then_download_attachment(db: DbPool) {
+        let app = test_app(db);
+        let item = create_example_item(&app).await;
+        let uri = format!("/api/items/{}/attachment", item.id);
+
+        // Upload attachment
+        let req = multipart_request(&uri, "image/png", b"not really a png");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+
+        // Download attachment
+        let req = Request::get(&uri).body(Body::empty()).unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!("image/png", res.headers()["content-type"]);
+        assert_eq!("attachment", res.headers()["content-disposition"]);
+        assert_eq!("nosniff", res.headers()["x-content-type-options"]);
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
             .unwrap();
+        assert_eq!(b"not really a png".as_slice(), &body[..]);
+    }
+
+    #[sqlx::test]
+    fn html_attachment_is_not_rendered_inline(db: DbPool) {
+        let app = test_app(db);
+        let item = create_example_item(&app).await;
+        let uri = format!("/api/items/{}/attachment", item.id);
+
+        let script = b"<script>alert(document.cookie)</script>";
+        let req = multipart_request(&uri, "text/html", script);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+
+        let req = Request::get(&uri).body(Body::empty()).unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!("attachment", res.headers()["content-disposition"]);
+        assert_eq!("nosniff", res.headers()["x-content-type-options"]);
+    }
+
+    #[sqlx::test]
+    fn only_creator_or_admin_may_upload_attachment(db: DbPool) {
+        insert_user(&db, "other", "other", "user").await;
+        let app = test_app(db);
+        let item = create_example_item(&app).await;
+        let uri = format!("/api/items/{}/attachment", item.id);
+
+        let req = multipart_request_as("other:other", &uri, "text/plain", b"mine now");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::FORBIDDEN, res.status());
+
+        // Nothing was stored
+        let req = Request::get(&uri).body(Body::empty()).unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+
+        let req = multipart_request_as("admin:admin", &uri, "text/plain", b"moderated");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+    }
+
+    #[sqlx::test]
+    fn oversized_attachment_responds_with_payload_too_large(db: DbPool) {
+        let app = test_app(db);
+        let item = create_example_item(&app).await;
+        let uri = format!("/api/items/{}/attachment", item.id);
+        let max_size = crate::infra::config::load_config()
+            .unwrap()
+            .server
+            .max_attachment_size;
+
+        let req = multipart_request(&uri, "image/png", &vec![0; max_size + 1]);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::PAYLOAD_TOO_LARGE, res.status());
+
+        // Nothing was stored
+        let req = Request::get(&uri).body(Body::empty()).unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+    }
+
+    #[sqlx::test]
+    fn disabled_feature_routes_are_not_mounted(db: DbPool) {
+        let store = PostgresStore::new(db.clone());
+        let mut config = crate::infra::config::load_config().unwrap();
+        config.features.urls = false;
+        let state = AppState::new(db, config.clone());
+        let app = app(state, config, store);
+
+        let req = Request::get("/api/urls").body(Body::empty()).unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+
+        // Other routes keep working
+        let req = Request::get("/api/hello").body(Body::empty()).unwrap();
         let res = app.oneshot(req).await.unwrap();
         assert_eq!(StatusCode::OK, res.status());
     }
@@ -575,7 +2751,7 @@ mod tests {
             .build()
             .unwrap();
         let response = client
-            .post(&format!("{url}/login"))
+            .post(format!("{url}/login"))
             .form(&LoginParams {
                 username: "user".to_string(),
                 password: "user".to_string(),
@@ -586,6 +2762,234 @@ mod tests {
         assert_eq!(reqwest::StatusCode::SEE_OTHER, response.status());
     }
 
+    #[sqlx::test]
+    fn version_matches_info(db: DbPool) {
+        let url = spawn_app_with_db(db).await;
+        let version: crate::api::info::info_api::Version = get(&format!("{url}/version")).await;
+        let info: serde_json::Value = get(&format!("{url}/info")).await;
+        assert_eq!(env!("CARGO_PKG_VERSION"), version.version);
+        assert_eq!(info["version"], version.version.as_str());
+        assert_eq!(crate::infra::version::GIT_SHA, version.git_sha);
+        assert!(!version.git_sha.is_empty());
+    }
+
+    #[sqlx::test]
+    fn duplicate_request_ids_are_collapsed(db: DbPool) {
+        let logs = crate::infra::logging::CapturedLogs::default();
+        let _guard = logs.capture();
+
+        let app = test_app(db);
+        let request_ids = |ids: &[&str]| {
+            let mut req = Request::get("/api/hello");
+            for id in ids {
+                req = req.header("x-request-id", *id);
+            }
+            req.body(Body::empty()).unwrap()
+        };
+
+        let res = app
+            .clone()
+            .oneshot(request_ids(&["first", "second"]))
+            .await
+            .unwrap();
+        let ids: Vec<_> = res.headers().get_all("x-request-id").iter().collect();
+        assert_eq!(vec!["first"], ids);
+        let output = logs.output();
+        assert!(output.contains("request_id=\"first\""), "{output}");
+        assert!(!output.contains("request_id=\"second\""), "{output}");
+
+        // Invalid ids are skipped, and replaced if none are valid
+        let res = app
+            .clone()
+            .oneshot(request_ids(&[" ", "second"]))
+            .await
+            .unwrap();
+        assert_eq!("second", res.headers()["x-request-id"]);
+        let long = "a".repeat(200);
+        let res = app.oneshot(request_ids(&["", &long])).await.unwrap();
+        let ids: Vec<_> = res.headers().get_all("x-request-id").iter().collect();
+        assert_eq!(1, ids.len());
+        assert_eq!(36, ids[0].len(), "expected a generated uuid");
+    }
+
+    #[tokio::test]
+    async fn failed_startup_is_reported() {
+        // Nothing listens on the url, so only the task can end the wait
+        let handle = tokio::spawn(async { Err(eyre!("no config")) });
+        let error = wait_until_started("http://127.0.0.1:9/api".to_string(), handle)
+            .await
+            .unwrap_err();
+        assert_eq!("app failed to start: no config", format!("{error:#}"));
+    }
+
+    #[sqlx::test]
+    fn spawned_app_is_running(db: DbPool) {
+        let app = try_spawn_app_with_db(db).await.unwrap();
+        assert!(!app.handle.is_finished());
+        let greeting: Greeting = get(&format!("{}/hello", app.url)).await;
+        assert_eq!("Hello, World!", greeting.greeting());
+    }
+
+    #[sqlx::test]
+    fn basic_auth_works_while_session_store_is_down(db: DbPool) {
+        // Loading a session fails without the session table
+        sqlx::query("DROP SCHEMA IF EXISTS tower_sessions CASCADE")
+            .execute(&db)
+            .await
+            .unwrap();
+        let app = test_app(db);
+        let cookie = format!("id={}", tower_sessions::session::Id::default());
+        let auth = base64::engine::general_purpose::STANDARD.encode("user:user");
+
+        let req = Request::get("/")
+            .header("Cookie", &cookie)
+            .header("Authorization", format!("Basic {auth}"))
+            .body(Body::empty())
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+
+        // Without other credentials the outage is reported, not treated as logged out
+        let req = Request::get("/")
+            .header("Cookie", &cookie)
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, res.status());
+    }
+
+    #[sqlx::test]
+    fn non_json_body_gives_415(db: DbPool) {
+        let app = test_app(db);
+        let auth = base64::engine::general_purpose::STANDARD.encode("user:user");
+        let create_item = |content_type: Option<&str>| {
+            let mut req =
+                Request::post("/api/items").header("Authorization", format!("Basic {auth}"));
+            if let Some(content_type) = content_type {
+                req = req.header("Content-Type", content_type);
+            }
+            req.body(Body::from(r#"{"name": "typed"}"#)).unwrap()
+        };
+
+        for content_type in [
+            Some("text/plain"),
+            Some("application/x-www-form-urlencoded"),
+            None,
+        ] {
+            let res = app
+                .clone()
+                .oneshot(create_item(content_type))
+                .await
+                .unwrap();
+            assert_eq!(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                res.status(),
+                "{content_type:?}"
+            );
+            let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let error: ErrorBody = serde_json::from_slice(&body).unwrap();
+            assert_eq!("unsupported media type", error.message());
+        }
+
+        let res = app
+            .oneshot(create_item(Some("application/json; charset=utf-8")))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+    }
+
+    #[sqlx::test]
+    fn deeply_nested_json_gives_400(db: DbPool) {
+        let app = test_app(db);
+        let auth = base64::engine::general_purpose::STANDARD.encode("user:user");
+        let nested = format!(r#"{{"name": "deep", "extra": {}}}"#, "[".repeat(100_000));
+        let req = Request::post("/api/items")
+            .header("Authorization", format!("Basic {auth}"))
+            .header("Content-Type", "application/json")
+            .body(Body::from(nested))
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let error: ErrorBody = serde_json::from_slice(&body).unwrap();
+        assert_eq!("json is nested deeper than 32 levels", error.message());
+    }
+
+    #[sqlx::test]
+    fn panic_response_carries_request_id(db: DbPool) {
+        let config = crate::infra::config::load_config().unwrap();
+        let state = AppState::new(db, config);
+        async fn panics() -> StatusCode {
+            panic!("forced")
+        }
+        let routes = Router::new().route("/panic", axum::routing::get(panics));
+        let app = with_layers(routes, state);
+
+        let req = Request::get("/panic")
+            .header("x-request-id", "panic-123")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, res.status());
+        assert_eq!("panic-123", res.headers()["x-request-id"]);
+
+        // A generated id is returned as well
+        let req = Request::get("/panic").body(Body::empty()).unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert!(res.headers().contains_key("x-request-id"));
+    }
+
+    #[sqlx::test]
+    fn repeated_failed_logins_are_rate_limited(db: DbPool) {
+        let mut config = crate::infra::config::load_config().unwrap();
+        config.security.login_limit.attempts = 2;
+        let app = test_app_with_config(db, config);
+        let login = || {
+            Request::post("/login")
+                .header("Content-Type", "application/x-www-form-urlencoded")
+                .body(Body::from("username=user&password=guess"))
+                .unwrap()
+        };
+        for _ in 0..2 {
+            let res = app.clone().oneshot(login()).await.unwrap();
+            assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+        }
+        let res = app.clone().oneshot(login()).await.unwrap();
+        assert_eq!(StatusCode::TOO_MANY_REQUESTS, res.status());
+        // Until the one minute window is over
+        let retry_after: u64 = res.headers()["Retry-After"]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((1..=60).contains(&retry_after), "{retry_after}");
+
+        // Basic auth shares the budget
+        let auth = base64::engine::general_purpose::STANDARD.encode("user:user");
+        let req = Request::get("/api/user")
+            .header("Authorization", format!("Basic {auth}"))
+            .body(Body::empty())
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::TOO_MANY_REQUESTS, res.status());
+
+        // Other users and unauthenticated traffic are unaffected
+        let auth = base64::engine::general_purpose::STANDARD.encode("admin:admin");
+        let req = Request::get("/api/user")
+            .header("Authorization", format!("Basic {auth}"))
+            .body(Body::empty())
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let req = Request::get("/api/hello").body(Body::empty()).unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+    }
+
     #[sqlx::test]
     fn post_login_with_wrong_password_responds_with_unauthorized(db: DbPool) {
         let url = spawn_app_with_db(db).await;
@@ -595,7 +2999,7 @@ mod tests {
             .build()
             .unwrap();
         let response = client
-            .post(&format!("{url}/login"))
+            .post(format!("{url}/login"))
             .form(&LoginParams {
                 username: "user".to_string(),
                 password: "notuser".to_string(),
@@ -605,4 +3009,17 @@ mod tests {
             .unwrap();
         assert_eq!(reqwest::StatusCode::UNAUTHORIZED, response.status());
     }
+
+    #[sqlx::test]
+    fn post_login_with_closed_pool_gives_error_instead_of_panic(db: DbPool) {
+        let app = test_app(db.clone());
+        db.close().await;
+        let req = Request::post("/login")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(Body::from("username=user&password=user"))
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        // A panic would be turned into a 500 by the panic handler
+        assert_eq!(StatusCode::BAD_GATEWAY, res.status());
+    }
 }
@@ -1,11 +1,15 @@
 //! For reading application configuration.
 
 use axum::extract::FromRef;
-use serde::Deserialize;
-use std::time::Duration;
+use chrono::{DateTime, Utc};
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize, Serializer};
+use std::{collections::HashMap, time::Duration};
 
 /// Application configuration.
-#[derive(Clone, Debug, Deserialize, FromRef)]
+///
+/// Secrets are redacted when serialized, so it is safe to expose.
+#[derive(Clone, Debug, Serialize, Deserialize, FromRef)]
 pub struct Config {
     /// Server configuration.
     pub server: ServerConfig,
@@ -13,10 +17,46 @@ pub struct Config {
     pub database: DatabaseConfig,
     /// Jaeger configuration.
     pub logging: LoggingConfig,
+    /// Message queue configuration.
+    #[serde(default)]
+    pub mq: MqConfig,
+    /// Email configuration.
+    #[serde(default)]
+    pub email: EmailConfig,
+    /// Password policy.
+    #[serde(default)]
+    pub password_policy: PasswordPolicy,
+    /// Streaming configuration.
+    #[serde(default)]
+    pub stream: StreamConfig,
+    /// Security configuration.
+    #[serde(default)]
+    pub security: SecurityConfig,
+    /// Features that can be toggled per environment.
+    #[serde(default)]
+    pub features: FeatureFlags,
+    /// Pagination limits.
+    #[serde(default)]
+    pub pagination: PaginationConfig,
+    /// Greetings for the hello endpoint.
+    #[serde(default)]
+    pub greeting: GreetingConfig,
+    /// Which dependencies must be reachable for the application to be ready.
+    #[serde(default)]
+    pub readiness: ReadinessConfig,
+    /// Item configuration.
+    #[serde(default)]
+    pub items: ItemsConfig,
+    /// Limits for JSON request bodies.
+    #[serde(default)]
+    pub json: JsonConfig,
+    /// Short URL configuration.
+    #[serde(default)]
+    pub urls: UrlsConfig,
 }
 
 /// Server configuration.
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ServerConfig {
     /// Server address.
     pub http_address: String,
@@ -29,14 +69,80 @@ pub struct ServerConfig {
     /// Lifetime of a session in seconds.
     #[serde(with = "humantime_serde")]
     pub session_duration: Duration,
+    /// The maximum size of an item attachment in bytes.
+    #[serde(default = "default_max_attachment_size")]
+    pub max_attachment_size: usize,
+    /// How long to wait for in-flight requests when shutting down.
+    #[serde(with = "humantime_serde", default = "default_shutdown_timeout")]
+    pub shutdown_timeout: Duration,
+    /// Proxies whose `x-forwarded-for` entries are trusted when determining the client ip.
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpNet>,
+    /// Paths, including everything below them, whose bodies are left out of the request log.
+    #[serde(default)]
+    pub unlogged_bodies: Vec<String>,
+    /// Routes that clients should stop using.
+    #[serde(default)]
+    pub deprecated_routes: Vec<DeprecatedRoute>,
+}
+
+fn default_max_attachment_size() -> usize {
+    1024 * 1024
+}
+
+fn default_shutdown_timeout() -> Duration {
+    Duration::from_secs(30)
+}
+
+impl ServerConfig {
+    /// The deprecated route that `path` is a request to, if any.
+    pub fn deprecated_route(&self, path: &str) -> Option<&DeprecatedRoute> {
+        self.deprecated_routes
+            .iter()
+            .find(|route| route.matches(path))
+    }
+
+    /// Whether request and response bodies should be left out of the request log for `path`.
+    pub fn is_body_unlogged(&self, path: &str) -> bool {
+        self.unlogged_bodies.iter().any(|unlogged| {
+            path.strip_prefix(unlogged.trim_end_matches('/'))
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        })
+    }
+}
+
+/// A route that is going away, announced to clients as described in RFC 8594.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeprecatedRoute {
+    /// The path as written in the OpenAPI spec, e.g. `/api/items/{id}`.
+    pub path: String,
+    /// When the route is expected to stop working.
+    #[serde(default)]
+    pub sunset: Option<DateTime<Utc>>,
+}
+
+impl DeprecatedRoute {
+    /// Whether a request to `path` is handled by this route.
+    ///
+    /// Segments like `{id}` match any single segment.
+    pub fn matches(&self, path: &str) -> bool {
+        let pattern = self.path.trim_end_matches('/').split('/');
+        let path = path.trim_end_matches('/').split('/');
+        pattern.clone().count() == path.clone().count()
+            && pattern.zip(path).all(|(pattern, segment)| {
+                pattern == segment
+                    || (pattern.starts_with('{') && pattern.ends_with('}') && !segment.is_empty())
+            })
+    }
 }
 
 /// Database configuration.
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct DatabaseConfig {
     /// The database username.
     pub username: String,
     /// The database password.
+    #[serde(serialize_with = "redact")]
     pub password: String,
     /// The database port.
     pub port: u16,
@@ -44,10 +150,52 @@ pub struct DatabaseConfig {
     pub database_name: String,
     /// The database host.
     pub host: String,
+    /// Queries slower than this are logged as warnings.
+    #[serde(with = "humantime_serde", default = "default_slow_query_threshold")]
+    pub slow_query_threshold: Duration,
+    /// Queries slower than this are aborted by the database.
+    #[serde(with = "humantime_serde", default = "default_statement_timeout")]
+    pub statement_timeout: Duration,
+    /// How long to wait for a connection from the pool.
+    #[serde(with = "humantime_serde", default = "default_acquire_timeout")]
+    pub acquire_timeout: Duration,
+    /// How long an unused connection is kept open.
+    #[serde(with = "humantime_serde", default = "default_idle_timeout")]
+    pub idle_timeout: Duration,
+    /// How long a connection is kept open before being replaced.
+    #[serde(with = "humantime_serde", default = "default_max_lifetime")]
+    pub max_lifetime: Duration,
+    /// How many times to attempt a write transaction that hits a serialization failure or deadlock.
+    #[serde(default = "default_transaction_attempts")]
+    pub transaction_attempts: u32,
+}
+
+fn default_slow_query_threshold() -> Duration {
+    Duration::from_secs(1)
+}
+
+fn default_statement_timeout() -> Duration {
+    Duration::from_secs(30)
+}
+
+fn default_acquire_timeout() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_idle_timeout() -> Duration {
+    Duration::from_secs(10 * 60)
+}
+
+fn default_max_lifetime() -> Duration {
+    Duration::from_secs(30 * 60)
+}
+
+fn default_transaction_attempts() -> u32 {
+    3
 }
 
 /// Jaeger configuration.
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct LoggingConfig {
     /// Logging configuration.
     pub rust_log: String,
@@ -55,6 +203,412 @@ pub struct LoggingConfig {
     pub jaeger_host: String,
     /// The jaeger port.
     pub jaeger_port: u16,
+    /// How often to log a snapshot of request and pool metrics, or zero to never log them.
+    #[serde(default, with = "humantime_serde")]
+    pub metrics_interval: Duration,
+    /// How hard to try storing each request in the request log.
+    #[serde(default)]
+    pub request_store: RequestStoreConfig,
+}
+
+/// Retries of storing a request in the request log.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RequestStoreConfig {
+    /// The number of attempts before giving up.
+    pub attempts: u32,
+    /// The delay after the first failed attempt, growing linearly with each attempt.
+    #[serde(with = "humantime_serde")]
+    pub backoff: Duration,
+    /// The most time to spend on storing a request, including delays.
+    #[serde(with = "humantime_serde")]
+    pub timeout: Duration,
+}
+
+impl Default for RequestStoreConfig {
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            backoff: Duration::from_secs(5),
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Message queue configuration.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MqConfig {
+    /// The message queue host.
+    pub host: String,
+    /// The message queue port.
+    pub port: u16,
+    /// The message queue username.
+    pub username: String,
+    /// The message queue password.
+    #[serde(serialize_with = "redact")]
+    pub password: String,
+}
+
+impl Default for MqConfig {
+    fn default() -> Self {
+        Self {
+            host: "localhost".to_string(),
+            port: 5672,
+            username: "guest".to_string(),
+            password: "guest".to_string(),
+        }
+    }
+}
+
+/// Email configuration.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EmailConfig {
+    /// The SMTP username.
+    pub username: String,
+    /// The SMTP password.
+    #[serde(serialize_with = "redact")]
+    pub password: String,
+    /// The SMTP relay host.
+    pub host: String,
+}
+
+impl Default for EmailConfig {
+    fn default() -> Self {
+        Self {
+            username: "changeme".to_string(),
+            password: "changeme".to_string(),
+            host: "localhost".to_string(),
+        }
+    }
+}
+
+/// Requirements for new passwords.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PasswordPolicy {
+    /// The minimum number of characters.
+    pub min_length: usize,
+    /// Require at least one uppercase letter.
+    pub require_uppercase: bool,
+    /// Require at least one lowercase letter.
+    pub require_lowercase: bool,
+    /// Require at least one digit.
+    pub require_digit: bool,
+    /// Require at least one character that is not a letter or digit.
+    pub require_symbol: bool,
+    /// Known breached passwords that are never accepted.
+    #[serde(default)]
+    pub denylist: Vec<String>,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: 8,
+            require_uppercase: true,
+            require_lowercase: true,
+            require_digit: true,
+            require_symbol: true,
+            denylist: Vec::new(),
+        }
+    }
+}
+
+/// Security configuration.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SecurityConfig {
+    /// The bcrypt work factor used when hashing passwords.
+    /// Lower it in tests to speed up authentication.
+    pub bcrypt_cost: u32,
+    /// How long an administrator may act as another user.
+    #[serde(with = "humantime_serde", default = "default_impersonation_duration")]
+    pub impersonation_duration: Duration,
+    /// Limits on failed logins.
+    #[serde(default)]
+    pub login_limit: LoginLimitConfig,
+}
+
+fn default_impersonation_duration() -> Duration {
+    Duration::from_secs(15 * 60)
+}
+
+impl Default for SecurityConfig {
+    fn default() -> Self {
+        Self {
+            bcrypt_cost: bcrypt::DEFAULT_COST,
+            impersonation_duration: default_impersonation_duration(),
+            login_limit: LoginLimitConfig::default(),
+        }
+    }
+}
+
+/// Limits on failed logins from one client for one username.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LoginLimitConfig {
+    /// The number of failed attempts allowed within a window.
+    pub attempts: u32,
+    /// How long failed attempts are counted for.
+    #[serde(with = "humantime_serde")]
+    pub window: Duration,
+}
+
+impl Default for LoginLimitConfig {
+    fn default() -> Self {
+        Self {
+            attempts: 5,
+            window: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Limits for streaming endpoints.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StreamConfig {
+    /// The maximum delay a client may request between each streamed element.
+    #[serde(with = "humantime_serde")]
+    pub max_throttle: Duration,
+    /// The maximum time a stream may hold on to a database connection.
+    #[serde(with = "humantime_serde")]
+    pub max_duration: Duration,
+}
+
+impl Default for StreamConfig {
+    fn default() -> Self {
+        Self {
+            max_throttle: Duration::from_secs(1),
+            max_duration: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Limits for paginated endpoints.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PaginationConfig {
+    /// The largest page size a client may request.
+    pub max_page_size: i64,
+    /// The largest number of rows a client may skip.
+    pub max_offset: i64,
+}
+
+impl Default for PaginationConfig {
+    fn default() -> Self {
+        Self {
+            max_page_size: 100,
+            max_offset: 10_000,
+        }
+    }
+}
+
+/// Limits for JSON request bodies, checked before they are parsed.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct JsonConfig {
+    /// How deeply arrays and objects may be nested.
+    pub max_depth: usize,
+    /// The most array elements and object members a body may contain in total.
+    pub max_elements: usize,
+}
+
+impl Default for JsonConfig {
+    fn default() -> Self {
+        Self {
+            max_depth: 32,
+            max_elements: 10_000,
+        }
+    }
+}
+
+/// Short URL configuration.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct UrlsConfig {
+    /// The schemes a short URL may redirect to, in lowercase.
+    pub allowed_schemes: Vec<String>,
+    /// Limits on visits to each short URL by one client.
+    #[serde(default)]
+    pub visit_limit: VisitLimitConfig,
+    /// Whether previews may fetch targets on loopback, private and link-local addresses.
+    #[serde(default)]
+    pub allow_private_targets: bool,
+}
+
+impl Default for UrlsConfig {
+    fn default() -> Self {
+        Self {
+            allowed_schemes: vec!["http".to_string(), "https".to_string()],
+            visit_limit: VisitLimitConfig::default(),
+            allow_private_targets: false,
+        }
+    }
+}
+
+/// Limits on visits to one short URL by one client.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct VisitLimitConfig {
+    /// The number of visits allowed within a window, or zero for no limit.
+    pub visits: u32,
+    /// How long visits are counted for.
+    #[serde(with = "humantime_serde")]
+    pub window: Duration,
+}
+
+impl Default for VisitLimitConfig {
+    fn default() -> Self {
+        Self {
+            visits: 600,
+            window: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Item configuration.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ItemsConfig {
+    /// The username that items without an owner are assigned to.
+    pub default_owner: String,
+    /// The trigram similarity, between 0 and 1, an item name needs to match a fuzzy search.
+    #[serde(default = "default_fuzzy_threshold")]
+    pub fuzzy_threshold: f32,
+}
+
+fn default_fuzzy_threshold() -> f32 {
+    0.3
+}
+
+impl Default for ItemsConfig {
+    fn default() -> Self {
+        Self {
+            default_owner: "admin".to_string(),
+            fuzzy_threshold: default_fuzzy_threshold(),
+        }
+    }
+}
+
+/// A subsystem the application depends on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Dependency {
+    /// The database.
+    Database,
+    /// The message queue.
+    Mq,
+    /// The mail server.
+    Smtp,
+}
+
+/// Readiness configuration.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ReadinessConfig {
+    /// Dependencies that must be reachable, the others are only reported.
+    pub required: Vec<Dependency>,
+    /// How long to wait for a single dependency before considering it unreachable.
+    #[serde(with = "humantime_serde")]
+    pub timeout: Duration,
+}
+
+impl Default for ReadinessConfig {
+    fn default() -> Self {
+        Self {
+            required: vec![Dependency::Database],
+            timeout: Duration::from_secs(2),
+        }
+    }
+}
+
+/// Greetings for the hello endpoint.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GreetingConfig {
+    /// The greeting used when no language is requested.
+    pub template: GreetingTemplate,
+    /// The name to greet when none is given.
+    pub default_name: String,
+    /// Greetings that can be selected by language code.
+    #[serde(default)]
+    pub languages: HashMap<String, GreetingTemplate>,
+}
+
+impl Default for GreetingConfig {
+    fn default() -> Self {
+        Self {
+            template: GreetingTemplate(format!("Hello, {}!", GreetingTemplate::NAME)),
+            default_name: "World".to_string(),
+            languages: HashMap::new(),
+        }
+    }
+}
+
+/// A greeting containing exactly one `{name}` placeholder.
+///
+/// Templates are checked when the configuration is loaded.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String")]
+pub struct GreetingTemplate(String);
+
+impl GreetingTemplate {
+    const NAME: &'static str = "{name}";
+
+    /// Fills in the name to greet.
+    pub fn render(&self, name: &str) -> String {
+        self.0.replace(Self::NAME, name)
+    }
+}
+
+impl TryFrom<String> for GreetingTemplate {
+    type Error = String;
+
+    fn try_from(template: String) -> Result<Self, Self::Error> {
+        match template.matches(Self::NAME).count() {
+            1 => Ok(Self(template)),
+            n => Err(format!(
+                "greeting template must contain {} exactly once, found {n} in {template:?}",
+                Self::NAME
+            )),
+        }
+    }
+}
+
+/// A feature that can be toggled with a [`FeatureFlags`] entry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Feature {
+    /// The URL shortener endpoints.
+    Urls,
+    /// The item attachment endpoints.
+    Attachments,
+}
+
+/// Toggles for optional features. Every feature is enabled unless disabled.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FeatureFlags {
+    /// Enables the URL shortener endpoints.
+    #[serde(default = "enabled")]
+    pub urls: bool,
+    /// Enables the item attachment endpoints.
+    #[serde(default = "enabled")]
+    pub attachments: bool,
+}
+
+/// Serializes a secret without revealing it.
+fn redact<T, S: Serializer>(_: &T, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str("***")
+}
+
+fn enabled() -> bool {
+    true
+}
+
+impl Default for FeatureFlags {
+    fn default() -> Self {
+        Self {
+            urls: true,
+            attachments: true,
+        }
+    }
+}
+
+impl FeatureFlags {
+    /// Returns whether the given feature is enabled.
+    pub fn is_enabled(&self, feature: Feature) -> bool {
+        match feature {
+            Feature::Urls => self.urls,
+            Feature::Attachments => self.attachments,
+        }
+    }
 }
 
 /// Retrieve [`Config`] from the default configuration file.
@@ -67,3 +621,110 @@ pub fn load_config() -> color_eyre::Result<Config> {
         .try_deserialize()?;
     Ok(config)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serialized_config_has_no_secrets() {
+        let mut config = load_config().unwrap();
+        config.database.password = "db-secret".to_string();
+        config.mq.password = "mq-secret".to_string();
+        config.email.password = "email-secret".to_string();
+
+        let json = serde_json::to_string(&config).unwrap();
+        assert!(!json.contains("secret"), "{json}");
+
+        let value = serde_json::to_value(&config).unwrap();
+        assert_eq!("***", value["database"]["password"]);
+        assert_eq!(config.database.host, value["database"]["host"]);
+    }
+
+    #[test]
+    fn config_without_newer_settings_uses_defaults() {
+        // The configuration from before most settings were added
+        let old = r#"
+            [server]
+            http_address = "0.0.0.0"
+            http_port = 8080
+            grpc_address = "0.0.0.0"
+            grpc_port = 3009
+            session_duration = "1min"
+
+            [database]
+            host = "localhost"
+            port = 5432
+            username = "postgres"
+            password = "password"
+            database_name = "axum-demo"
+
+            [logging]
+            rust_log = "warn"
+            jaeger_host = "http://localhost"
+            jaeger_port = 4317
+        "#;
+        let config: Config = config::Config::builder()
+            .add_source(config::File::from_str(old, config::FileFormat::Toml))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap();
+        let current = load_config().unwrap();
+        assert_eq!(
+            current.server.max_attachment_size,
+            config.server.max_attachment_size
+        );
+        assert_eq!(
+            current.server.shutdown_timeout,
+            config.server.shutdown_timeout
+        );
+        assert_eq!(
+            current.database.slow_query_threshold,
+            config.database.slow_query_threshold
+        );
+        assert_eq!(
+            current.database.statement_timeout,
+            config.database.statement_timeout
+        );
+        assert_eq!(
+            current.database.acquire_timeout,
+            config.database.acquire_timeout
+        );
+        assert_eq!(current.database.idle_timeout, config.database.idle_timeout);
+        assert_eq!(current.database.max_lifetime, config.database.max_lifetime);
+        assert_eq!(current.mq.port, config.mq.port);
+        assert_eq!(current.email.host, config.email.host);
+        assert_eq!(
+            current.password_policy.min_length,
+            config.password_policy.min_length
+        );
+        assert_eq!(current.stream.max_duration, config.stream.max_duration);
+    }
+
+    #[test]
+    fn deprecated_routes_match_path_parameters() {
+        let route = DeprecatedRoute {
+            path: "/api/items/{id}".to_string(),
+            sunset: None,
+        };
+        assert!(route.matches("/api/items/1"));
+        assert!(route.matches("/api/items/1/"));
+        assert!(!route.matches("/api/items"));
+        assert!(!route.matches("/api/items/"));
+        assert!(!route.matches("/api/items/1/attachment"));
+        assert!(!route.matches("/api/urls/1"));
+    }
+
+    #[test]
+    fn greeting_template_renders_name() {
+        let template = GreetingTemplate::try_from("Hei, {name}!".to_string()).unwrap();
+        assert_eq!("Hei, Foo!", template.render("Foo"));
+    }
+
+    #[test]
+    fn greeting_template_without_single_placeholder_is_rejected() {
+        assert!(serde_json::from_str::<GreetingTemplate>(r#""Hello!""#).is_err());
+        assert!(serde_json::from_str::<GreetingTemplate>(r#""{name} {name}""#).is_err());
+    }
+}
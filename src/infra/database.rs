@@ -1,7 +1,12 @@
 //! For interacting with the database.
 
-use super::config::DatabaseConfig;
+use super::{
+    config::DatabaseConfig,
+    error::{ApiError, ApiResult, InternalError},
+};
+use futures::future::BoxFuture;
 use sqlx::{
+    migrate::Migrator,
     pool::{PoolConnection, PoolOptions},
     postgres::{PgConnectOptions, PgSslMode},
     ConnectOptions, PgPool, Postgres,
@@ -9,6 +14,9 @@ use sqlx::{
 use std::time::Duration;
 use tracing::log::LevelFilter;
 
+/// The migrations embedded in the application.
+pub static MIGRATOR: Migrator = sqlx::migrate!();
+
 /// A common transaction type.
 /// Use this for the business and persistence layer.
 pub type Tx = sqlx::Transaction<'static, Postgres>;
@@ -28,14 +36,233 @@ pub fn init_db(config: &DatabaseConfig) -> PgPool {
         .port(config.port)
         .database(&config.database_name)
         .ssl_mode(PgSslMode::Prefer)
-        .log_statements(LevelFilter::Debug)
-        .log_slow_statements(LevelFilter::Warn, Duration::from_secs(1));
+        .log_statements(LevelFilter::Debug);
+    let db_options = with_slow_query_log(db_options, config.slow_query_threshold);
+    let db_options = with_statement_timeout(db_options, config.statement_timeout);
     let db: PgPool = PoolOptions::default()
-        .acquire_timeout(Duration::from_secs(10))
+        .acquire_timeout(config.acquire_timeout)
         .min_connections(5)
         .max_connections(25)
-        .idle_timeout(Duration::from_secs(10 * 60))
-        .max_lifetime(Duration::from_secs(30 * 60))
+        .idle_timeout(config.idle_timeout)
+        .max_lifetime(config.max_lifetime)
         .connect_lazy_with(db_options);
     db
 }
+
+/// Logs a warning with the statement and elapsed time for queries slower than `threshold`.
+pub fn with_slow_query_log(options: PgConnectOptions, threshold: Duration) -> PgConnectOptions {
+    options.log_slow_statements(LevelFilter::Warn, threshold)
+}
+
+/// Makes the database abort statements that run longer than `timeout`.
+pub fn with_statement_timeout(options: PgConnectOptions, timeout: Duration) -> PgConnectOptions {
+    options.options([("statement_timeout", timeout.as_millis().to_string())])
+}
+
+/// SQLSTATEs of failures that may succeed if the transaction is retried,
+/// `serialization_failure` and `deadlock_detected`.
+const RETRYABLE_CODES: [&str; 2] = ["40001", "40P01"];
+
+/// Whether an error is a transient conflict with a concurrent transaction.
+fn is_retryable(e: &ApiError) -> bool {
+    match e {
+        ApiError::InternalError(InternalError::SqlxError(sqlx::Error::Database(e))) => e
+            .code()
+            .is_some_and(|code| RETRYABLE_CODES.contains(&code.as_ref())),
+        _ => false,
+    }
+}
+
+/// Runs `f` in a transaction and commits it, retrying up to `attempts` times in total
+/// on serialization failures and deadlocks.
+///
+/// Since `f` may run more than once, it must not have side effects outside the transaction.
+pub async fn with_retry_on_serialization<T, F>(db: &DbPool, attempts: u32, mut f: F) -> ApiResult<T>
+where
+    F: for<'c> FnMut(&'c mut Tx) -> BoxFuture<'c, ApiResult<T>>,
+{
+    let mut attempt = 1;
+    loop {
+        let mut tx = db.begin().await?;
+        let result = match f(&mut tx).await {
+            Ok(value) => tx.commit().await.map(|_| value).map_err(ApiError::from),
+            Err(e) => Err(e),
+        };
+        match result {
+            Err(e) if attempt < attempts && is_retryable(&e) => {
+                tracing::warn!("Retrying transaction (attempt {}): {}", attempt, e);
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// A transaction for tests that is always rolled back.
+///
+/// It derefs to a [`Tx`], but cannot be committed, so a test cannot
+/// accidentally leave data behind for other tests sharing the database.
+#[cfg(test)]
+pub(crate) struct TestTx(Tx);
+
+#[cfg(test)]
+impl TestTx {
+    /// Starts a transaction that is rolled back when dropped.
+    pub(crate) async fn begin(db: &DbPool) -> Self {
+        Self(db.begin().await.expect("failed to begin test transaction"))
+    }
+}
+
+#[cfg(test)]
+impl std::ops::Deref for TestTx {
+    type Target = Tx;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl std::ops::DerefMut for TestTx {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infra::logging::CapturedLogs;
+    use sqlx::postgres::PgPoolOptions;
+
+    #[sqlx::test]
+    async fn test_tx_is_rolled_back_on_drop(db: DbPool) {
+        let mut tx = TestTx::begin(&db).await;
+        sqlx::query("INSERT INTO items (name) VALUES ('temporary')")
+            .execute(tx.as_mut())
+            .await
+            .unwrap();
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM items")
+            .fetch_one(tx.as_mut())
+            .await
+            .unwrap();
+        assert_eq!(1, count);
+        drop(tx);
+
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM items")
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(0, count);
+    }
+
+    #[sqlx::test]
+    async fn slow_query_is_logged_as_warning(
+        pool_opts: PgPoolOptions,
+        connect_opts: PgConnectOptions,
+    ) {
+        let connect_opts = with_slow_query_log(connect_opts, Duration::from_millis(100));
+        let db = pool_opts.connect_with(connect_opts).await.unwrap();
+        let logs = CapturedLogs::default();
+        let _guard = logs.capture();
+
+        sqlx::query("SELECT 'fast'").execute(&db).await.unwrap();
+        sqlx::query("SELECT 'slow', pg_sleep(0.2)")
+            .execute(&db)
+            .await
+            .unwrap();
+
+        let warnings: Vec<_> = logs
+            .output()
+            .lines()
+            .filter(|l| l.contains("WARN") && l.contains("slow statement"))
+            .map(str::to_string)
+            .collect();
+        assert_eq!(1, warnings.len(), "{warnings:?}");
+        assert!(warnings[0].contains("pg_sleep"), "{warnings:?}");
+    }
+
+    #[sqlx::test]
+    async fn statement_exceeding_timeout_is_aborted(
+        pool_opts: PgPoolOptions,
+        connect_opts: PgConnectOptions,
+    ) {
+        let connect_opts = with_statement_timeout(connect_opts, Duration::from_millis(100));
+        let db = pool_opts.connect_with(connect_opts).await.unwrap();
+
+        sqlx::query("SELECT 'fast'").execute(&db).await.unwrap();
+        let result = sqlx::query("SELECT pg_sleep(5)").execute(&db).await;
+
+        let Err(sqlx::Error::Database(e)) = result else {
+            panic!("expected statement to be aborted, got {result:?}");
+        };
+        // query_canceled
+        assert_eq!(Some("57014"), e.code().as_deref());
+    }
+
+    #[sqlx::test]
+    async fn serialization_failure_is_retried(db: DbPool) {
+        sqlx::query("CREATE TABLE attempts (n INT)")
+            .execute(&db)
+            .await
+            .unwrap();
+        let mut calls = 0;
+        let result = with_retry_on_serialization(&db, 3, |tx| {
+            calls += 1;
+            let fail = calls == 1;
+            Box::pin(async move {
+                sqlx::query("INSERT INTO attempts VALUES (1)")
+                    .execute(tx.as_mut())
+                    .await?;
+                if fail {
+                    sqlx::query("DO $$ BEGIN RAISE serialization_failure; END $$")
+                        .execute(tx.as_mut())
+                        .await?;
+                }
+                Ok("done")
+            })
+        })
+        .await;
+        assert_eq!("done", result.unwrap());
+        assert_eq!(2, calls);
+
+        // Only the successful attempt was committed
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM attempts")
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(1, count);
+    }
+
+    #[sqlx::test]
+    async fn retries_give_up_after_attempts(db: DbPool) {
+        let mut calls = 0;
+        let result: ApiResult<()> = with_retry_on_serialization(&db, 3, |tx| {
+            calls += 1;
+            Box::pin(async move {
+                sqlx::query("DO $$ BEGIN RAISE deadlock_detected; END $$")
+                    .execute(tx.as_mut())
+                    .await?;
+                Ok(())
+            })
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(3, calls);
+    }
+
+    #[sqlx::test]
+    async fn other_errors_are_not_retried(db: DbPool) {
+        let mut calls = 0;
+        let result: ApiResult<()> = with_retry_on_serialization(&db, 3, |tx| {
+            calls += 1;
+            Box::pin(async move {
+                sqlx::query("SELECT 1 / 0").execute(tx.as_mut()).await?;
+                Ok(())
+            })
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(1, calls);
+    }
+}
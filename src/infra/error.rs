@@ -12,11 +12,13 @@ use axum::{
 use chrono::{DateTime, Utc};
 use hyper::StatusCode;
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
 use tower_http::catch_panic::ResponseForPanic;
 use utoipa::ToSchema;
 
 /// A standard error response body.
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
 pub struct ErrorBody {
     /// A description of the error.
     message: String,
@@ -77,14 +79,44 @@ impl From<sqlx::Error> for ApiError {
     fn from(e: sqlx::Error) -> Self {
         match e {
             sqlx::Error::RowNotFound => ApiError::ClientError(ClientError::NotFound),
+            sqlx::Error::PoolTimedOut => ApiError::InternalError(InternalError::PoolTimedOut),
             sqlx::Error::Database(e) if e.constraint().is_some() => {
-                ApiError::ClientError(ClientError::Conflict)
+                let message = conflict_message(e.constraint().unwrap_or_default());
+                ApiError::ClientError(ClientError::Conflict(message.to_string()))
             }
             e => ApiError::InternalError(InternalError::SqlxError(e)),
         }
     }
 }
 
+/// A client-friendly description of a violated database constraint.
+fn conflict_message(constraint: &str) -> &'static str {
+    match constraint {
+        "items_name_key" => "item name already taken",
+        "users_username_key" => "username already taken",
+        "short_urls_name_key" => "short url name already taken",
+        _ => "conflict",
+    }
+}
+
+impl From<color_eyre::Report> for ApiError {
+    fn from(e: color_eyre::Report) -> Self {
+        ApiError::InternalError(InternalError::Report(e))
+    }
+}
+
+impl From<std::io::Error> for ApiError {
+    fn from(e: std::io::Error) -> Self {
+        match e.kind() {
+            std::io::ErrorKind::NotFound => {
+                tracing::debug!("file not found: {}", e);
+                ApiError::ClientError(ClientError::NotFound)
+            }
+            _ => ApiError::InternalError(InternalError::IoError(e)),
+        }
+    }
+}
+
 impl From<bcrypt::BcryptError> for ApiError {
     fn from(e: bcrypt::BcryptError) -> Self {
         ApiError::InternalError(InternalError::BcryptError(e))
@@ -93,19 +125,9 @@ impl From<bcrypt::BcryptError> for ApiError {
 
 impl From<validator::ValidationErrors> for ApiError {
     fn from(e: validator::ValidationErrors) -> Self {
-        let mut invalid_fields = String::new();
-        for (k, v) in e.field_errors() {
-            let mut codes = String::new();
-            for e in v {
-                codes += &format!("{},", e.code);
-            }
-            let codes = codes.trim_end_matches(',');
-            invalid_fields += &format!("{k} ({codes}), ");
-        }
-        let invalid_fields = invalid_fields.trim_end_matches(", ");
-        ApiError::ClientError(ClientError::UnprocessableEntity(format!(
-            "invalid field(s): {invalid_fields}"
-        )))
+        ApiError::ClientError(ClientError::UnprocessableEntity(
+            super::validation::describe(&e),
+        ))
     }
 }
 
@@ -129,11 +151,20 @@ pub enum ClientError {
     #[error("not found")]
     NotFound,
     /// The resource already exists.
-    #[error("conflict")]
-    Conflict,
+    #[error("{0}")]
+    Conflict(String),
     /// Validation errors.
     #[error("{0}")]
     UnprocessableEntity(String),
+    /// A conditional request did not match the current state of the resource.
+    #[error("precondition failed")]
+    PreconditionFailed,
+    /// The resource existed, but is no longer available.
+    #[error("gone")]
+    Gone,
+    /// The client has sent too many requests and should retry after the given duration.
+    #[error("{0}")]
+    TooManyRequests(String, Duration),
     /// Custom error.
     #[error("{1}")]
     Custom(StatusCode, String),
@@ -147,7 +178,22 @@ impl Default for ClientError {
 
 impl From<JsonRejection> for ClientError {
     fn from(value: JsonRejection) -> Self {
-        ClientError::Custom(value.status(), value.body_text())
+        // Use the underlying error, which is concise and includes the field and location
+        let message = std::error::Error::source(&value)
+            .and_then(std::error::Error::source)
+            .map(|source| source.to_string());
+        match value {
+            // Includes validation details from `Valid<T>`
+            JsonRejection::JsonDataError(e) => {
+                ClientError::UnprocessableEntity(message.unwrap_or_else(|| e.body_text()))
+            }
+            JsonRejection::JsonSyntaxError(e) => ClientError::BadRequest(format!(
+                "malformed json: {}",
+                message.unwrap_or_else(|| e.body_text())
+            )),
+            JsonRejection::MissingJsonContentType(_) => ClientError::UnsupportedMediaType,
+            value => ClientError::Custom(value.status(), value.body_text()),
+        }
     }
 }
 
@@ -166,17 +212,32 @@ impl From<PathRejection> for ClientError {
 impl IntoResponse for ClientError {
     fn into_response(self) -> axum::response::Response {
         let msg = self.to_string();
+        let retry_after = match &self {
+            Self::TooManyRequests(_, retry_after) => Some(*retry_after),
+            _ => None,
+        };
         let status = match self {
             Self::BadRequest(_) => StatusCode::BAD_REQUEST,
             Self::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
             Self::Unauthorized => StatusCode::UNAUTHORIZED,
             Self::Forbidden => StatusCode::FORBIDDEN,
             Self::NotFound => StatusCode::NOT_FOUND,
-            Self::Conflict => StatusCode::CONFLICT,
+            Self::Conflict(_) => StatusCode::CONFLICT,
             Self::UnprocessableEntity(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
+            Self::Gone => StatusCode::GONE,
+            Self::TooManyRequests(..) => StatusCode::TOO_MANY_REQUESTS,
             Self::Custom(status, _) => status,
         };
-        (status, Json(ErrorBody::new(msg))).into_response()
+        let mut response = (status, Json(ErrorBody::new(msg))).into_response();
+        if let Some(retry_after) = retry_after {
+            // Whole seconds, rounded up so that retrying on time is not rejected again
+            let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+            response
+                .headers_mut()
+                .insert("Retry-After", HeaderValue::from(seconds.max(1)));
+        }
+        response
     }
 }
 
@@ -187,6 +248,12 @@ pub enum InternalError {
     /// An [`sqlx`] error.
     #[error("{0}")]
     SqlxError(#[from] sqlx::Error),
+    /// No database connection became available in time.
+    #[error("timed out waiting for a database connection")]
+    PoolTimedOut,
+    /// The session store could not be read.
+    #[error("session store is unavailable: {0}")]
+    SessionStoreUnavailable(String),
     /// An axum extension was not set.
     #[error("missing extension: {0}")]
     MissingExtension(String),
@@ -202,15 +269,28 @@ pub enum InternalError {
     /// Serde json error.
     #[error("serde json error: {0}")]
     SerdeJsonError(#[from] serde_json::Error),
+    /// Reading or writing a file failed.
+    #[error("io error: {0}")]
+    IoError(#[from] std::io::Error),
+    /// An error from code using [`color_eyre`], displayed with its full chain of causes.
+    #[error("{0:#}")]
+    Report(#[from] color_eyre::Report),
     /// Other miscellaneous errors.
     #[error("{0}")]
     Other(String),
 }
 
+/// Marks a response caused by an exhausted database pool.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PoolExhausted;
+
 impl IntoResponse for InternalError {
     fn into_response(self) -> axum::response::Response {
+        let pool_exhausted = matches!(self, Self::PoolTimedOut);
         let status = match self {
             Self::SqlxError(_) => StatusCode::BAD_GATEWAY,
+            Self::PoolTimedOut => StatusCode::SERVICE_UNAVAILABLE,
+            Self::SessionStoreUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
             Self::IntegrationError(_) => StatusCode::BAD_GATEWAY,
             Self::ReqwestError(e) if e.is_timeout() => StatusCode::GATEWAY_TIMEOUT,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
@@ -220,6 +300,9 @@ impl IntoResponse for InternalError {
         response
             .headers_mut()
             .insert("Retry-After", HeaderValue::from_static("5"));
+        if pool_exhausted {
+            response.extensions_mut().insert(PoolExhausted);
+        }
         response
     }
 }
@@ -270,6 +353,125 @@ mod tests {
         assert!(now.signed_duration_since(error.timestamp).num_seconds() < 60);
     }
 
+    #[test]
+    fn known_constraints_give_specific_conflict_messages() {
+        assert_eq!(
+            "item name already taken",
+            conflict_message("items_name_key")
+        );
+        assert_eq!(
+            "short url name already taken",
+            conflict_message("short_urls_name_key")
+        );
+        assert_eq!("conflict", conflict_message("unknown_constraint"));
+    }
+
+    #[tokio::test]
+    async fn too_many_requests_gives_429_with_retry_after() {
+        let retry_after = Duration::from_millis(41_500);
+        let res =
+            ClientError::TooManyRequests("slow down".to_string(), retry_after).into_response();
+        assert_eq!(StatusCode::TOO_MANY_REQUESTS, res.status());
+        assert_eq!("42", res.headers()["Retry-After"]);
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let error: ErrorBody = serde_json::from_slice(&body).unwrap();
+        assert_eq!("slow down", error.message());
+    }
+
+    async fn read_file(path: &str) -> ApiResult<Vec<u8>> {
+        Ok(tokio::fs::read(path).await?)
+    }
+
+    #[tokio::test]
+    async fn missing_file_gives_404() {
+        let error = read_file("/definitely/not/a/file").await.unwrap_err();
+        assert_eq!(StatusCode::NOT_FOUND, error.into_response().status());
+    }
+
+    #[tokio::test]
+    async fn io_failure_gives_500_and_logs_os_error() {
+        let logs = crate::infra::logging::CapturedLogs::default();
+        let _guard = logs.capture();
+
+        // Reading a directory as a file fails
+        let error = read_file("/").await.unwrap_err();
+        assert_eq!(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            error.into_response().status()
+        );
+        let output = logs.output();
+        assert!(output.contains("internal error: io error:"), "{output}");
+        assert!(output.contains("os error"), "{output}");
+    }
+
+    async fn json_error(body: &'static str) -> (StatusCode, String) {
+        use crate::{api::item::item_repository::NewItem, infra::config::JsonConfig};
+        use axum::extract::FromRequest;
+
+        let req = http::Request::post("/")
+            .header("Content-Type", "application/json")
+            .body(axum::body::Body::from(body))
+            .unwrap();
+        let rejection = Json::<NewItem>::from_request(req, &JsonConfig::default())
+            .await
+            .unwrap_err();
+        let res = rejection.into_response();
+        let status = res.status();
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let error: ErrorBody = serde_json::from_slice(&body).unwrap();
+        (status, error.message)
+    }
+
+    #[tokio::test]
+    async fn truncated_json_gives_bad_request_with_location() {
+        let (status, message) = json_error(r#"{"name": "foo""#).await;
+        assert_eq!(StatusCode::BAD_REQUEST, status);
+        assert_eq!(
+            "malformed json: EOF while parsing an object at line 1 column 14",
+            message
+        );
+    }
+
+    #[tokio::test]
+    async fn wrong_field_type_gives_unprocessable_entity_with_field() {
+        let (status, message) = json_error(r#"{"name": 1}"#).await;
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, status);
+        assert_eq!(
+            "name: invalid type: integer `1`, expected a string at line 1 column 10",
+            message
+        );
+    }
+
+    #[tokio::test]
+    async fn eyre_report_gives_internal_error_and_logs_chain() {
+        use color_eyre::eyre::{eyre, WrapErr};
+
+        let logs = crate::infra::logging::CapturedLogs::default();
+        let _guard = logs.capture();
+
+        async fn handler() -> ApiResult<()> {
+            Err(eyre!("connection refused")).wrap_err("failed to reach upstream")?;
+            Ok(())
+        }
+        let res = handler().await.into_response();
+
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, res.status());
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let error: ErrorBody = serde_json::from_slice(&body).unwrap();
+        assert_eq!("internal error", error.message());
+        let output = logs.output();
+        assert!(
+            output.contains("failed to reach upstream: connection refused"),
+            "{output}"
+        );
+    }
+
     #[test]
     fn validation_errors_gives_useful_message() {
         let mut errors = validator::ValidationErrors::new();
@@ -1,17 +1,122 @@
 //! Custom axum extractors.
 
-use super::error::ClientError;
+use super::{
+    config::{Config, JsonConfig},
+    error::{ApiError, ClientError},
+    middleware::request_client_ip,
+    security::{Role, Unknown, User},
+    state::AppState,
+    validation::Valid,
+};
 use axum::{
-    extract::{FromRequest, FromRequestParts},
+    async_trait,
+    extract::{rejection::JsonRejection, FromRef, FromRequest, FromRequestParts, Request},
     response::IntoResponse,
 };
-use serde::Serialize;
+use bytes::Bytes;
+use http::{header::CONTENT_TYPE, request::Parts, HeaderMap};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{convert::Infallible, net::IpAddr};
+use validator::Validate;
 
 /// A custom JSON extractor since axum's does not let us customize the response.
-#[derive(Debug, Clone, Copy, Default, FromRequest)]
-#[from_request(via(axum::extract::Json), rejection(ClientError))]
+///
+/// Bodies exceeding the limits in [`JsonConfig`] are rejected before they are parsed.
+#[derive(Debug, Clone, Copy, Default)]
 pub struct Json<T>(pub T);
 
+#[async_trait]
+impl<T, S> FromRequest<S> for Json<T>
+where
+    T: DeserializeOwned,
+    JsonConfig: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = ClientError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        if !has_json_content_type(req.headers()) {
+            return Err(ClientError::UnsupportedMediaType);
+        }
+        let bytes = Bytes::from_request(req, state)
+            .await
+            .map_err(JsonRejection::from)?;
+        check_json_limits(&bytes, &JsonConfig::from_ref(state))?;
+        let axum::extract::Json(value) = axum::extract::Json::from_bytes(&bytes)?;
+        Ok(Json(value))
+    }
+}
+
+/// Whether the body is declared to be JSON, e.g. `application/json` or `application/problem+json`.
+fn has_json_content_type(headers: &HeaderMap) -> bool {
+    let Some(content_type) = headers.get(CONTENT_TYPE).and_then(|v| v.to_str().ok()) else {
+        return false;
+    };
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    essence == "application/json"
+        || essence
+            .strip_prefix("application/")
+            .is_some_and(|subtype| subtype.ends_with("+json"))
+}
+
+/// Checks that a JSON document stays within `limits` without parsing it.
+///
+/// This is a single pass over the brackets and commas outside of strings, which
+/// stops at the first violation. Malformed documents are left for the parser to reject.
+fn check_json_limits(json: &[u8], limits: &JsonConfig) -> Result<(), ClientError> {
+    let mut depth = 0usize;
+    let mut elements = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+    // Whether the next value is the first in an array or object
+    let mut first = false;
+    for &byte in json {
+        if in_string {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        if byte.is_ascii_whitespace() {
+            continue;
+        }
+        let first_in_container = std::mem::take(&mut first);
+        if byte == b',' || (first_in_container && !matches!(byte, b']' | b'}')) {
+            elements += 1;
+            if elements > limits.max_elements {
+                return Err(ClientError::BadRequest(format!(
+                    "json has more than {} elements",
+                    limits.max_elements
+                )));
+            }
+        }
+        match byte {
+            b'"' => in_string = true,
+            b'[' | b'{' => {
+                depth += 1;
+                first = true;
+                if depth > limits.max_depth {
+                    return Err(ClientError::BadRequest(format!(
+                        "json is nested deeper than {} levels",
+                        limits.max_depth
+                    )));
+                }
+            }
+            b']' | b'}' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
 impl<T> AsRef<T> for Json<T> {
     fn as_ref(&self) -> &T {
         &self.0
@@ -24,13 +129,236 @@ impl<T: Serialize> IntoResponse for Json<T> {
     }
 }
 
-/// A custom Query extractor since axum's does not let us customize the response.
-#[derive(Debug, Clone, Copy, Default, FromRequestParts)]
-#[from_request(via(axum::extract::Query), rejection(ClientError))]
+/// An authenticated user together with the validated JSON body they sent.
+///
+/// The user is authenticated before the body is read, so unauthenticated
+/// requests are rejected without parsing it.
+#[derive(Debug)]
+pub struct AuthenticatedJson<T, R = Unknown> {
+    /// The user making the request.
+    pub user: User<R>,
+    /// The validated body.
+    pub value: Valid<T>,
+}
+
+#[async_trait]
+impl<T, R> FromRequest<AppState> for AuthenticatedJson<T, R>
+where
+    T: DeserializeOwned + Validate,
+    R: Role + Send,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: Request, state: &AppState) -> Result<Self, Self::Rejection> {
+        let (mut parts, body) = req.into_parts();
+        let user = User::<R>::from_request_parts(&mut parts, state).await?;
+        let req = Request::from_parts(parts, body);
+        let Json(value) = Json::<Valid<T>>::from_request(req, state).await?;
+        Ok(Self { user, value })
+    }
+}
+
+/// The ip of the client making a request, if known.
+///
+/// Entries in `x-forwarded-for` are only used when added by a trusted proxy.
+#[derive(Debug, Clone, Copy)]
+pub struct ClientIp(pub Option<IpAddr>);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ClientIp
+where
+    Config: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let config = Config::from_ref(state);
+        let trusted_proxies = &config.server.trusted_proxies;
+        Ok(ClientIp(request_client_ip(
+            &parts.extensions,
+            &parts.headers,
+            trusted_proxies,
+        )))
+    }
+}
+
+/// A custom Query extractor since axum's does not say which parameter was invalid.
+#[derive(Debug, Clone, Copy, Default)]
 pub struct Query<T>(pub T);
 
+#[async_trait]
+impl<T, S> FromRequestParts<S> for Query<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = ClientError;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        let query = parts.uri.query().unwrap_or_default();
+        let deserializer =
+            serde_urlencoded::Deserializer::new(form_urlencoded::parse(query.as_bytes()));
+        serde_path_to_error::deserialize(deserializer)
+            .map(Query)
+            .map_err(|e| ClientError::BadRequest(describe_query_error(&e)))
+    }
+}
+
+/// Describes a query string error, naming the parameter when it is known.
+fn describe_query_error(e: &serde_path_to_error::Error<serde_urlencoded::de::Error>) -> String {
+    let message = e.inner().to_string();
+    if let Some(field) = message.strip_prefix("missing field ") {
+        return format!("missing query parameter {field}");
+    }
+    match e.path().to_string().as_str() {
+        "." => format!("invalid query string: {message}"),
+        path => format!("invalid query parameter `{path}`: {message}"),
+    }
+}
+
 impl<T> AsRef<T> for Query<T> {
     fn as_ref(&self) -> &T {
         &self.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api::item::item_repository::NewItem,
+        infra::{config::load_config, database::DbPool},
+    };
+    use axum::body::Body;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize)]
+    #[allow(dead_code)]
+    struct Params {
+        name: String,
+        page: Option<i64>,
+    }
+
+    async fn query_error(uri: &str) -> String {
+        let (mut parts, _) = http::Request::get(uri).body(()).unwrap().into_parts();
+        match Query::<Params>::from_request_parts(&mut parts, &()).await {
+            Err(ClientError::BadRequest(message)) => message,
+            other => panic!("expected bad request, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn wrong_type_names_parameter() {
+        assert_eq!(
+            "invalid query parameter `page`: invalid digit found in string",
+            query_error("/?name=foo&page=two").await
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_parameter_is_named() {
+        assert_eq!(
+            "missing query parameter `name`",
+            query_error("/?page=1").await
+        );
+    }
+
+    async fn authenticated_json(
+        db: DbPool,
+        auth: Option<&str>,
+        body: &str,
+    ) -> Result<AuthenticatedJson<NewItem>, ApiError> {
+        let state = AppState::new(db, load_config().unwrap());
+        let mut req = http::Request::post("/").header("Content-Type", "application/json");
+        if let Some(auth) = auth {
+            req = req.header("Authorization", auth);
+        }
+        let req = req.body(Body::from(body.to_string())).unwrap();
+        AuthenticatedJson::from_request(req, &state).await
+    }
+
+    fn limits() -> JsonConfig {
+        JsonConfig {
+            max_depth: 3,
+            max_elements: 5,
+        }
+    }
+
+    #[test]
+    fn json_within_limits_is_accepted() {
+        let json = br#"{"a": [1, {"b": "[[[[,,,,,"}], "c": []}"#;
+        assert!(check_json_limits(json, &limits()).is_ok());
+        assert!(check_json_limits(br#""\"[[[[""#, &limits()).is_ok());
+    }
+
+    #[test]
+    fn deeply_nested_json_is_rejected_without_parsing() {
+        // Unterminated, so a parser would have to read all of it to find out
+        let json = "[".repeat(1_000_000);
+        match check_json_limits(json.as_bytes(), &limits()) {
+            Err(ClientError::BadRequest(message)) => {
+                assert_eq!("json is nested deeper than 3 levels", message)
+            }
+            other => panic!("expected bad request, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn json_with_too_many_elements_is_rejected() {
+        let json = format!("[{}", "0,".repeat(1_000_000));
+        match check_json_limits(json.as_bytes(), &limits()) {
+            Err(ClientError::BadRequest(message)) => {
+                assert_eq!("json has more than 5 elements", message)
+            }
+            other => panic!("expected bad request, got {other:?}"),
+        }
+        // Object members count too
+        let json = br#"{"a": 1, "b": 2, "c": {"d": 3, "e": 4, "f": 5}}"#;
+        assert!(check_json_limits(json, &limits()).is_err());
+    }
+
+    #[test]
+    fn json_content_types_are_recognized() {
+        let headers = |content_type: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(CONTENT_TYPE, content_type.parse().unwrap());
+            headers
+        };
+        assert!(has_json_content_type(&headers("application/json")));
+        assert!(has_json_content_type(&headers(
+            "Application/JSON; charset=utf-8"
+        )));
+        assert!(has_json_content_type(&headers(
+            "application/merge-patch+json"
+        )));
+        assert!(!has_json_content_type(&headers("text/plain")));
+        assert!(!has_json_content_type(&headers("application/jsonp")));
+        assert!(!has_json_content_type(&HeaderMap::new()));
+    }
+
+    #[sqlx::test]
+    async fn authenticated_json_rejects_unauthenticated_before_parsing(db: DbPool) {
+        let result = authenticated_json(db, None, "not json").await;
+        assert!(matches!(
+            result,
+            Err(ApiError::ClientError(ClientError::Unauthorized))
+        ));
+    }
+
+    #[sqlx::test]
+    async fn authenticated_json_validates_body(db: DbPool) {
+        // user:user
+        let auth = Some("Basic dXNlcjp1c2Vy");
+        let result = authenticated_json(db.clone(), auth, r#"{"name": ""}"#).await;
+        assert!(matches!(
+            result,
+            Err(ApiError::ClientError(ClientError::UnprocessableEntity(_)))
+        ));
+
+        let result = authenticated_json(db, auth, r#"{"name": "item"}"#).await;
+        let AuthenticatedJson { user, value } = result.unwrap();
+        assert_eq!("user", user.username());
+        assert_eq!("item", value.inner().name);
+    }
+}
@@ -1,11 +1,15 @@
 //! For setting up logging.
 
-use opentelemetry::KeyValue;
+use opentelemetry::{trace::TraceError, KeyValue};
 use opentelemetry_otlp::WithExportConfig;
-use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::{trace::Tracer, Resource};
+use tracing::Subscriber;
 use tracing_appender::non_blocking::WorkerGuard;
 use tracing_error::ErrorLayer;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer};
+use tracing_subscriber::{
+    fmt::MakeWriter, layer::SubscriberExt, registry::LookupSpan, util::SubscriberInitExt,
+    EnvFilter, Layer,
+};
 
 use super::config::LoggingConfig;
 
@@ -16,17 +20,74 @@ pub struct LogGuard {
 }
 
 /// Initializes logging.
+///
+/// If the trace exporter cannot be set up, logs are still written to stdout.
 pub fn init_logging(config: &LoggingConfig) -> LogGuard {
+    let (non_blocking_stdout, stdout_guard) = tracing_appender::non_blocking(std::io::stdout());
+    let (subscriber, tracer_error) = subscriber(config, non_blocking_stdout);
+
+    let console_layer = if cfg!(debug_assertions) {
+        Some(console_subscriber::spawn())
+    } else {
+        None
+    };
+
+    subscriber.with(console_layer).init();
+
+    if let Some(e) = tracer_error {
+        tracing::warn!(
+            "Failed to set up trace exporter, continuing without it: {}",
+            e
+        );
+    }
+
+    LogGuard {
+        _guards: vec![stdout_guard],
+    }
+}
+
+/// Builds a subscriber that writes logs to `writer` and exports traces to Jaeger.
+///
+/// The exporter is left out, and the reason returned, if it cannot be set up.
+fn subscriber<W>(
+    config: &LoggingConfig,
+    writer: W,
+) -> (
+    impl Subscriber + for<'a> LookupSpan<'a> + Send + Sync,
+    Option<TraceError>,
+)
+where
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
     let log_level = &config.rust_log;
 
-    let (non_blocking_stdout, stdout_guard) = tracing_appender::non_blocking(std::io::stdout());
     let stdout = tracing_subscriber::fmt::layer()
-        .with_writer(non_blocking_stdout)
+        .with_writer(writer)
         .with_filter(EnvFilter::new(log_level.clone()));
 
+    let (opentelemetry, tracer_error) = match tracer(config) {
+        Ok(tracer) => {
+            let layer = tracing_opentelemetry::layer()
+                .with_tracer(tracer)
+                .with_filter(EnvFilter::new(log_level));
+            (Some(layer), None)
+        }
+        Err(e) => (None, Some(e)),
+    };
+
+    let subscriber = tracing_subscriber::registry()
+        .with(stdout)
+        .with(opentelemetry)
+        .with(ErrorLayer::default());
+
+    (subscriber, tracer_error)
+}
+
+/// Sets up a tracer that exports spans to Jaeger.
+fn tracer(config: &LoggingConfig) -> Result<Tracer, TraceError> {
     let app_name = env!("CARGO_PKG_NAME");
     let jaeger_endpoint = format!("{}:{}", config.jaeger_host, config.jaeger_port);
-    let opentelemetry_tracer = opentelemetry_otlp::new_pipeline()
+    opentelemetry_otlp::new_pipeline()
         .tracing()
         .with_exporter(
             opentelemetry_otlp::new_exporter()
@@ -38,26 +99,90 @@ pub fn init_logging(config: &LoggingConfig) -> LogGuard {
                 .with_resource(Resource::new(vec![KeyValue::new("service.name", app_name)])),
         )
         .install_batch(opentelemetry_sdk::runtime::Tokio)
-        .unwrap();
-    let opentelemetry = tracing_opentelemetry::layer()
-        .with_tracer(opentelemetry_tracer)
-        .with_filter(EnvFilter::new(log_level));
+}
 
-    let console_layer = if cfg!(debug_assertions) {
-        Some(console_subscriber::spawn())
-    } else {
-        None
-    };
+/// Captures log output in memory, for asserting on logs in tests.
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub(crate) struct CapturedLogs(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
 
-    let reg = tracing_subscriber::registry()
-        .with(stdout)
-        .with(opentelemetry)
-        .with(console_layer)
-        .with(ErrorLayer::default());
+#[cfg(test)]
+impl CapturedLogs {
+    /// Captures `INFO` and above on the current thread until the guard is dropped.
+    pub(crate) fn capture(&self) -> tracing::subscriber::DefaultGuard {
+        self.capture_at(tracing::Level::INFO)
+    }
+
+    /// Captures `level` and above on the current thread until the guard is dropped.
+    pub(crate) fn capture_at(&self, level: tracing::Level) -> tracing::subscriber::DefaultGuard {
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(self.clone())
+            .with_ansi(false)
+            .with_max_level(level)
+            .finish();
+        tracing::subscriber::set_default(subscriber)
+    }
 
-    reg.init();
+    /// Returns everything logged so far.
+    pub(crate) fn output(&self) -> String {
+        String::from_utf8_lossy(&self.0.lock().unwrap()).into_owned()
+    }
+}
 
-    LogGuard {
-        _guards: vec![stdout_guard],
+#[cfg(test)]
+impl std::io::Write for CapturedLogs {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl<'a> tracing_subscriber::fmt::MakeWriter<'a> for CapturedLogs {
+    type Writer = Self;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn logging_config(jaeger_host: &str) -> LoggingConfig {
+        let mut config = crate::infra::config::load_config().unwrap().logging;
+        config.rust_log = "info".to_string();
+        config.jaeger_host = jaeger_host.to_string();
+        config
+    }
+
+    #[tokio::test]
+    async fn invalid_jaeger_endpoint_falls_back_to_local_logs() {
+        let logs = CapturedLogs::default();
+        let (subscriber, tracer_error) = subscriber(&logging_config("not a host"), logs.clone());
+        assert!(tracer_error.is_some());
+
+        let _guard = tracing::subscriber::set_default(subscriber);
+        tracing::info!("still logging");
+        assert!(logs.output().contains("still logging"));
+    }
+
+    #[tokio::test]
+    async fn unreachable_jaeger_host_still_logs() {
+        let logs = CapturedLogs::default();
+        // Nothing listens on port 9, the exporter only fails when sending
+        let mut config = logging_config("http://127.0.0.1");
+        config.jaeger_port = 9;
+        let (subscriber, tracer_error) = subscriber(&config, logs.clone());
+        assert!(tracer_error.is_none());
+
+        let _guard = tracing::subscriber::set_default(subscriber);
+        tracing::info_span!("span").in_scope(|| tracing::info!("still logging"));
+        assert!(logs.output().contains("still logging"));
     }
 }
@@ -0,0 +1,146 @@
+//! Periodic snapshots of request and pool metrics, for deployments that only collect logs.
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use http::StatusCode;
+
+use super::{database::DbPool, shutdown::InFlight};
+
+/// The tracing target used for metrics snapshot lines.
+pub const METRICS_TARGET: &str = "metrics";
+
+/// Counts handled requests, and how many of them failed with a server error.
+#[derive(Clone, Debug, Default)]
+pub struct RequestCounts {
+    requests: Arc<AtomicU64>,
+    errors: Arc<AtomicU64>,
+}
+
+impl RequestCounts {
+    /// Records a handled request.
+    pub(crate) fn record(&self, status: StatusCode) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        if status.is_server_error() {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// The total number of handled requests.
+    pub fn requests(&self) -> u64 {
+        self.requests.load(Ordering::Relaxed)
+    }
+
+    /// The total number of requests that failed with a server error.
+    pub fn errors(&self) -> u64 {
+        self.errors.load(Ordering::Relaxed)
+    }
+}
+
+/// Logs a snapshot of the metrics under [`METRICS_TARGET`] every `interval`.
+///
+/// Rates are per second since the previous snapshot. Runs forever, so it
+/// should be stopped on shutdown. Does nothing if `interval` is zero.
+pub async fn log_snapshots(
+    interval: Duration,
+    counts: RequestCounts,
+    in_flight: InFlight,
+    db: DbPool,
+) {
+    if interval.is_zero() {
+        return;
+    }
+    let mut ticker = tokio::time::interval(interval);
+    // The first tick completes immediately
+    ticker.tick().await;
+    let (mut requests, mut errors) = (counts.requests(), counts.errors());
+    loop {
+        ticker.tick().await;
+        let (new_requests, new_errors) = (counts.requests(), counts.errors());
+        let per_second = |n: u64| n as f64 / interval.as_secs_f64();
+        tracing::info!(
+            target: METRICS_TARGET,
+            request_rate = %format!("{:.2}", per_second(new_requests - requests)),
+            error_rate = %format!("{:.2}", per_second(new_errors - errors)),
+            in_flight = in_flight.count(),
+            pool_size = db.size(),
+            pool_idle = db.num_idle(),
+            pool_max = db.options().get_max_connections(),
+            "Metrics snapshot"
+        );
+        (requests, errors) = (new_requests, new_errors);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infra::logging::CapturedLogs;
+
+    #[test]
+    fn server_errors_are_counted_as_errors() {
+        let counts = RequestCounts::default();
+        counts.record(StatusCode::OK);
+        counts.record(StatusCode::NOT_FOUND);
+        counts.record(StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(3, counts.requests());
+        assert_eq!(1, counts.errors());
+    }
+
+    #[sqlx::test]
+    async fn snapshots_are_logged_periodically(db: DbPool) {
+        let logs = CapturedLogs::default();
+        let _guard = logs.capture();
+        let counts = RequestCounts::default();
+        let interval = Duration::from_millis(50);
+        let snapshots = log_snapshots(interval, counts.clone(), InFlight::default(), db);
+        let traffic = async {
+            // Within the first interval
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            for _ in 0..5 {
+                counts.record(StatusCode::OK);
+            }
+            counts.record(StatusCode::BAD_GATEWAY);
+            std::future::pending::<()>().await
+        };
+        let _ = tokio::time::timeout(Duration::from_millis(120), async {
+            tokio::join!(snapshots, traffic)
+        })
+        .await;
+
+        let output = logs.output();
+        let line = output
+            .lines()
+            .find(|l| l.contains(METRICS_TARGET))
+            .expect("no metrics snapshot");
+        for field in [
+            "request_rate=120.00",
+            "error_rate=20.00",
+            "in_flight=0",
+            "pool_size=",
+            "pool_idle=",
+            "pool_max=",
+        ] {
+            assert!(line.contains(field), "{field} missing from {line}");
+        }
+    }
+
+    #[tokio::test]
+    async fn zero_interval_disables_snapshots() {
+        let db = DbPool::connect_lazy("postgres://localhost/unused").unwrap();
+        let snapshots = log_snapshots(
+            Duration::ZERO,
+            RequestCounts::default(),
+            InFlight::default(),
+            db,
+        );
+        tokio::time::timeout(Duration::from_secs(1), snapshots)
+            .await
+            .expect("snapshots should return immediately");
+    }
+}
@@ -1,25 +1,45 @@
 //! Middleware for modifying requests and responses.
 
-use std::time::Duration;
+use std::{
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    task::{Context, Poll},
+    time::Instant,
+};
 
 use crate::{
     api::request::request_repository::{self, NewRequest},
     infra::{
+        config::{Config, RequestStoreConfig},
         database::DbPool,
-        error::{ApiError, ClientError},
+        error::{ApiError, ClientError, PoolExhausted},
+        metrics::RequestCounts,
+        shutdown::InFlight,
     },
 };
-use axum::{body::Body, extract::State, middleware::Next, response::IntoResponse};
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, State},
+    middleware::Next,
+    response::IntoResponse,
+};
 use bytes::Bytes;
-use http::{Request, Response};
+use http::{header::CONTENT_TYPE, HeaderValue, Request, Response};
 use http_body_util::BodyExt;
-use hyper::body::Body as _;
+use hyper::body::{Body as HttpBody, Frame, SizeHint};
+use ipnet::IpNet;
+use tokio_util::task::TaskTracker;
 use tower_http::trace::MakeSpan;
 use tracing::Instrument;
 
 use super::error::ApiResult;
 
 static X_REQUEST_ID: &str = "x-request-id";
+static DEPRECATION: &str = "deprecation";
+static SUNSET: &str = "sunset";
+
+/// The tracing target used for access log lines.
+pub const ACCESS_LOG_TARGET: &str = "access_log";
 
 #[derive(Clone)]
 pub(crate) struct MakeRequestIdSpan;
@@ -38,31 +58,254 @@ impl<B> MakeSpan<B> for MakeRequestIdSpan {
             method = %request.method(),
             uri = %request.uri(),
             version = ?request.version(),
+            user_id = tracing::field::Empty,
+            user_role = tracing::field::Empty,
         )
     }
 }
 
+/// The longest inbound request id that is kept.
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+/// Leaves at most one `x-request-id` on the request, so that every layer sees the same id.
+///
+/// The first valid inbound id is kept. If there is none, the header is removed
+/// and a new id is generated by the layer that sets request ids.
+pub(crate) async fn canonical_request_id(mut req: Request<Body>, next: Next) -> impl IntoResponse {
+    let headers = req.headers_mut();
+    let canonical = headers.get_all(X_REQUEST_ID).iter().find(|id| {
+        id.to_str()
+            .is_ok_and(|id| !id.trim().is_empty() && id.len() <= MAX_REQUEST_ID_LENGTH)
+    });
+    if let Some(id) = canonical.cloned() {
+        headers.insert(X_REQUEST_ID, id);
+    } else {
+        headers.remove(X_REQUEST_ID);
+    }
+    next.run(req).await
+}
+
+/// Count the request as in flight until a response has been produced.
+pub(crate) async fn track_in_flight(
+    State(in_flight): State<InFlight>,
+    req: Request<Body>,
+    next: Next,
+) -> impl IntoResponse {
+    let _guard = in_flight.start();
+    next.run(req).await
+}
+
+/// Record the status of every response in the request counts.
+pub(crate) async fn count_requests(
+    State(counts): State<RequestCounts>,
+    req: Request<Body>,
+    next: Next,
+) -> impl IntoResponse {
+    let res = next.run(req).await;
+    counts.record(res.status());
+    res
+}
+
+/// A response body that counts the bytes passing through it.
+///
+/// The total is handed to `on_complete` once the body has been sent,
+/// or when it is dropped early, e.g. because the client went away.
+struct CountingBody {
+    inner: Body,
+    bytes: u64,
+    on_complete: Option<Box<dyn FnOnce(u64) + Send>>,
+}
+
+impl CountingBody {
+    fn new(inner: Body, on_complete: impl FnOnce(u64) + Send + 'static) -> Self {
+        Self {
+            inner,
+            bytes: 0,
+            on_complete: Some(Box::new(on_complete)),
+        }
+    }
+
+    fn complete(&mut self) {
+        if let Some(on_complete) = self.on_complete.take() {
+            on_complete(self.bytes);
+        }
+    }
+}
+
+impl HttpBody for CountingBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let poll = Pin::new(&mut self.inner).poll_frame(cx);
+        match &poll {
+            Poll::Ready(Some(Ok(frame))) => {
+                if let Some(data) = frame.data_ref() {
+                    self.bytes += data.len() as u64;
+                }
+            }
+            Poll::Ready(None) => self.complete(),
+            _ => {}
+        }
+        poll
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl Drop for CountingBody {
+    fn drop(&mut self) {
+        self.complete();
+    }
+}
+
+/// Emit a single access log line per request.
+///
+/// The line is logged at `INFO` under [`ACCESS_LOG_TARGET`],
+/// so it can be filtered independently of the application logs.
+/// It is written once the response body has been sent, so that
+/// the size of streamed responses can be included.
+pub(crate) async fn access_log(
+    State(config): State<Config>,
+    req: Request<Body>,
+    next: Next,
+) -> impl IntoResponse {
+    let start = Instant::now();
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+    let version = req.version();
+    let request_id = header_value(req.headers(), X_REQUEST_ID);
+    let client_ip = request_client_ip(
+        req.extensions(),
+        req.headers(),
+        &config.server.trusted_proxies,
+    )
+    .map(|ip| ip.to_string())
+    .unwrap_or_else(|| "-".to_string());
+
+    let res = next.run(req).await;
+
+    let status = res.status().as_u16();
+    let (parts, body) = res.into_parts();
+    let body = CountingBody::new(body, move |bytes| {
+        let duration_ms = start.elapsed().as_millis();
+        tracing::info!(
+            target: ACCESS_LOG_TARGET,
+            "{client_ip} \"{method} {path} {version:?}\" {status} {bytes} {duration_ms}ms {request_id}"
+        );
+    });
+    Response::from_parts(parts, Body::new(body))
+}
+
+/// Read a header as a string, or `-` if it is missing or invalid.
+fn header_value(headers: &http::HeaderMap, name: &str) -> String {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("-")
+        .to_string()
+}
+
+/// Determine the client ip of a request, see [`client_ip`].
+pub(crate) fn request_client_ip(
+    extensions: &http::Extensions,
+    headers: &http::HeaderMap,
+    trusted_proxies: &[IpNet],
+) -> Option<IpAddr> {
+    let peer = extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    let forwarded_for = headers.get("x-forwarded-for").and_then(|v| v.to_str().ok());
+    client_ip(peer, forwarded_for, trusted_proxies)
+}
+
+/// Determine the client ip from the socket peer and the `x-forwarded-for` header.
+///
+/// The header can be forged by clients, so its entries are only used while
+/// they were added by a trusted proxy. It is walked from the right, and the first
+/// untrusted hop is the client. Without a trusted peer the header is ignored.
+fn client_ip(
+    peer: Option<IpAddr>,
+    forwarded_for: Option<&str>,
+    trusted_proxies: &[IpNet],
+) -> Option<IpAddr> {
+    let is_trusted = |ip: &IpAddr| trusted_proxies.iter().any(|net| net.contains(ip));
+    let peer = peer?;
+    if !is_trusted(&peer) {
+        return Some(peer);
+    }
+    let mut client = peer;
+    for hop in forwarded_for.unwrap_or_default().rsplit(',') {
+        let Ok(hop) = hop.trim().parse::<IpAddr>() else {
+            break;
+        };
+        client = hop;
+        if !is_trusted(&hop) {
+            break;
+        }
+    }
+    Some(client)
+}
+
+/// Mark responses from routes in `server.deprecated_routes` with the
+/// `Deprecation` header, and the `Sunset` header if they have a sunset date.
+pub(crate) async fn deprecation_headers(
+    State(config): State<Config>,
+    req: Request<Body>,
+    next: Next,
+) -> impl IntoResponse {
+    let route = config.server.deprecated_route(req.uri().path()).cloned();
+    let mut res = next.run(req).await;
+    if let Some(route) = route {
+        let headers = res.headers_mut();
+        headers.insert(DEPRECATION, HeaderValue::from_static("true"));
+        if let Some(sunset) = route.sunset {
+            let date = sunset.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+            headers.insert(
+                SUNSET,
+                HeaderValue::from_str(&date).expect("dates are ascii"),
+            );
+        }
+    }
+    res
+}
+
 /// The maximum size of the request body to log.
 const MAX_BODY_SIZE: u64 = 8192;
 
 /// Print and log the request and response.
+///
+/// Bodies of paths in `server.unlogged_bodies` are not read, only the metadata is logged.
 pub(crate) async fn log_request_response(
     State(db): State<DbPool>,
+    State(config): State<Config>,
+    State(request_log): State<TaskTracker>,
     req: Request<Body>,
     next: Next,
 ) -> Result<impl IntoResponse, ApiError> {
+    let log_bodies = !config.server.is_body_unlogged(req.uri().path());
+
     // Print request
     let (parts, body) = req.into_parts();
     let req;
     let log_req = match body.size_hint().upper() {
-        Some(n) => n <= MAX_BODY_SIZE,
+        Some(n) => log_bodies && n <= MAX_BODY_SIZE,
         _ => false,
     };
     let req_string = if log_req {
         let body_bytes = buffer_and_print("Request", body).await?;
-        req = Request::from_parts(parts, Body::from(body_bytes.clone()));
-        let body_vec = body_bytes.to_vec();
-        String::from_utf8(body_vec).ok()
+        let description = describe_body(&body_bytes, parts.headers.get(CONTENT_TYPE));
+        req = Request::from_parts(parts, Body::from(body_bytes));
+        Some(description)
     } else {
         req = Request::from_parts(parts, body);
         None
@@ -81,18 +324,29 @@ pub(crate) async fn log_request_response(
     // Perform request
     let res = next.run(req).await;
 
+    if res.extensions().get::<PoolExhausted>().is_some() {
+        tracing::warn!(
+            size = db.size(),
+            idle = db.num_idle(),
+            max = db.options().get_max_connections(),
+            "Database pool exhausted while handling {} {}",
+            method,
+            uri
+        );
+    }
+
     // Print response
     let (parts, body) = res.into_parts();
     let res;
     let log_res = match body.size_hint().upper() {
-        Some(n) => n <= MAX_BODY_SIZE,
+        Some(n) => log_bodies && n <= MAX_BODY_SIZE,
         _ => false,
     };
     let res_string = if log_res {
         let body_bytes = buffer_and_print("Response", body).await?;
-        let body_vec = body_bytes.to_vec();
-        res = Response::from_parts(parts, Body::from(body_bytes.clone())).into_response();
-        String::from_utf8(body_vec).ok()
+        let description = describe_body(&body_bytes, parts.headers.get(CONTENT_TYPE));
+        res = Response::from_parts(parts, Body::from(body_bytes)).into_response();
+        Some(description)
     } else {
         res = Response::from_parts(parts, body);
         None
@@ -100,37 +354,87 @@ pub(crate) async fn log_request_response(
     let status = res.status().as_u16() as i32;
 
     let span = tracing::info_span!("async log");
-    // Log request asynchronously
-    tokio::spawn(
-        async move {
-            let new_req = NewRequest {
-                host,
-                method,
-                uri,
-                request_body: req_string,
-                response_body: res_string,
-                status,
-            };
-            // Store request (with retries)
-            let mut tries = 0;
-            while tries < 3 {
-                match store_request(db.clone(), &new_req).await {
-                    Err(e) => {
-                        tracing::error!("Failed to store request (attempt {}): {}", tries + 1, e);
-                        tries += 1;
-                        tokio::time::sleep(Duration::from_secs((tries + 1) * 5)).await;
-                    }
-                    Ok(req) => {
-                        tracing::info!("Stored request with id {}", req.id);
-                        break;
+    // Log request asynchronously once the response size is known
+    let retries = config.logging.request_store;
+    let store = move |response_size: u64| {
+        let new_req = NewRequest {
+            host,
+            method,
+            uri,
+            request_body: req_string,
+            response_body: res_string,
+            status,
+            response_size: i64::try_from(response_size).ok(),
+        };
+        // Tracked so that shutdown can wait for it
+        request_log.spawn(store_with_retries(db, new_req, retries).instrument(span));
+    };
+
+    let (parts, body) = res.into_parts();
+    Ok(Response::from_parts(
+        parts,
+        Body::new(CountingBody::new(body, store)),
+    ))
+}
+
+/// Describes a body for the request log.
+///
+/// Text is logged as is, while binary content is replaced by a placeholder
+/// with its size and content type, so the log still shows that there was a body.
+fn describe_body(body: &[u8], content_type: Option<&HeaderValue>) -> String {
+    let content_type = content_type.and_then(|v| v.to_str().ok());
+    match std::str::from_utf8(body) {
+        Ok(text) if content_type.is_none_or(is_text_content_type) => text.to_string(),
+        _ => format!(
+            "<binary {} bytes, content-type {}>",
+            body.len(),
+            content_type.unwrap_or("unknown")
+        ),
+    }
+}
+
+/// Whether a content type is textual, e.g. `text/plain` or `application/json`.
+fn is_text_content_type(content_type: &str) -> bool {
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    let Some((kind, subtype)) = essence.split_once('/') else {
+        return false;
+    };
+    kind == "text"
+        || matches!(
+            subtype,
+            "json" | "xml" | "javascript" | "x-www-form-urlencoded" | "x-ndjson"
+        )
+        || subtype.ends_with("+json")
+        || subtype.ends_with("+xml")
+}
+
+/// Stores a request, retrying failed attempts until they or the time run out.
+async fn store_with_retries(db: DbPool, new_req: NewRequest, config: RequestStoreConfig) {
+    let retries = async {
+        for attempt in 1..=config.attempts {
+            match store_request(db.clone(), &new_req).await {
+                Ok(req) => {
+                    tracing::info!("Stored request with id {}", req.id);
+                    return;
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to store request (attempt {}): {}", attempt, e);
+                    if attempt < config.attempts {
+                        tokio::time::sleep(config.backoff * attempt).await;
                     }
                 }
             }
         }
-        .instrument(span),
-    );
-
-    Ok(res)
+        tracing::error!("Gave up storing request after {} attempts", config.attempts);
+    };
+    if tokio::time::timeout(config.timeout, retries).await.is_err() {
+        tracing::error!("Gave up storing request after {:?}", config.timeout);
+    }
 }
 
 /// Store a request in the database.
@@ -157,3 +461,326 @@ async fn buffer_and_print(direction: &str, body: Body) -> Result<Bytes, ApiError
 
     Ok(body)
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::{routing::get, Router};
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::infra::{config::DeprecatedRoute, logging::CapturedLogs};
+
+    #[tokio::test]
+    async fn deprecated_routes_announce_their_sunset() {
+        let mut config = crate::infra::config::load_config().unwrap();
+        config.server.deprecated_routes = vec![DeprecatedRoute {
+            path: "/old/{id}".to_string(),
+            sunset: Some("2027-01-01T00:00:00Z".parse().unwrap()),
+        }];
+        let app = Router::new()
+            .route("/old/:id", get(|| async { "old" }))
+            .route("/new/:id", get(|| async { "new" }))
+            .layer(axum::middleware::from_fn_with_state(
+                config,
+                deprecation_headers,
+            ));
+
+        let req = Request::get("/old/1").body(Body::empty()).unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!("true", res.headers()[DEPRECATION]);
+        assert_eq!("Fri, 01 Jan 2027 00:00:00 GMT", res.headers()[SUNSET]);
+
+        let req = Request::get("/new/1").body(Body::empty()).unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert!(res.headers().get(DEPRECATION).is_none());
+        assert!(res.headers().get(SUNSET).is_none());
+    }
+
+    #[tokio::test]
+    async fn access_log_contains_request_fields() {
+        let logs = CapturedLogs::default();
+        let _guard = logs.capture();
+
+        let mut config = crate::infra::config::load_config().unwrap();
+        config.server.trusted_proxies = vec!["10.0.0.0/8".parse().unwrap()];
+        let app = Router::new()
+            .route("/hello", get(|| async { "hello" }))
+            .layer(axum::middleware::from_fn_with_state(config, access_log));
+        let mut req = Request::get("/hello?name=World")
+            .header(X_REQUEST_ID, "abc-123")
+            .header("x-forwarded-for", "192.0.2.1, 10.0.0.2")
+            .body(Body::empty())
+            .unwrap();
+        let peer: SocketAddr = "10.0.0.3:1234".parse().unwrap();
+        req.extensions_mut().insert(ConnectInfo(peer));
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(http::StatusCode::OK, res.status());
+        res.into_body().collect().await.unwrap();
+
+        let output = logs.output();
+        let line = output
+            .lines()
+            .find(|l| l.contains(ACCESS_LOG_TARGET))
+            .expect("no access log line");
+        assert!(line.contains("INFO"), "{line}");
+        assert!(
+            line.contains("192.0.2.1 \"GET /hello HTTP/1.1\" 200 5 "),
+            "{line}"
+        );
+        assert!(line.contains("ms abc-123"), "{line}");
+    }
+
+    #[tokio::test]
+    async fn access_log_counts_streamed_response_bytes() {
+        let logs = CapturedLogs::default();
+        let _guard = logs.capture();
+
+        let config = crate::infra::config::load_config().unwrap();
+        let app = Router::new()
+            .route(
+                "/stream",
+                get(|| async {
+                    let chunks = ["hello", ", ", "world"].map(Ok::<_, std::io::Error>);
+                    Body::from_stream(futures::stream::iter(chunks))
+                }),
+            )
+            .layer(axum::middleware::from_fn_with_state(config, access_log));
+        let req = Request::get("/stream").body(Body::empty()).unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(None, res.body().size_hint().exact());
+        assert!(
+            !logs.output().contains(ACCESS_LOG_TARGET),
+            "logged before the body was sent"
+        );
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!("hello, world", body);
+
+        let output = logs.output();
+        let line = output
+            .lines()
+            .find(|l| l.contains(ACCESS_LOG_TARGET))
+            .expect("no access log line");
+        assert!(line.contains("\"GET /stream HTTP/1.1\" 200 12 "), "{line}");
+    }
+
+    #[sqlx::test]
+    async fn unlogged_paths_store_metadata_only(db: DbPool) {
+        let mut config = crate::infra::config::load_config().unwrap();
+        config.server.unlogged_bodies = vec!["/secret".to_string()];
+        let state = crate::infra::state::AppState::new(db.clone(), config);
+        let app = Router::new()
+            .route(
+                "/secret",
+                axum::routing::post(|body: String| async { body }),
+            )
+            .route(
+                "/public",
+                axum::routing::post(|body: String| async { body }),
+            )
+            .layer(axum::middleware::from_fn_with_state(
+                state,
+                log_request_response,
+            ));
+        for path in ["/secret", "/public"] {
+            let req = Request::post(path).body(Body::from("psst")).unwrap();
+            let res = app.clone().oneshot(req).await.unwrap();
+            res.into_body().collect().await.unwrap();
+        }
+
+        // Requests are stored in the background
+        let mut rows = Vec::new();
+        for _ in 0..50 {
+            rows = sqlx::query_as::<_, (String, Option<String>, Option<String>)>(
+                "SELECT uri, request_body, response_body FROM requests ORDER BY uri",
+            )
+            .fetch_all(&db)
+            .await
+            .unwrap();
+            if rows.len() == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let psst = Some("psst".to_string());
+        assert_eq!(
+            vec![
+                ("/public".to_string(), psst.clone(), psst),
+                ("/secret".to_string(), None, None),
+            ],
+            rows
+        );
+    }
+
+    #[sqlx::test]
+    async fn binary_bodies_are_stored_as_placeholders(db: DbPool) {
+        let state = crate::infra::state::AppState::new(
+            db.clone(),
+            crate::infra::config::load_config().unwrap(),
+        );
+        let app = Router::new()
+            .route(
+                "/upload",
+                axum::routing::post(|| async {
+                    ([(CONTENT_TYPE, "application/octet-stream")], "stored")
+                }),
+            )
+            .layer(axum::middleware::from_fn_with_state(
+                state,
+                log_request_response,
+            ));
+        let req = Request::post("/upload")
+            .header(CONTENT_TYPE, "image/png")
+            .body(Body::from(vec![0x89, b'P', b'N', b'G', 0xff, 0x00]))
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        res.into_body().collect().await.unwrap();
+
+        // Requests are stored in the background
+        let mut row = None;
+        for _ in 0..50 {
+            row = sqlx::query_as::<_, (Option<String>, Option<String>)>(
+                "SELECT request_body, response_body FROM requests",
+            )
+            .fetch_optional(&db)
+            .await
+            .unwrap();
+            if row.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let (request_body, response_body) = row.expect("request was not stored");
+        assert_eq!(
+            Some("<binary 6 bytes, content-type image/png>".to_string()),
+            request_body
+        );
+        assert_eq!(
+            Some("<binary 6 bytes, content-type application/octet-stream>".to_string()),
+            response_body
+        );
+    }
+
+    /// A request to store in a database that cannot be reached.
+    fn unstorable_request() -> (DbPool, NewRequest) {
+        let db = sqlx::postgres::PgPoolOptions::new()
+            .acquire_timeout(Duration::from_millis(50))
+            .connect_lazy("postgres://localhost:1/unreachable")
+            .unwrap();
+        let new_req = NewRequest {
+            host: "self".to_string(),
+            method: "GET".to_string(),
+            uri: "/".to_string(),
+            request_body: None,
+            response_body: None,
+            status: 200,
+            response_size: None,
+        };
+        (db, new_req)
+    }
+
+    #[tokio::test]
+    async fn failing_store_gives_up_after_attempts() {
+        let logs = CapturedLogs::default();
+        let _guard = logs.capture();
+        let (db, new_req) = unstorable_request();
+        let config = RequestStoreConfig {
+            attempts: 3,
+            backoff: Duration::from_millis(10),
+            timeout: Duration::from_secs(5),
+        };
+        store_with_retries(db, new_req, config).await;
+
+        let output = logs.output();
+        assert!(output.contains("(attempt 3)"), "{output}");
+        assert!(
+            output.contains("Gave up storing request after 3 attempts"),
+            "{output}"
+        );
+    }
+
+    #[tokio::test]
+    async fn failing_store_gives_up_within_timeout() {
+        let logs = CapturedLogs::default();
+        let _guard = logs.capture();
+        let (db, new_req) = unstorable_request();
+        let config = RequestStoreConfig {
+            attempts: u32::MAX,
+            backoff: Duration::from_millis(10),
+            timeout: Duration::from_millis(300),
+        };
+        let start = Instant::now();
+        store_with_retries(db, new_req, config).await;
+
+        assert!(
+            start.elapsed() < Duration::from_secs(1),
+            "{:?}",
+            start.elapsed()
+        );
+        let output = logs.output();
+        assert!(
+            output.contains("Gave up storing request after 300ms"),
+            "{output}"
+        );
+    }
+
+    #[test]
+    fn text_bodies_are_described_as_is() {
+        let json = HeaderValue::from_static("application/json; charset=utf-8");
+        assert_eq!("{}", describe_body(b"{}", Some(&json)));
+        assert_eq!("plain", describe_body(b"plain", None));
+        let problem = HeaderValue::from_static("application/problem+json");
+        assert_eq!("{}", describe_body(b"{}", Some(&problem)));
+        assert_eq!(
+            "<binary 2 bytes, content-type unknown>",
+            describe_body(&[0xff, 0xfe], None)
+        );
+    }
+
+    #[test]
+    fn unlogged_bodies_cover_subpaths_only() {
+        let mut config = crate::infra::config::load_config().unwrap();
+        config.server.unlogged_bodies = vec!["/api/admin/impersonate".to_string()];
+        assert!(config.server.is_body_unlogged("/api/admin/impersonate"));
+        assert!(config.server.is_body_unlogged("/api/admin/impersonate/3"));
+        assert!(!config.server.is_body_unlogged("/api/admin/impersonated"));
+        assert!(!config.server.is_body_unlogged("/api/items"));
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn forged_forwarded_for_from_untrusted_peer_is_ignored() {
+        let trusted = ["10.0.0.0/8".parse().unwrap()];
+        let client = client_ip(Some(ip("203.0.113.7")), Some("1.2.3.4"), &trusted);
+        assert_eq!(Some(ip("203.0.113.7")), client);
+    }
+
+    #[test]
+    fn forwarded_for_is_walked_until_first_untrusted_hop() {
+        let trusted = ["10.0.0.0/8".parse().unwrap()];
+        // The client forged the leftmost entry, the proxy appended the real one
+        let client = client_ip(
+            Some(ip("10.0.0.1")),
+            Some("1.2.3.4, 203.0.113.7, 10.0.0.2"),
+            &trusted,
+        );
+        assert_eq!(Some(ip("203.0.113.7")), client);
+    }
+
+    #[test]
+    fn only_trusted_hops_gives_leftmost_entry() {
+        let trusted = ["10.0.0.0/8".parse().unwrap()];
+        let client = client_ip(Some(ip("10.0.0.1")), Some("10.0.0.3, 10.0.0.2"), &trusted);
+        assert_eq!(Some(ip("10.0.0.3")), client);
+    }
+
+    #[test]
+    fn trusted_peer_without_forwarded_for_gives_peer() {
+        let trusted = ["127.0.0.1/32".parse().unwrap()];
+        let client = client_ip(Some(ip("127.0.0.1")), None, &trusted);
+        assert_eq!(Some(ip("127.0.0.1")), client);
+    }
+}
@@ -7,10 +7,16 @@ pub mod database;
 pub mod error;
 pub mod extract;
 pub mod logging;
+pub mod metrics;
 pub mod middleware;
 pub mod openapi;
 pub mod pagination;
+pub mod rate_limit;
 pub mod security;
+pub mod selfcheck;
 pub mod shutdown;
+pub mod startup;
 pub mod state;
+pub mod timestamped;
 pub mod validation;
+pub mod version;
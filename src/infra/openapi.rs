@@ -2,9 +2,18 @@
 
 use crate::api::item::item_repository;
 use crate::api::url::url_repository;
-use crate::api::{hello::hello_api, info::info_api, item::item_api, url::url_api, user::user_api};
+use crate::api::user::user_repository;
+use crate::api::webhook::{webhook_api, webhook_repository};
+use crate::api::{
+    hello::hello_api, info::info_api, item::item_api, request::request_api, url::url_api,
+    user::user_api,
+};
+use crate::infra::config::ServerConfig;
 use utoipa::{
-    openapi::security::{Http, HttpAuthScheme, SecurityScheme},
+    openapi::{
+        security::{Http, HttpAuthScheme, SecurityScheme},
+        Deprecated,
+    },
     Modify, OpenApi,
 };
 
@@ -13,35 +22,108 @@ use utoipa::{
 #[openapi(
     paths(
         info_api::info,
+        info_api::get_version,
+        info_api::ready,
+        info_api::migrations,
+        info_api::debug_config,
+        crate::api::stats::stats_api::stats,
         hello_api::hello,
         item_api::create_item,
+        item_api::get_item,
         item_api::list_items,
+        item_api::count_items,
+        item_api::search_items,
+        item_api::assign_orphans,
         item_api::update_item,
         item_api::delete_item,
         item_api::stream_items,
+        item_api::upload_attachment,
+        item_api::download_attachment,
+        user_api::me,
+        user_api::update_me,
+        user_api::delete_me,
         user_api::user,
         user_api::admin,
+        user_api::custom,
+        user_api::change_password,
+        user_api::update_role,
+        user_api::impersonate,
+        user_api::invalidate_cache,
+        request_api::export_requests,
         url_api::create_url,
+        url_api::import_urls,
         url_api::visit_url,
+        url_api::disable_url,
+        url_api::enable_url,
+        url_api::preview_url,
+        url_api::update_url,
         url_api::delete_url,
         url_api::list_urls,
+        webhook_api::create_webhook,
+        webhook_api::list_webhooks,
+        webhook_api::update_webhook,
+        webhook_api::delete_webhook,
     ),
     components(
         schemas(
             info_api::AppInfo,
+            info_api::Version,
+            info_api::Readiness,
+            info_api::DependencyStatus,
+            info_api::MigrationStatus,
+            crate::api::stats::stats_repository::Stats,
+            crate::api::info::migration_repository::AppliedMigration,
             hello_api::Greeting,
+            user_api::Profile,
+            user_api::UpdateProfile,
+            user_api::NewPassword,
+            user_api::NewRole,
+            user_repository::Impersonation,
             item_repository::NewItem,
             item_repository::Item,
+            item_api::ItemCount,
+            item_api::OrphansAssigned,
             url_repository::NewShortUrl,
+            url_repository::UpdateShortUrl,
             url_repository::ShortUrl,
+            url_api::ImportResult,
+            url_api::UrlPreview,
+            webhook_repository::NewWebhook,
+            webhook_repository::Webhook,
             crate::infra::error::ErrorBody
         )
     ),
+    tags(
+        (name = "hello", description = "Greetings"),
+        (name = "info", description = "Information about the running application"),
+        (name = "stats", description = "Usage statistics"),
+        (name = "users", description = "Authentication and user management"),
+        (name = "items", description = "Items and their attachments"),
+        (name = "urls", description = "Short urls"),
+        (name = "webhooks", description = "Notifying other systems about item changes"),
+        (name = "requests", description = "The log of handled requests"),
+    ),
     modifiers(&SecurityAddon)
 )]
 #[derive(Clone, Copy, Debug)]
 pub struct ApiDoc;
 
+/// The OpenAPI spec, with the operations in `server.deprecated_routes` marked as deprecated.
+pub fn api_doc(config: &ServerConfig) -> utoipa::openapi::OpenApi {
+    let mut openapi = ApiDoc::openapi();
+    for route in &config.deprecated_routes {
+        match openapi.paths.paths.get_mut(&route.path) {
+            Some(path) => {
+                for operation in path.operations.values_mut() {
+                    operation.deprecated = Some(Deprecated::True);
+                }
+            }
+            None => tracing::warn!("Deprecated route {} is not documented", route.path),
+        }
+    }
+    openapi
+}
+
 /// Security settings
 struct SecurityAddon;
 
@@ -55,3 +137,81 @@ impl Modify for SecurityAddon {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn creating_items_requires_basic_auth() {
+        let openapi = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let scheme = &openapi["components"]["securitySchemes"]["basic"];
+        assert_eq!("http", scheme["type"], "{scheme}");
+        assert_eq!("basic", scheme["scheme"], "{scheme}");
+        let security = &openapi["paths"]["/api/items"]["post"]["security"];
+        assert_eq!(serde_json::json!([{ "basic": [] }]), *security);
+    }
+
+    #[test]
+    fn operations_that_can_be_unauthorized_require_auth() {
+        let openapi = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        for (path, operations) in openapi["paths"].as_object().unwrap() {
+            for (method, operation) in operations.as_object().unwrap() {
+                if operation["responses"].get("401").is_some() {
+                    assert!(
+                        operation["security"].is_array(),
+                        "{method} {path} has no security requirement"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn item_stream_is_documented_as_ndjson() {
+        let openapi = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let content = &openapi["paths"]["/api/items2"]["get"]["responses"]["200"]["content"];
+        let schema = &content["application/x-ndjson"]["schema"]["$ref"];
+        assert_eq!("#/components/schemas/Item", schema, "{content}");
+    }
+
+    #[test]
+    fn webhook_secret_is_write_only() {
+        let openapi = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let schemas = &openapi["components"]["schemas"];
+        assert!(schemas["NewWebhook"]["properties"]["secret"].is_object());
+        assert!(schemas["Webhook"]["properties"]["secret"].is_null());
+    }
+
+    #[test]
+    fn operations_are_tagged() {
+        let openapi = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let tags: Vec<&str> = openapi["tags"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|tag| tag["name"].as_str().unwrap())
+            .collect();
+        let expected = |path: &str| match path {
+            "/api/hello" => "hello",
+            "/api/info"
+            | "/api/version"
+            | "/api/ready"
+            | "/api/admin/migrations"
+            | "/api/debug/config" => "info",
+            "/api/stats" => "stats",
+            p if p.starts_with("/api/items") || p.starts_with("/api/admin/items") => "items",
+            p if p.starts_with("/api/urls") => "urls",
+            p if p.starts_with("/api/admin/webhooks") => "webhooks",
+            p if p.starts_with("/api/requests") => "requests",
+            _ => "users",
+        };
+        for (path, operations) in openapi["paths"].as_object().unwrap() {
+            for (method, operation) in operations.as_object().unwrap() {
+                let tag = operation["tags"][0].as_str();
+                assert_eq!(Some(expected(path)), tag, "{method} {path}");
+                assert!(tags.contains(&expected(path)), "{path} has undeclared tag");
+            }
+        }
+    }
+}
@@ -1,14 +1,27 @@
-use serde::{Deserialize, Serialize};
-use utoipa::IntoParams;
+use axum::response::{IntoResponse, IntoResponseParts, Response, ResponseParts};
+use http::{header::LINK, HeaderValue, Uri};
+use serde::{de::Error, Deserialize, Deserializer, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::infra::{config::PaginationConfig, extract::Json};
 
 /// Pagination parameters.
 #[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, IntoParams)]
 #[serde(rename_all = "camelCase")]
 pub struct PaginationParams {
     /// The 0-indexed page to fetch.
+    /// Pages beyond the configured maximum offset are clamped.
+    #[serde(default, deserialize_with = "at_least::<_, 0>")]
+    #[param(minimum = 0)]
     page: Option<i64>,
     /// The number of elements per page.
+    /// Values above the configured maximum (100 by default) are clamped.
+    #[serde(default, deserialize_with = "at_least::<_, 1>")]
+    #[param(minimum = 1)]
     page_size: Option<i64>,
+    /// Wrap the results in a [`Page`] with the total number of results.
+    #[serde(default)]
+    envelope: bool,
 }
 
 impl PaginationParams {
@@ -27,4 +40,279 @@ impl PaginationParams {
     pub fn offset(&self) -> i64 {
         self.page() * self.page_size()
     }
+
+    /// Whether the results should be wrapped in a [`Page`].
+    pub fn envelope(&self) -> bool {
+        self.envelope
+    }
+
+    /// Clamps the page size and page to the configured limits.
+    pub fn clamp(self, limits: &PaginationConfig) -> Self {
+        let page_size = self.page_size().clamp(1, limits.max_page_size.max(1));
+        let page = self.page().clamp(0, limits.max_offset / page_size);
+        Self {
+            page: Some(page),
+            page_size: Some(page_size),
+            ..self
+        }
+    }
+}
+
+/// Deserializes an optional number, rejecting values below `MIN`.
+fn at_least<'de, D, const MIN: i64>(deserializer: D) -> Result<Option<i64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<i64>::deserialize(deserializer)? {
+        Some(n) if n < MIN => Err(D::Error::custom(format!("must be at least {MIN}"))),
+        n => Ok(n),
+    }
+}
+
+/// A page of results, along with where it is in the full result set.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Page<T> {
+    /// The results on this page.
+    pub items: Vec<T>,
+    /// The total number of results.
+    #[schema(example = 120)]
+    pub total: i64,
+    /// The maximum number of results on a page.
+    #[schema(example = 50)]
+    pub limit: i64,
+    /// The number of results before this page.
+    #[schema(example = 50)]
+    pub offset: i64,
+    /// Whether there are results after this page.
+    pub has_more: bool,
+}
+
+impl<T> Page<T> {
+    /// Constructs the page fetched with `params`, out of `total` results.
+    pub fn new(items: Vec<T>, total: i64, params: &PaginationParams) -> Self {
+        let offset = params.offset();
+        let has_more = offset + (items.len() as i64) < total;
+        Self {
+            items,
+            total,
+            limit: params.limit(),
+            offset,
+            has_more,
+        }
+    }
+}
+
+impl<T: Serialize> IntoResponse for Page<T> {
+    fn into_response(self) -> Response {
+        Json(self).into_response()
+    }
+}
+
+/// A `Link` header (RFC 8288) to the first, previous, next and last pages of a listing.
+///
+/// The links keep the other query parameters of the request,
+/// and `prev` and `next` are left out when there is no such page.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PageLinks(String);
+
+impl PageLinks {
+    /// Links to the pages around the page at `uri`, fetched with `params`, out of `total` results.
+    pub fn new(uri: &Uri, params: &PaginationParams, total: i64) -> Self {
+        let page_size = params.page_size();
+        let page = params.page();
+        let last = (total - 1).max(0) / page_size;
+        let other_params: Vec<(String, String)> =
+            serde_urlencoded::from_str(uri.query().unwrap_or_default()).unwrap_or_default();
+        let link = |page: i64, rel: &str| {
+            let mut query = other_params
+                .iter()
+                .filter(|(key, _)| key != "page" && key != "pageSize")
+                .map(|(key, value)| (key.as_str(), value.clone()))
+                .collect::<Vec<_>>();
+            query.push(("page", page.to_string()));
+            query.push(("pageSize", page_size.to_string()));
+            let query = serde_urlencoded::to_string(query).unwrap_or_default();
+            format!("<{}?{}>; rel=\"{}\"", uri.path(), query, rel)
+        };
+        let mut links = vec![link(0, "first")];
+        if page > 0 {
+            links.push(link((page - 1).min(last), "prev"));
+        }
+        if page < last {
+            links.push(link(page + 1, "next"));
+        }
+        links.push(link(last, "last"));
+        Self(links.join(", "))
+    }
+}
+
+impl IntoResponseParts for PageLinks {
+    type Error = std::convert::Infallible;
+
+    fn into_response_parts(self, mut res: ResponseParts) -> Result<ResponseParts, Self::Error> {
+        if let Ok(value) = HeaderValue::from_str(&self.0) {
+            res.headers_mut().insert(LINK, value);
+        }
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(page: i64, page_size: i64) -> PaginationParams {
+        PaginationParams {
+            page: Some(page),
+            page_size: Some(page_size),
+            envelope: true,
+        }
+    }
+
+    #[test]
+    fn clamp_caps_page_size() {
+        let params = params(0, 1_000_000).clamp(&PaginationConfig::default());
+        assert_eq!(100, params.limit());
+    }
+
+    #[test]
+    fn clamp_caps_offset() {
+        let params = params(i64::MAX, 50).clamp(&PaginationConfig::default());
+        assert_eq!(10_000, params.offset());
+    }
+
+    #[test]
+    fn clamp_rejects_negative_values() {
+        let params = params(-1, -1).clamp(&PaginationConfig::default());
+        assert_eq!(1, params.limit());
+        assert_eq!(0, params.offset());
+    }
+
+    #[test]
+    fn clamp_keeps_values_within_limits() {
+        let params = params(2, 10).clamp(&PaginationConfig::default());
+        assert_eq!(10, params.limit());
+        assert_eq!(20, params.offset());
+    }
+
+    async fn query_error(query: &str) -> String {
+        use crate::infra::{error::ClientError, extract::Query};
+        use axum::extract::FromRequestParts;
+
+        let uri = format!("/?{query}");
+        let (mut parts, _) = http::Request::get(uri).body(()).unwrap().into_parts();
+        match Query::<PaginationParams>::from_request_parts(&mut parts, &()).await {
+            Err(ClientError::BadRequest(message)) => message,
+            other => panic!("expected bad request, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn negative_page_is_rejected() {
+        assert_eq!(
+            "invalid query parameter `page`: must be at least 0",
+            query_error("page=-1").await
+        );
+    }
+
+    #[tokio::test]
+    async fn non_positive_page_size_is_rejected() {
+        assert_eq!(
+            "invalid query parameter `pageSize`: must be at least 1",
+            query_error("pageSize=0").await
+        );
+        assert_eq!(
+            "invalid query parameter `pageSize`: must be at least 1",
+            query_error("page=1&pageSize=-5").await
+        );
+    }
+
+    #[test]
+    fn missing_values_use_defaults() {
+        let params: PaginationParams = serde_urlencoded::from_str("envelope=true").unwrap();
+        assert_eq!(50, params.limit());
+        assert_eq!(0, params.offset());
+    }
+
+    #[test]
+    fn middle_page_links_to_all_neighbours() {
+        let uri: Uri = "/api/items?page=2&pageSize=10&envelope=true"
+            .parse()
+            .unwrap();
+        let links = PageLinks::new(&uri, &params(2, 10), 55);
+        assert_eq!(
+            "</api/items?envelope=true&page=0&pageSize=10>; rel=\"first\", \
+             </api/items?envelope=true&page=1&pageSize=10>; rel=\"prev\", \
+             </api/items?envelope=true&page=3&pageSize=10>; rel=\"next\", \
+             </api/items?envelope=true&page=5&pageSize=10>; rel=\"last\"",
+            links.0
+        );
+    }
+
+    #[test]
+    fn edge_pages_leave_out_missing_neighbours() {
+        let uri: Uri = "/api/urls".parse().unwrap();
+        let first = PageLinks::new(&uri, &params(0, 10), 15);
+        assert!(!first.0.contains("rel=\"prev\""), "{}", first.0);
+        assert!(
+            first.0.contains("page=1&pageSize=10>; rel=\"next\""),
+            "{}",
+            first.0
+        );
+        let last = PageLinks::new(&uri, &params(1, 10), 15);
+        assert!(
+            last.0.contains("page=0&pageSize=10>; rel=\"prev\""),
+            "{}",
+            last.0
+        );
+        assert!(!last.0.contains("rel=\"next\""), "{}", last.0);
+        let empty = PageLinks::new(&uri, &params(0, 10), 0);
+        assert_eq!(
+            "</api/urls?page=0&pageSize=10>; rel=\"first\", \
+             </api/urls?page=0&pageSize=10>; rel=\"last\"",
+            empty.0
+        );
+    }
+
+    #[test]
+    fn page_knows_whether_there_are_more_results() {
+        assert!(Page::new(vec![1, 2], 5, &params(0, 2)).has_more);
+        assert!(Page::new(vec![3, 4], 5, &params(1, 2)).has_more);
+        assert!(!Page::new(vec![5], 5, &params(2, 2)).has_more);
+        assert!(!Page::<i32>::new(vec![], 0, &params(0, 2)).has_more);
+    }
+
+    #[test]
+    fn pages_of_items_and_urls_serialize_consistently() {
+        use crate::api::{item::item_repository::Item, url::url_repository::ShortUrl};
+        use time::OffsetDateTime;
+
+        let item = Item {
+            id: 1,
+            name: "item".to_string(),
+            description: None,
+            created_by: None,
+            updated_by: None,
+            updated_at: OffsetDateTime::UNIX_EPOCH,
+        };
+        let url = ShortUrl {
+            id: 1,
+            name: "url".to_string(),
+            target: "https://example.com".to_string(),
+            created_by: 1,
+            created_at: OffsetDateTime::UNIX_EPOCH,
+            updated_by: None,
+            enabled: true,
+        };
+        let items = serde_json::to_value(Page::new(vec![item], 3, &params(0, 1))).unwrap();
+        let urls = serde_json::to_value(Page::new(vec![url], 1, &params(0, 1))).unwrap();
+
+        let keys =
+            |v: &serde_json::Value| v.as_object().unwrap().keys().cloned().collect::<Vec<_>>();
+        assert_eq!(keys(&items), keys(&urls));
+        assert_eq!(serde_json::json!(true), items["hasMore"]);
+        assert_eq!(serde_json::json!(false), urls["hasMore"]);
+        assert_eq!(serde_json::json!(3), items["total"]);
+    }
 }
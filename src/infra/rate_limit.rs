@@ -0,0 +1,262 @@
+//! Limiting repeated failed login attempts and visits to short URLs.
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use super::{
+    config::{LoginLimitConfig, VisitLimitConfig},
+    error::{ApiError, ApiResult, ClientError},
+};
+
+/// A client ip and the username it tried to log in as.
+type LoginKey = (Option<IpAddr>, String);
+
+/// A client ip and the short URL it visited.
+type VisitKey = (Option<IpAddr>, String);
+
+/// What has been counted for one key within the current window.
+#[derive(Debug)]
+struct Window {
+    started: Instant,
+    count: u32,
+}
+
+impl Window {
+    /// How long until a window of length `window` is over.
+    fn remaining(&self, window: Duration) -> Duration {
+        window.saturating_sub(self.started.elapsed())
+    }
+}
+
+/// Counts failed logins per client ip and username, and rejects further
+/// attempts with `429 Too Many Requests` once the configured budget is spent.
+///
+/// Successful logins are not counted, so regular authenticated traffic is unaffected.
+#[derive(Clone, Debug, Default)]
+pub struct LoginLimiter(Arc<Mutex<HashMap<LoginKey, Window>>>);
+
+impl LoginLimiter {
+    /// Runs a login attempt unless the client has too many recent failures.
+    ///
+    /// Failing with `401 Unauthorized` counts against the budget, succeeding resets it.
+    pub async fn attempt<T>(
+        &self,
+        ip: Option<IpAddr>,
+        username: &str,
+        config: &LoginLimitConfig,
+        login: impl Future<Output = ApiResult<T>>,
+    ) -> ApiResult<T> {
+        let key = (ip, username.to_string());
+        self.check(&key, config)?;
+        let result = login.await;
+        let mut windows = self.0.lock().expect("login limiter poisoned");
+        match &result {
+            Ok(_) => {
+                windows.remove(&key);
+            }
+            Err(ApiError::ClientError(ClientError::Unauthorized)) => {
+                // Forget windows that have expired, to keep the map from growing
+                windows.retain(|_, w| w.started.elapsed() < config.window);
+                let window = windows.entry(key).or_insert_with(|| Window {
+                    started: Instant::now(),
+                    count: 0,
+                });
+                window.count += 1;
+            }
+            Err(_) => {}
+        }
+        result
+    }
+
+    fn check(&self, key: &LoginKey, config: &LoginLimitConfig) -> ApiResult<()> {
+        let windows = self.0.lock().expect("login limiter poisoned");
+        match windows.get(key) {
+            Some(w) if w.started.elapsed() < config.window && w.count >= config.attempts => {
+                tracing::warn!("Too many failed login attempts from {:?}", key.0);
+                Err(ClientError::TooManyRequests(
+                    "too many failed login attempts".to_string(),
+                    w.remaining(config.window),
+                ))?
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Counts visits per client ip and short URL, and rejects further visits with
+/// `429 Too Many Requests` once the configured budget for the window is spent.
+///
+/// Each client has its own budget, so one client cannot lock others out of a short URL.
+#[derive(Clone, Debug, Default)]
+pub struct VisitLimiter(Arc<Mutex<HashMap<VisitKey, Window>>>);
+
+impl VisitLimiter {
+    /// Counts a visit to the short URL `name` from `ip`,
+    /// unless the client has visited it too many times recently.
+    pub fn visit(
+        &self,
+        ip: Option<IpAddr>,
+        name: &str,
+        config: &VisitLimitConfig,
+    ) -> ApiResult<()> {
+        if config.visits == 0 {
+            return Ok(());
+        }
+        let key = (ip, name.to_string());
+        let mut windows = self.0.lock().expect("visit limiter poisoned");
+        let expired = |w: &Window| w.started.elapsed() >= config.window;
+        if windows.get(&key).is_none_or(expired) {
+            // Forget windows that have expired, to keep the map from growing
+            windows.retain(|_, w| !expired(w));
+            windows.insert(
+                key.clone(),
+                Window {
+                    started: Instant::now(),
+                    count: 0,
+                },
+            );
+        }
+        let window = windows.get_mut(&key).expect("window was just inserted");
+        if window.count >= config.visits {
+            tracing::warn!("Too many visits to short url {} from {:?}", name, ip);
+            return Err(ClientError::TooManyRequests(
+                "too many visits to this short url".to_string(),
+                window.remaining(config.window),
+            ))?;
+        }
+        window.count += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn login(
+        limiter: &LoginLimiter,
+        username: &str,
+        config: &LoginLimitConfig,
+        ok: bool,
+    ) -> ApiResult<()> {
+        let result = if ok {
+            Ok(())
+        } else {
+            Err(ClientError::Unauthorized.into())
+        };
+        limiter
+            .attempt(None, username, config, async { result })
+            .await
+    }
+
+    fn is_limited(result: ApiResult<()>) -> bool {
+        matches!(
+            result,
+            Err(ApiError::ClientError(ClientError::TooManyRequests(..)))
+        )
+    }
+
+    #[tokio::test]
+    async fn failures_beyond_budget_are_limited_per_username() {
+        let limiter = LoginLimiter::default();
+        let config = LoginLimitConfig {
+            attempts: 2,
+            window: Duration::from_secs(60),
+        };
+        assert!(!is_limited(login(&limiter, "a", &config, false).await));
+        assert!(!is_limited(login(&limiter, "a", &config, false).await));
+        assert!(is_limited(login(&limiter, "a", &config, true).await));
+        assert!(!is_limited(login(&limiter, "b", &config, true).await));
+    }
+
+    #[test]
+    fn visits_beyond_budget_are_limited_per_url() {
+        let limiter = VisitLimiter::default();
+        let config = VisitLimitConfig {
+            visits: 2,
+            window: Duration::from_secs(60),
+        };
+        assert!(!is_limited(limiter.visit(None, "a", &config)));
+        assert!(!is_limited(limiter.visit(None, "a", &config)));
+        assert!(is_limited(limiter.visit(None, "a", &config)));
+        assert!(!is_limited(limiter.visit(None, "b", &config)));
+    }
+
+    #[test]
+    fn limited_visits_report_time_left_in_window() {
+        let limiter = VisitLimiter::default();
+        let config = VisitLimitConfig {
+            visits: 1,
+            window: Duration::from_secs(60),
+        };
+        limiter.visit(None, "a", &config).unwrap();
+        match limiter.visit(None, "a", &config) {
+            Err(ApiError::ClientError(ClientError::TooManyRequests(_, retry_after))) => {
+                assert!(retry_after <= config.window, "{retry_after:?}");
+                assert!(retry_after > Duration::from_secs(55), "{retry_after:?}");
+            }
+            other => panic!("expected to be limited, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn visits_are_limited_per_client() {
+        let limiter = VisitLimiter::default();
+        let config = VisitLimitConfig {
+            visits: 1,
+            window: Duration::from_secs(60),
+        };
+        let (a, b) = ("203.0.113.1".parse().ok(), "203.0.113.2".parse().ok());
+        assert!(!is_limited(limiter.visit(a, "url", &config)));
+        assert!(is_limited(limiter.visit(a, "url", &config)));
+        assert!(!is_limited(limiter.visit(b, "url", &config)));
+    }
+
+    #[tokio::test]
+    async fn visit_budget_is_restored_after_window() {
+        let limiter = VisitLimiter::default();
+        let config = VisitLimitConfig {
+            visits: 1,
+            window: Duration::from_millis(50),
+        };
+        limiter.visit(None, "a", &config).unwrap();
+        assert!(is_limited(limiter.visit(None, "a", &config)));
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert!(!is_limited(limiter.visit(None, "a", &config)));
+    }
+
+    #[test]
+    fn zero_visits_disables_the_limit() {
+        let limiter = VisitLimiter::default();
+        let config = VisitLimitConfig {
+            visits: 0,
+            window: Duration::from_secs(60),
+        };
+        for _ in 0..10 {
+            limiter.visit(None, "a", &config).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn budget_is_restored_after_window_or_success() {
+        let limiter = LoginLimiter::default();
+        let config = LoginLimitConfig {
+            attempts: 2,
+            window: Duration::from_millis(50),
+        };
+        login(&limiter, "a", &config, false).await.unwrap_err();
+        login(&limiter, "a", &config, true).await.unwrap();
+        login(&limiter, "a", &config, false).await.unwrap_err();
+        assert!(!is_limited(login(&limiter, "a", &config, false).await));
+        assert!(is_limited(login(&limiter, "a", &config, false).await));
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert!(!is_limited(login(&limiter, "a", &config, true).await));
+    }
+}
@@ -36,23 +36,97 @@
 use crate::infra::error::Redirection;
 
 use super::{
-    database::Tx,
+    config::PasswordPolicy,
+    database::{DbPool, Tx},
     error::{ApiError, ApiResult, ClientError, InternalError},
+    middleware::request_client_ip,
     state::AppState,
 };
 use axum::{async_trait, extract::FromRequestParts, RequestPartsExt};
 use axum_extra::{
-    headers::{authorization::Basic, Authorization},
+    headers::{
+        authorization::{Basic, Bearer},
+        Authorization,
+    },
     TypedHeader,
 };
-use cached::proc_macro::cached;
+use cached::{proc_macro::cached, Cached};
+use http::header::AUTHORIZATION;
 use serde::{Deserialize, Serialize};
-use std::marker::PhantomData;
+use std::{marker::PhantomData, str::FromStr};
 use tower_sessions::Session;
 use tracing::instrument;
+use uuid::Uuid;
+use validator::{ValidationError, ValidationErrors};
 
+const USER_ROLE: &str = "user";
 const ADMIN_ROLE: &str = "admin";
 
+/// The roles a user can be assigned.
+pub const KNOWN_ROLES: [&str; 2] = [USER_ROLE, ADMIN_ROLE];
+
+/// A role stored in the database, parsed when a user is authenticated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum KnownRole {
+    /// A regular user.
+    User,
+    /// An administrator.
+    Admin,
+}
+
+impl KnownRole {
+    /// The name of the role as stored in the database.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::User => USER_ROLE,
+            Self::Admin => ADMIN_ROLE,
+        }
+    }
+}
+
+impl FromStr for KnownRole {
+    type Err = String;
+
+    fn from_str(role: &str) -> Result<Self, Self::Err> {
+        match role {
+            USER_ROLE => Ok(Self::User),
+            ADMIN_ROLE => Ok(Self::Admin),
+            other => Err(format!("unknown role: {other}")),
+        }
+    }
+}
+
+impl TryFrom<String> for KnownRole {
+    type Error = String;
+
+    fn try_from(role: String) -> Result<Self, Self::Error> {
+        role.parse()
+    }
+}
+
+impl From<KnownRole> for String {
+    fn from(role: KnownRole) -> Self {
+        role.as_str().to_string()
+    }
+}
+
+impl std::fmt::Display for KnownRole {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Parses a role read from the database.
+///
+/// An unknown role is an inconsistency in the database, not a reason to deny access.
+fn parse_role(user_id: i32, role: &str) -> ApiResult<KnownRole> {
+    role.parse().map_err(|e| {
+        tracing::error!("User {} has an invalid role: {}", user_id, e);
+        ApiError::from(InternalError::Other(format!("user {user_id} has {e}")))
+    })
+}
+
 /// A trait to implement to create new roles.
 ///
 /// # Examples
@@ -146,7 +220,9 @@ impl Role for Admin {
 pub struct User<R = Unknown> {
     id: i32,
     username: String,
-    role: String,
+    role: KnownRole,
+    #[serde(default)]
+    impersonated_by: Option<i32>,
     role_type: PhantomData<R>,
 }
 
@@ -163,7 +239,17 @@ impl<R> User<R> {
 
     /// The role of the user.
     pub fn role(&self) -> &str {
-        self.role.as_ref()
+        self.role.as_str()
+    }
+
+    /// The parsed role of the user.
+    pub fn known_role(&self) -> KnownRole {
+        self.role
+    }
+
+    /// The id of the administrator acting as this user, if any.
+    pub fn impersonated_by(&self) -> Option<i32> {
+        self.impersonated_by
     }
 
     /// Attempt to upgrade (or downgrade) a user's roles.
@@ -171,11 +257,12 @@ impl<R> User<R> {
     where
         NewRole: Role,
     {
-        if NewRole::is_satisfied(&[&self.role]) {
+        if NewRole::is_satisfied(&[self.role.as_str()]) {
             Ok(User {
                 id: self.id,
                 username: self.username,
                 role: self.role,
+                impersonated_by: self.impersonated_by,
                 role_type: PhantomData,
             })
         } else {
@@ -191,6 +278,7 @@ impl User<Admin> {
             id: self.id,
             username: self.username,
             role: self.role,
+            impersonated_by: self.impersonated_by,
             role_type: PhantomData,
         }
     }
@@ -201,6 +289,7 @@ impl<R> std::fmt::Debug for User<R> {
         f.debug_struct("User")
             .field("id", &self.id)
             .field("role", &self.role)
+            .field("impersonated_by", &self.impersonated_by)
             .finish()
     }
 }
@@ -209,16 +298,18 @@ async fn extract_session(req: &mut http::request::Parts) -> Result<Option<Sessio
     let session = req
         .extract::<Option<Session>>()
         .await
-        .map_err(|e| {
+        .inspect_err(|e| {
             tracing::error!("Failed to extract session: {}", e);
-            e
         })
         .unwrap_or(None);
 
     Ok(session)
 }
 
-async fn extract_user<R>(session: Option<&Session>) -> Result<Option<User<R>>, ApiError>
+async fn extract_user<R>(
+    session: Option<&Session>,
+    state: &AppState,
+) -> Result<Option<User<R>>, ApiError>
 where
     R: Role + Send,
 {
@@ -226,11 +317,21 @@ where
     if let Some(session) = session {
         let user = session.get::<User>("user").await.map_err(|e| {
             tracing::warn!("Failed to fetch user data from session: {}", e);
-            InternalError::Other(e.to_string())
+            InternalError::SessionStoreUnavailable(e.to_string())
         })?;
         match user {
             Some(user) => {
-                tracing::info!("{} is already logged in", user.username());
+                // Make sure the session reflects the user's current role
+                let role = current_role(state.db(), user.id()).await?;
+                if role.as_deref() != Some(user.role()) {
+                    tracing::info!("Role has changed, invalidating session");
+                    session.flush().await.map_err(|e| {
+                        tracing::warn!("Failed to flush session: {}", e);
+                        InternalError::Other(e.to_string())
+                    })?;
+                    return Err(ApiError::from(Redirection::ToLogin));
+                }
+                tracing::info!("User {} is already logged in", user.id());
                 return Ok(Some(user.try_upgrade()?));
             }
             None => {
@@ -254,39 +355,94 @@ where
         req: &mut http::request::Parts,
         state: &AppState,
     ) -> Result<Self, Self::Rejection> {
-        tracing::info!("Path {} requires authentication", req.uri.path());
-
-        // Try to get user from session
-        let session = extract_session(req).await?;
-        let user = extract_user(session.as_ref()).await?;
-        if let Some(user) = user {
-            tracing::info!("User found in session");
-            return Ok(user);
-        }
-
-        tracing::info!("No session");
-
-        // Get authorization header
-        let TypedHeader(auth) = req
-            .extract::<TypedHeader<Authorization<Basic>>>()
-            .await
-            .map_err(|_| ClientError::Unauthorized)?;
-
-        // Get db connection
-        let db = state.db();
-        let mut tx = db.begin().await?;
+        let user = authenticate_request::<R>(req, state).await?;
+        // Lets traces be filtered by user, the username is left out for privacy
+        let span = tracing::Span::current();
+        span.record("user_id", user.id());
+        span.record("user_role", user.role());
+        Ok(user)
+    }
+}
 
-        // Authenticate user
-        let user = authenticate(&mut tx, auth.username(), auth.password()).await?;
+/// Authenticates the user making a request, through a session,
+/// an impersonation token, or basic auth, in that order.
+async fn authenticate_request<R>(
+    req: &mut http::request::Parts,
+    state: &AppState,
+) -> Result<User<R>, ApiError>
+where
+    R: Role + Send,
+{
+    tracing::info!("Path {} requires authentication", req.uri.path());
+
+    // Try to get user from session, unless the store is down and there are other credentials
+    let session = extract_session(req).await?;
+    let user = match extract_user(session.as_ref(), state).await {
+        Err(ApiError::InternalError(InternalError::SessionStoreUnavailable(e)))
+            if req.headers.contains_key(AUTHORIZATION) =>
+        {
+            tracing::warn!(
+                "Session store is unavailable, using credentials instead: {}",
+                e
+            );
+            None
+        }
+        result => result?,
+    };
+    if let Some(user) = user {
+        tracing::info!("User found in session");
+        return Ok(user);
+    }
 
-        // Make sure they have the correct roles
-        let user = user.try_upgrade()?;
+    tracing::info!("No session");
 
-        Ok(user)
+    // An administrator acting as another user, see `create_impersonation`
+    if let Ok(TypedHeader(auth)) = req.extract::<TypedHeader<Authorization<Bearer>>>().await {
+        let mut tx = state.db().begin().await?;
+        let user = authenticate_impersonation(&mut tx, auth.token()).await?;
+        tx.commit().await?;
+        if !req.method.is_safe() {
+            tracing::warn!("Impersonating admin attempted {}", req.method);
+            return Err(ClientError::Forbidden)?;
+        }
+        return user.try_upgrade();
     }
+
+    // Get authorization header
+    let TypedHeader(auth) = req
+        .extract::<TypedHeader<Authorization<Basic>>>()
+        .await
+        .map_err(|_| ClientError::Unauthorized)?;
+
+    // Get db connection
+    let db = state.db();
+    let mut tx = db.begin().await?;
+
+    // Authenticate user, unless there have been too many failed attempts
+    let config = state.config();
+    let ip = request_client_ip(
+        &req.extensions,
+        &req.headers,
+        &config.server.trusted_proxies,
+    );
+    let cost = config.security.bcrypt_cost;
+    let login = authenticate(&mut tx, auth.username(), auth.password(), cost);
+    let user = state
+        .login_limiter()
+        .attempt(ip, auth.username(), &config.security.login_limit, login)
+        .await?;
+    tx.commit().await?;
+
+    // Make sure they have the correct roles
+    let user = user.try_upgrade()?;
+
+    Ok(user)
 }
 
 /// Validate a user's password.
+///
+/// If the stored hash was made with a different cost than `cost`,
+/// the password is rehashed so that the configured cost takes effect.
 #[cached(
     size = 100,
     time = 30,
@@ -296,8 +452,13 @@ where
     convert = r##"{ format!("{}:{}", username, password) }"##,
     result = true
 )]
-#[instrument(skip(conn, password))]
-pub async fn authenticate(conn: &mut Tx, username: &str, password: &str) -> ApiResult<User> {
+#[instrument(skip(conn, username, password))]
+pub async fn authenticate(
+    conn: &mut Tx,
+    username: &str,
+    password: &str,
+    cost: u32,
+) -> ApiResult<User> {
     tracing::info!("Fetching password");
     let user = sqlx::query!(
         r#"
@@ -314,10 +475,16 @@ pub async fn authenticate(conn: &mut Tx, username: &str, password: &str) -> ApiR
     let password_is_ok = bcrypt::verify(password, &user.password)?;
     if password_is_ok {
         tracing::info!("Correct password");
+        let stored_cost = bcrypt::HashParts::from_str(&user.password)?.get_cost();
+        if stored_cost != cost {
+            tracing::info!("Rehashing password from cost {} to {}", stored_cost, cost);
+            change_password(conn, user.id, password, cost).await?;
+        }
         Ok(User {
             id: user.id,
             username: username.to_string(),
-            role: user.role,
+            role: parse_role(user.id, &user.role)?,
+            impersonated_by: None,
             role_type: PhantomData,
         })
     } else {
@@ -326,15 +493,201 @@ pub async fn authenticate(conn: &mut Tx, username: &str, password: &str) -> ApiR
     }
 }
 
+/// Resolves an impersonation token to the impersonated user.
+///
+/// Unknown and expired tokens are rejected.
+#[instrument(skip_all)]
+pub async fn authenticate_impersonation(conn: &mut Tx, token: &str) -> ApiResult<User> {
+    let token = Uuid::parse_str(token).map_err(|_| ClientError::Unauthorized)?;
+    let user = sqlx::query!(
+        r#"
+        SELECT users.id, users.username, users.role, admins.id AS admin_id
+        FROM impersonations
+        JOIN users ON users.id = impersonations.user_id
+        JOIN users admins ON admins.id = impersonations.admin_id
+        WHERE impersonations.token = $1 AND impersonations.expires_at > NOW()
+        "#,
+        token
+    )
+    .fetch_optional(conn.as_mut())
+    .await?
+    .ok_or(ClientError::Unauthorized)?;
+    tracing::info!("Admin {} is acting as user {}", user.admin_id, user.id);
+    Ok(User {
+        id: user.id,
+        username: user.username,
+        role: parse_role(user.id, &user.role)?,
+        impersonated_by: Some(user.admin_id),
+        role_type: PhantomData,
+    })
+}
+
+/// Clears all cached authentication results.
+///
+/// Call this after changing a user's credentials or role.
+pub async fn invalidate_auth_cache() {
+    tracing::info!("Invalidating authentication cache");
+    AUTHENTICATE.lock().await.cache_clear();
+}
+
+/// Clears the cached authentication results of a single user.
+///
+/// Call this after changing the credentials or role of a known user.
+pub async fn invalidate_user_auth_cache(username: &str) {
+    tracing::info!("Invalidating authentication cache for {}", username);
+    let mut cache = AUTHENTICATE.lock().await;
+    // Keys are `username:password`, and basic auth usernames cannot contain a colon
+    let keys: Vec<String> = cache
+        .key_order()
+        .filter(|key| {
+            key.split_once(':')
+                .is_some_and(|(user, _)| user == username)
+        })
+        .cloned()
+        .collect();
+    for key in keys {
+        cache.cache_remove(&key);
+    }
+}
+
+/// Clears the cached authentication results of a single user by id.
+///
+/// Unlike [`invalidate_user_auth_cache`], this also clears entries cached
+/// under a username the user no longer has.
+pub async fn invalidate_user_id_auth_cache(user_id: i32) {
+    tracing::info!("Invalidating authentication cache for user {}", user_id);
+    let mut cache = AUTHENTICATE.lock().await;
+    let keys: Vec<String> = cache
+        .key_order()
+        .zip(cache.value_order())
+        .filter(|(_, (_, user))| user.id() == user_id)
+        .map(|(key, _)| key.clone())
+        .collect();
+    for key in keys {
+        cache.cache_remove(&key);
+    }
+}
+
+/// Fetches a user's current role, cached so that sessions
+/// do not query it on every request.
+///
+/// Call [`invalidate_role_cache`] after changing a user's role.
+#[cached(
+    size = 1000,
+    time = 30,
+    key = "i32",
+    convert = "{ user_id }",
+    result = true
+)]
+async fn current_role(db: &DbPool, user_id: i32) -> ApiResult<Option<String>> {
+    let mut tx = db.begin().await?;
+    let role = fetch_role(&mut tx, user_id).await?;
+    tx.commit().await?;
+    Ok(role)
+}
+
+/// Clears the cached role of a user,
+/// so that their sessions are invalidated on their next request.
+pub async fn invalidate_role_cache(user_id: i32) {
+    tracing::info!("Invalidating cached role of user {}", user_id);
+    CURRENT_ROLE.lock().await.cache_remove(&user_id);
+}
+
+/// Fetches a user's current role.
+#[instrument(skip(conn))]
+pub async fn fetch_role(conn: &mut Tx, user_id: i32) -> ApiResult<Option<String>> {
+    let role = sqlx::query_scalar!(
+        r#"
+        SELECT role FROM users
+        WHERE id = $1
+        "#,
+        user_id
+    )
+    .fetch_optional(conn.as_mut())
+    .await?;
+    Ok(role)
+}
+
+/// Checks that a password satisfies the password policy.
+///
+/// Every unmet requirement is reported as a separate code on the `password` field.
+pub fn validate_password(policy: &PasswordPolicy, password: &str) -> Result<(), ValidationErrors> {
+    let mut errors = ValidationErrors::new();
+    let mut require = |satisfied: bool, code: &'static str| {
+        if !satisfied {
+            errors.add("password", ValidationError::new(code));
+        }
+    };
+    require(password.chars().count() >= policy.min_length, "length");
+    require(
+        !policy.require_uppercase || password.chars().any(char::is_uppercase),
+        "uppercase",
+    );
+    require(
+        !policy.require_lowercase || password.chars().any(char::is_lowercase),
+        "lowercase",
+    );
+    require(
+        !policy.require_digit || password.chars().any(|c| c.is_ascii_digit()),
+        "digit",
+    );
+    require(
+        !policy.require_symbol || password.chars().any(|c| !c.is_alphanumeric()),
+        "symbol",
+    );
+    require(!policy.denylist.iter().any(|p| p == password), "breached");
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Changes a user's password.
+#[instrument(skip(conn, password))]
+pub async fn change_password(
+    conn: &mut Tx,
+    user_id: i32,
+    password: &str,
+    cost: u32,
+) -> ApiResult<()> {
+    tracing::info!("Changing password");
+    let hash = bcrypt::hash(password, cost)?;
+    let rows = sqlx::query!(
+        r#"
+        UPDATE users SET password = $1
+        WHERE id = $2
+        "#,
+        hash,
+        user_id
+    )
+    .execute(conn.as_mut())
+    .await?;
+
+    if rows.rows_affected() == 0 {
+        tracing::warn!("User not found");
+        return Err(ClientError::NotFound)?;
+    }
+
+    tracing::info!("Changed password");
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use std::marker::PhantomData;
 
-    use super::authenticate;
+    use std::str::FromStr;
+
+    use super::{
+        authenticate, invalidate_user_auth_cache, invalidate_user_id_auth_cache, validate_password,
+    };
     use crate::infra::{
+        config::PasswordPolicy,
         database::DbPool,
         error::{ApiError, ClientError},
-        security::{Admin, User},
+        security::{Admin, KnownRole, User, KNOWN_ROLES},
     };
 
     #[sqlx::test]
@@ -342,12 +695,16 @@ mod tests {
         let mut tx = db.begin().await.unwrap();
         let username = "user";
         let password = "user";
-        let user = authenticate(&mut tx, username, password).await.unwrap();
+        let user = authenticate(&mut tx, username, password, bcrypt::DEFAULT_COST)
+            .await
+            .unwrap();
         assert_eq!(1, user.id());
 
         let username = "admin";
         let password = "admin";
-        let user = authenticate(&mut tx, username, password).await.unwrap();
+        let user = authenticate(&mut tx, username, password, bcrypt::DEFAULT_COST)
+            .await
+            .unwrap();
         assert_eq!(2, user.id());
     }
 
@@ -356,18 +713,108 @@ mod tests {
         let mut tx = db.begin().await.unwrap();
         let username = "user";
         let password = "notuser";
-        let result = authenticate(&mut tx, username, password).await;
+        let result = authenticate(&mut tx, username, password, bcrypt::DEFAULT_COST).await;
         assert!(matches!(
             result,
             Err(ApiError::ClientError(ClientError::Unauthorized))
         ))
     }
 
+    #[sqlx::test]
+    async fn low_cost_rehashes_password_once(db: DbPool) {
+        let (username, password) = ("lowcost", "lowcost");
+        let hash = bcrypt::hash(password, bcrypt::DEFAULT_COST).unwrap();
+        sqlx::query("INSERT INTO users (username, password, role) VALUES ($1, $2, 'user')")
+            .bind(username)
+            .bind(hash)
+            .execute(&db)
+            .await
+            .unwrap();
+        let stored_hash = || {
+            sqlx::query_scalar::<_, String>("SELECT password FROM users WHERE username = $1")
+                .bind(username)
+                .fetch_one(&db)
+        };
+
+        // The first login verifies against the expensive hash and rehashes it
+        let mut tx = db.begin().await.unwrap();
+        authenticate(&mut tx, username, password, 4).await.unwrap();
+        tx.commit().await.unwrap();
+        let rehashed = stored_hash().await.unwrap();
+        assert_eq!(
+            4,
+            bcrypt::HashParts::from_str(&rehashed).unwrap().get_cost()
+        );
+
+        // Later logins verify against the cheap hash, and leave it as is
+        invalidate_user_auth_cache(username).await;
+        let mut tx = db.begin().await.unwrap();
+        authenticate(&mut tx, username, password, 4).await.unwrap();
+        tx.commit().await.unwrap();
+        assert_eq!(rehashed, stored_hash().await.unwrap());
+    }
+
+    #[sqlx::test]
+    async fn invalidating_by_id_ignores_stale_usernames(db: DbPool) {
+        let (username, password) = ("staleid", "staleid");
+        let hash = bcrypt::hash(password, 4).unwrap();
+        let id: i32 = sqlx::query_scalar(
+            "INSERT INTO users (username, password, role) VALUES ($1, $2, 'user') RETURNING id",
+        )
+        .bind(username)
+        .bind(hash)
+        .fetch_one(&db)
+        .await
+        .unwrap();
+        let mut tx = db.begin().await.unwrap();
+        authenticate(&mut tx, username, password, 4).await.unwrap();
+        tx.commit().await.unwrap();
+
+        // Invalidating under another username misses the cached entry
+        sqlx::query("UPDATE users SET password = 'invalid' WHERE id = $1")
+            .bind(id)
+            .execute(&db)
+            .await
+            .unwrap();
+        invalidate_user_auth_cache("staleid-renamed").await;
+        let mut tx = db.begin().await.unwrap();
+        assert!(authenticate(&mut tx, username, password, 4).await.is_ok());
+
+        invalidate_user_id_auth_cache(id).await;
+        assert!(authenticate(&mut tx, username, password, 4).await.is_err());
+    }
+
+    #[test]
+    fn known_roles_parse() {
+        for role in KNOWN_ROLES {
+            assert_eq!(role, role.parse::<KnownRole>().unwrap().as_str());
+        }
+        assert!("superuser".parse::<KnownRole>().is_err());
+    }
+
+    #[sqlx::test]
+    async fn unknown_role_in_database_is_an_error(db: DbPool) {
+        let hash = bcrypt::hash("unknownrole", 4).unwrap();
+        sqlx::query("INSERT INTO users (username, password, role) VALUES ($1, $2, 'superuser')")
+            .bind("unknownrole")
+            .bind(hash)
+            .execute(&db)
+            .await
+            .unwrap();
+        let mut tx = db.begin().await.unwrap();
+        let result = authenticate(&mut tx, "unknownrole", "unknownrole", 4).await;
+        let Err(ApiError::InternalError(e)) = result else {
+            panic!("expected an internal error, got {result:?}");
+        };
+        assert!(e.to_string().contains("unknown role: superuser"), "{e}");
+    }
+
     fn user() -> User {
         User {
             id: 0,
             username: "user".into(),
-            role: "user".into(),
+            role: KnownRole::User,
+            impersonated_by: None,
             role_type: PhantomData,
         }
     }
@@ -376,7 +823,8 @@ mod tests {
         User {
             id: 0,
             username: "admin".into(),
-            role: "admin".into(),
+            role: KnownRole::Admin,
+            impersonated_by: None,
             role_type: PhantomData::<Admin>,
         }
         .try_upgrade()
@@ -423,4 +871,58 @@ mod tests {
             Err(ApiError::ClientError(ClientError::Forbidden))
         ));
     }
+
+    fn policy() -> PasswordPolicy {
+        PasswordPolicy {
+            min_length: 8,
+            require_uppercase: true,
+            require_lowercase: true,
+            require_digit: true,
+            require_symbol: true,
+            denylist: vec!["Password1!".to_string()],
+        }
+    }
+
+    fn failed_codes(password: &str) -> Vec<String> {
+        let errors = validate_password(&policy(), password).unwrap_err();
+        errors.field_errors()["password"]
+            .iter()
+            .map(|e| e.code.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn password_meeting_all_rules_is_accepted() {
+        assert!(validate_password(&policy(), "Str0ng!pass").is_ok());
+    }
+
+    #[test]
+    fn short_password_is_rejected() {
+        assert_eq!(vec!["length"], failed_codes("Sh0rt!"));
+    }
+
+    #[test]
+    fn password_without_uppercase_is_rejected() {
+        assert_eq!(vec!["uppercase"], failed_codes("str0ng!pass"));
+    }
+
+    #[test]
+    fn password_without_lowercase_is_rejected() {
+        assert_eq!(vec!["lowercase"], failed_codes("STR0NG!PASS"));
+    }
+
+    #[test]
+    fn password_without_digit_is_rejected() {
+        assert_eq!(vec!["digit"], failed_codes("Strong!pass"));
+    }
+
+    #[test]
+    fn password_without_symbol_is_rejected() {
+        assert_eq!(vec!["symbol"], failed_codes("Str0ngpass"));
+    }
+
+    #[test]
+    fn breached_password_is_rejected() {
+        assert_eq!(vec!["breached"], failed_codes("Password1!"));
+    }
 }
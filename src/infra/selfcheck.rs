@@ -0,0 +1,180 @@
+//! Checks of the application's integrations.
+//!
+//! Misconfigured integrations otherwise only surface on first use,
+//! so we probe each of them once on startup and log the outcome.
+//! The same checks decide whether the application is ready to serve requests.
+
+use super::{
+    config::{Config, Dependency, EmailConfig, MqConfig},
+    database::DbPool,
+};
+use std::{fmt::Display, time::Duration};
+use tokio::net::TcpStream;
+
+/// The outcome of checking a single subsystem.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CheckStatus {
+    /// The subsystem is reachable.
+    Pass,
+    /// The subsystem could not be reached.
+    Fail(String),
+}
+
+impl Display for CheckStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CheckStatus::Pass => write!(f, "PASS"),
+            CheckStatus::Fail(reason) => write!(f, "FAIL ({reason})"),
+        }
+    }
+}
+
+/// The result of checking a single subsystem.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CheckResult {
+    /// The name of the subsystem.
+    pub name: &'static str,
+    /// Whether the application can work without this subsystem.
+    pub optional: bool,
+    /// The outcome of the check.
+    pub status: CheckStatus,
+}
+
+/// The results of all startup checks.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SelfCheck {
+    results: Vec<CheckResult>,
+}
+
+impl SelfCheck {
+    /// The results of the individual checks.
+    pub fn results(&self) -> &[CheckResult] {
+        &self.results
+    }
+
+    /// Returns the result of the check with the given name.
+    pub fn get(&self, name: &str) -> Option<&CheckResult> {
+        self.results.iter().find(|r| r.name == name)
+    }
+
+    /// Whether all required subsystems passed.
+    pub fn is_healthy(&self) -> bool {
+        self.results
+            .iter()
+            .all(|r| r.optional || r.status == CheckStatus::Pass)
+    }
+}
+
+/// Checks all integrations.
+///
+/// Only the dependencies listed in the readiness configuration are required.
+pub async fn check(config: &Config, db: &DbPool) -> SelfCheck {
+    let timeout = config.readiness.timeout;
+    let optional = |dependency| !config.readiness.required.contains(&dependency);
+    let (database, mq, smtp) = tokio::join!(
+        check_db(db, timeout),
+        check_mq(&config.mq, timeout),
+        check_smtp(&config.email, timeout)
+    );
+    let results = vec![
+        CheckResult {
+            name: "database",
+            optional: optional(Dependency::Database),
+            status: database,
+        },
+        CheckResult {
+            name: "mq",
+            optional: optional(Dependency::Mq),
+            status: mq,
+        },
+        CheckResult {
+            name: "smtp",
+            optional: optional(Dependency::Smtp),
+            status: smtp,
+        },
+    ];
+    SelfCheck { results }
+}
+
+/// Checks all integrations and logs a summary.
+///
+/// This never aborts startup, failures are only logged.
+#[tracing::instrument(skip_all)]
+pub async fn run(config: &Config, db: &DbPool) -> SelfCheck {
+    let self_check = check(config, db).await;
+
+    for result in self_check.results() {
+        match (&result.status, result.optional) {
+            (CheckStatus::Pass, _) => {
+                tracing::info!("Self-check {}: {}", result.name, result.status)
+            }
+            (CheckStatus::Fail(_), true) => {
+                tracing::warn!("Self-check {}: {}", result.name, result.status)
+            }
+            (CheckStatus::Fail(_), false) => {
+                tracing::error!("Self-check {}: {}", result.name, result.status)
+            }
+        }
+    }
+
+    if self_check.is_healthy() {
+        tracing::info!("Self-check completed, all required subsystems are healthy");
+    } else {
+        tracing::error!("Self-check completed, some required subsystems are unhealthy");
+    }
+    self_check
+}
+
+async fn check_db(db: &DbPool, timeout: Duration) -> CheckStatus {
+    let query = sqlx::query("SELECT 1").execute(db);
+    match tokio::time::timeout(timeout, query).await {
+        Ok(Ok(_)) => CheckStatus::Pass,
+        Ok(Err(e)) => CheckStatus::Fail(e.to_string()),
+        Err(_) => CheckStatus::Fail("timed out".to_string()),
+    }
+}
+
+async fn check_mq(config: &MqConfig, timeout: Duration) -> CheckStatus {
+    let connect = TcpStream::connect((config.host.as_str(), config.port));
+    match tokio::time::timeout(timeout, connect).await {
+        Ok(Ok(_)) => CheckStatus::Pass,
+        Ok(Err(e)) => CheckStatus::Fail(e.to_string()),
+        Err(_) => CheckStatus::Fail("timed out".to_string()),
+    }
+}
+
+async fn check_smtp(config: &EmailConfig, timeout: Duration) -> CheckStatus {
+    let lookup = tokio::net::lookup_host((config.host.as_str(), 0));
+    match tokio::time::timeout(timeout, lookup).await {
+        Ok(Ok(mut addrs)) => match addrs.next() {
+            Some(_) => CheckStatus::Pass,
+            None => CheckStatus::Fail("host did not resolve".to_string()),
+        },
+        Ok(Err(e)) => CheckStatus::Fail(e.to_string()),
+        Err(_) => CheckStatus::Fail("timed out".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[sqlx::test]
+    async fn unreachable_mq_is_only_a_warning(db: DbPool) {
+        let mut config = crate::infra::config::load_config().unwrap();
+        config.mq.host = "127.0.0.1".to_string();
+        config.mq.port = 1;
+
+        let self_check = run(&config, &db).await;
+
+        assert_eq!(
+            CheckStatus::Pass,
+            self_check.get("database").unwrap().status
+        );
+        assert!(matches!(
+            self_check.get("mq").unwrap().status,
+            CheckStatus::Fail(_)
+        ));
+        assert!(self_check.is_healthy());
+    }
+}
@@ -1,7 +1,64 @@
-use tokio::signal;
+use std::{
+    future::{Future, IntoFuture},
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use axum::Router;
+use tokio::{net::TcpListener, signal, sync::Notify};
+use tokio_util::sync::CancellationToken;
+
+/// Counts the requests that are currently being handled.
+#[derive(Clone, Debug, Default)]
+pub struct InFlight(Arc<AtomicUsize>);
+
+impl InFlight {
+    /// The number of requests currently being handled.
+    pub fn count(&self) -> usize {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Marks a request as started, until the returned guard is dropped.
+    pub(crate) fn start(&self) -> InFlightGuard {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        InFlightGuard(self.0.clone())
+    }
+}
+
+/// Marks a request as finished when dropped.
+#[derive(Debug)]
+pub(crate) struct InFlightGuard(Arc<AtomicUsize>);
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// A token that is cancelled when the application should shut down.
+///
+/// Everything that should stop on shutdown observes a clone of this token,
+/// so that they all stop together.
+pub(crate) fn shutdown_token() -> CancellationToken {
+    let token = CancellationToken::new();
+    tokio::spawn(cancel_on_signal(token.clone()));
+    token
+}
+
+/// Cancels `token` on `SIGINT` or `SIGTERM`, or on ctrl-c on other platforms.
+async fn cancel_on_signal(token: CancellationToken) {
+    tokio::select! {
+        _ = shutdown_signal() => token.cancel(),
+        _ = token.cancelled() => {},
+    }
+}
 
 /// A future that completes when the application should shut down.
-pub(crate) async fn shutdown_signal() {
+async fn shutdown_signal() {
     let ctrl_c = async {
         signal::ctrl_c()
             .await
@@ -26,3 +83,134 @@ pub(crate) async fn shutdown_signal() {
 
     tracing::info!("Received shutdown signal");
 }
+
+/// Serves `app` until `signal` completes, then waits up to `timeout` for in-flight requests.
+pub(crate) async fn serve_until(
+    listener: TcpListener,
+    app: Router,
+    signal: impl Future<Output = ()> + Send + 'static,
+    in_flight: InFlight,
+    timeout: Duration,
+) -> std::io::Result<()> {
+    let shutdown_started = Arc::new(Notify::new());
+    let graceful = {
+        let shutdown_started = shutdown_started.clone();
+        let in_flight = in_flight.clone();
+        async move {
+            signal.await;
+            tracing::info!(
+                in_flight = in_flight.count(),
+                "Shutting down with {} requests in flight",
+                in_flight.count()
+            );
+            shutdown_started.notify_one();
+        }
+    };
+    let server = axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(graceful);
+    let deadline = async {
+        shutdown_started.notified().await;
+        tokio::time::sleep(timeout).await;
+    };
+
+    tokio::select! {
+        result = server.into_future() => {
+            result?;
+            tracing::info!("Drained cleanly");
+        }
+        _ = deadline => {
+            tracing::warn!(
+                in_flight = in_flight.count(),
+                "Forced shutdown after timeout with {} requests in flight",
+                in_flight.count()
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infra::logging::CapturedLogs;
+    use axum::routing::get;
+
+    /// Serves a slow endpoint, and starts `n` requests before signalling shutdown.
+    async fn shutdown_during_requests(n: usize, work: Duration, timeout: Duration) -> String {
+        let logs = CapturedLogs::default();
+        let _guard = logs.capture();
+
+        let in_flight = InFlight::default();
+        let app = Router::new()
+            .route("/slow", get(move || tokio::time::sleep(work)))
+            .layer(axum::middleware::from_fn_with_state(
+                in_flight.clone(),
+                crate::infra::middleware::track_in_flight,
+            ));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/slow", listener.local_addr().unwrap());
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(serve_until(
+            listener,
+            app,
+            async move {
+                let _ = rx.await;
+            },
+            in_flight.clone(),
+            timeout,
+        ));
+
+        for _ in 0..n {
+            tokio::spawn(reqwest::get(url.clone()));
+        }
+        while in_flight.count() < n {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        tx.send(()).unwrap();
+        server.await.unwrap().unwrap();
+        logs.output()
+    }
+
+    #[tokio::test]
+    async fn shutdown_logs_in_flight_requests_and_drains() {
+        let output =
+            shutdown_during_requests(3, Duration::from_millis(200), Duration::from_secs(5)).await;
+        assert!(
+            output.contains("Shutting down with 3 requests in flight"),
+            "{output}"
+        );
+        assert!(output.contains("Drained cleanly"), "{output}");
+    }
+
+    #[tokio::test]
+    async fn cancelling_token_completes_shutdown() {
+        let token = shutdown_token();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server = tokio::spawn(serve_until(
+            listener,
+            Router::new(),
+            token.clone().cancelled_owned(),
+            InFlight::default(),
+            Duration::from_secs(5),
+        ));
+        token.cancel();
+        tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .expect("server did not shut down")
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn shutdown_is_forced_after_timeout() {
+        let output =
+            shutdown_during_requests(2, Duration::from_secs(10), Duration::from_millis(50)).await;
+        assert!(
+            output.contains("Forced shutdown after timeout with 2 requests in flight"),
+            "{output}"
+        );
+    }
+}
@@ -0,0 +1,106 @@
+//! Serving requests while the application is starting up.
+
+use std::sync::{Arc, OnceLock};
+
+use axum::{
+    extract::Request,
+    http::{HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Router,
+};
+use tower::ServiceExt;
+
+use super::{error::ErrorBody, extract::Json};
+
+/// An application that answers `503 Service Unavailable` until it is ready.
+///
+/// This lets the listener be bound before slow startup work such as migrations,
+/// so that health checks get a clear answer instead of a refused connection.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Startup(Arc<OnceLock<Router>>);
+
+impl Startup {
+    /// Starts routing requests to `app`.
+    pub(crate) fn ready(&self, app: Router) {
+        if self.0.set(app).is_err() {
+            tracing::warn!("Application was already ready");
+        }
+    }
+
+    /// A router that forwards to the application once it is ready.
+    pub(crate) fn router(&self) -> Router {
+        let startup = self.clone();
+        Router::new().fallback(move |req: Request| {
+            let app = startup.0.get().cloned();
+            async move {
+                match app {
+                    Some(app) => app.oneshot(req).await.into_response(),
+                    None => starting_up(),
+                }
+            }
+        })
+    }
+}
+
+/// The response given before the application is ready.
+fn starting_up() -> Response {
+    let mut response = (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(ErrorBody::new("starting up".to_string())),
+    )
+        .into_response();
+    response
+        .headers_mut()
+        .insert("Retry-After", HeaderValue::from_static("5"));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::infra::shutdown::{serve_until, InFlight};
+    use axum::routing::get;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn requests_get_503_until_ready() {
+        let startup = Startup::default();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hello", listener.local_addr().unwrap());
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(serve_until(
+            listener,
+            startup.router(),
+            async move {
+                let _ = rx.await;
+            },
+            InFlight::default(),
+            Duration::from_secs(5),
+        ));
+
+        // Simulate slow migrations
+        let migrations = {
+            let startup = startup.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                startup.ready(Router::new().route("/hello", get(|| async { "hello" })));
+            })
+        };
+
+        let response = reqwest::get(&url).await.unwrap();
+        assert_eq!(503, response.status());
+        assert_eq!("5", response.headers()["retry-after"]);
+        let body: ErrorBody = response.json().await.unwrap();
+        assert_eq!("starting up", body.message());
+
+        migrations.await.unwrap();
+        let response = reqwest::get(&url).await.unwrap();
+        assert_eq!(200, response.status());
+        assert_eq!("hello", response.text().await.unwrap());
+
+        tx.send(()).unwrap();
+        server.await.unwrap().unwrap();
+    }
+}
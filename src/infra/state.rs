@@ -3,22 +3,55 @@
 //! Used for access to common resources such as a
 //! database pool or a preconfigured http client.
 
-use super::{config::Config, database::DbPool};
+use crate::api::{item::item_events::ItemEvents, url::url_service::PreviewClient};
+
+use super::{
+    config::{
+        Config, DatabaseConfig, EmailConfig, Feature, FeatureFlags, GreetingConfig, ItemsConfig,
+        JsonConfig, LoggingConfig, MqConfig, PaginationConfig, PasswordPolicy, ReadinessConfig,
+        SecurityConfig, ServerConfig, StreamConfig, UrlsConfig,
+    },
+    database::DbPool,
+    metrics::RequestCounts,
+    rate_limit::{LoginLimiter, VisitLimiter},
+    shutdown::InFlight,
+};
 use axum::extract::FromRef;
 use reqwest::Client;
+use tokio_util::task::TaskTracker;
 
 /// Global application state.
 #[derive(Clone, Debug, FromRef)]
 pub struct AppState {
     db: DbPool,
     client: Client,
+    preview_client: PreviewClient,
+    config: Config,
+    item_events: ItemEvents,
+    in_flight: InFlight,
+    request_counts: RequestCounts,
+    login_limiter: LoginLimiter,
+    visit_limiter: VisitLimiter,
+    request_log: TaskTracker,
 }
 
 impl AppState {
     /// Constructs a new [`AppState`].
-    pub fn new(db: DbPool) -> Self {
+    pub fn new(db: DbPool, config: Config) -> Self {
         let client = reqwest::Client::new();
-        Self { db, client }
+        let preview_client = PreviewClient::new(&config.urls);
+        Self {
+            db,
+            client,
+            preview_client,
+            config,
+            item_events: ItemEvents::default(),
+            in_flight: InFlight::default(),
+            request_counts: RequestCounts::default(),
+            login_limiter: LoginLimiter::default(),
+            visit_limiter: VisitLimiter::default(),
+            request_log: TaskTracker::new(),
+        }
     }
 
     /// Returns the database pool.
@@ -31,8 +64,109 @@ impl AppState {
         &self.client
     }
 
-    /// Loads the application configuration.
-    pub fn config(&self) -> color_eyre::Result<Config> {
-        crate::infra::config::load_config()
+    /// Returns the application configuration.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Returns the channel for item notifications.
+    pub fn item_events(&self) -> &ItemEvents {
+        &self.item_events
+    }
+
+    /// Returns the counter of requests currently being handled.
+    pub fn in_flight(&self) -> &InFlight {
+        &self.in_flight
+    }
+
+    /// Returns the counts of handled requests.
+    pub fn request_counts(&self) -> &RequestCounts {
+        &self.request_counts
+    }
+
+    /// Returns the limiter of failed logins.
+    pub fn login_limiter(&self) -> &LoginLimiter {
+        &self.login_limiter
+    }
+
+    /// Returns the limiter of visits to short URLs.
+    pub fn visit_limiter(&self) -> &VisitLimiter {
+        &self.visit_limiter
+    }
+
+    /// Returns the tasks storing requests in the request log.
+    pub fn request_log(&self) -> &TaskTracker {
+        &self.request_log
+    }
+
+    /// Returns whether the given feature is enabled.
+    pub fn is_enabled(&self, feature: Feature) -> bool {
+        self.config.features.is_enabled(feature)
+    }
+}
+
+/// Lets handlers extract a part of the configuration, e.g. `State<PaginationConfig>`,
+/// instead of depending on all of it.
+macro_rules! sub_config {
+    ($($field:ident: $config:ty),* $(,)?) => {
+        $(
+            impl FromRef<AppState> for $config {
+                fn from_ref(state: &AppState) -> Self {
+                    state.config.$field.clone()
+                }
+            }
+        )*
+    };
+}
+
+sub_config! {
+    server: ServerConfig,
+    database: DatabaseConfig,
+    logging: LoggingConfig,
+    mq: MqConfig,
+    email: EmailConfig,
+    password_policy: PasswordPolicy,
+    stream: StreamConfig,
+    security: SecurityConfig,
+    features: FeatureFlags,
+    pagination: PaginationConfig,
+    greeting: GreetingConfig,
+    readiness: ReadinessConfig,
+    items: ItemsConfig,
+    json: JsonConfig,
+    urls: UrlsConfig,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, extract::State, routing::get, Router};
+    use http::Request;
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    #[sqlx::test]
+    async fn sub_configs_are_extracted_from_state(db: DbPool) {
+        let mut config = crate::infra::config::load_config().unwrap();
+        config.pagination.max_page_size = 7;
+        config.items.default_owner = "owner".to_string();
+        let app = Router::new()
+            .route(
+                "/",
+                get(
+                    |State(pagination): State<PaginationConfig>,
+                     State(items): State<ItemsConfig>| async move {
+                        format!("{} {}", pagination.max_page_size, items.default_owner)
+                    },
+                ),
+            )
+            .with_state(AppState::new(db, config));
+
+        let res = app
+            .oneshot(Request::get("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!("7 owner", body);
     }
 }
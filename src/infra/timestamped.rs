@@ -0,0 +1,68 @@
+//! An opt-in response envelope that records when a response was served.
+
+use axum::response::{IntoResponse, Response};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::infra::extract::Json;
+
+/// A response payload together with the time it was served.
+///
+/// Useful for debugging clock skew between clients and the server.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Timestamped<T> {
+    /// The wrapped payload.
+    pub data: T,
+    /// The time the response was served.
+    #[schema(value_type = String, example = "2021-01-01T00:00:00Z")]
+    pub served_at: OffsetDateTime,
+}
+
+impl<T> Timestamped<T> {
+    /// Wraps `data` with the current time.
+    pub fn now(data: T) -> Self {
+        Self {
+            data,
+            served_at: OffsetDateTime::now_utc(),
+        }
+    }
+}
+
+impl<T: Serialize> IntoResponse for Timestamped<T> {
+    fn into_response(self) -> Response {
+        Json(self).into_response()
+    }
+}
+
+/// Options for wrapping a response in a [`Timestamped`] envelope.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, IntoParams)]
+pub struct TimestampParams {
+    /// Wrap the response as `{ data, servedAt }`.
+    #[serde(default)]
+    timestamped: bool,
+}
+
+impl TimestampParams {
+    /// Responds with `data`, wrapped in a [`Timestamped`] envelope if requested.
+    pub fn respond<T: Serialize>(&self, data: T) -> Response {
+        if self.timestamped {
+            Timestamped::now(data).into_response()
+        } else {
+            Json(data).into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamped_serializes_data_and_served_at() {
+        let value = serde_json::to_value(Timestamped::now(vec![1, 2])).unwrap();
+        assert_eq!(serde_json::json!([1, 2]), value["data"]);
+        assert!(!value["servedAt"].is_null(), "{value}");
+    }
+}
@@ -12,11 +12,22 @@ pub struct Valid<T> {
 
 impl<T> Valid<T> {
     /// Constructs a new validated value.
+    ///
+    /// Failures are logged at debug level with the invalid fields, but not their values.
     pub fn new(value: T) -> Result<Valid<T>, ValidationErrors>
     where
         T: Validate,
     {
-        value.validate().map(|_| Valid { value })
+        value
+            .validate()
+            .inspect_err(|e| {
+                tracing::debug!(
+                    "Validation of {} failed: {}",
+                    std::any::type_name::<T>(),
+                    describe(e)
+                )
+            })
+            .map(|_| Valid { value })
     }
 
     /// Returns a reference to the validated value.
@@ -42,13 +53,57 @@ impl<'de, T: Deserialize<'de> + Validate> Deserialize<'de> for Valid<T> {
         D: serde::Deserializer<'de>,
     {
         let value: T = T::deserialize(deserializer)?;
-        Valid::new(value).map_err(|e| serde::de::Error::custom(e.to_string()))
+        Valid::new(value).map_err(|e| serde::de::Error::custom(describe(&e)))
     }
 }
 
+/// Deserializes a string with surrounding whitespace removed.
+///
+/// Use with `#[serde(deserialize_with = "trimmed")]` so that length validation
+/// applies to the trimmed value.
+pub fn trimmed<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let value = String::deserialize(deserializer)?;
+    Ok(value.trim().to_string())
+}
+
+/// Like [`trimmed`], but for optional strings.
+///
+/// Use together with `#[serde(default)]` so that a missing field is still accepted.
+pub fn trimmed_option<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let value = Option::<String>::deserialize(deserializer)?;
+    Ok(value.map(|v| v.trim().to_string()))
+}
+
+/// Describes which fields are invalid and why, e.g. `invalid field(s): name (length)`.
+pub(crate) fn describe(errors: &ValidationErrors) -> String {
+    let mut invalid_fields = String::new();
+    for (k, v) in errors.field_errors() {
+        let mut codes = String::new();
+        for e in v {
+            codes += &format!("{},", e.code);
+        }
+        let codes = codes.trim_end_matches(',');
+        invalid_fields += &format!("{k} ({codes}), ");
+    }
+    let invalid_fields = invalid_fields.trim_end_matches(", ");
+    format!("invalid field(s): {invalid_fields}")
+}
+
 #[cfg(test)]
 mod tests {
     use super::Valid;
+    use crate::{
+        api::item::item_repository::NewItem,
+        infra::{config::JsonConfig, error::ErrorBody, extract::Json},
+    };
+    use axum::{body::Body, extract::FromRequest, response::IntoResponse};
+    use http::{Request, StatusCode};
     use serde::Deserialize;
     use validator::Validate;
 
@@ -91,4 +146,46 @@ mod tests {
         let value = serde_json::from_str::<Valid<Fields>>(data);
         assert!(value.is_err());
     }
+
+    #[tokio::test]
+    async fn invalid_json_body_gives_unprocessable_entity_with_fields() {
+        let req = Request::post("/")
+            .header("Content-Type", "application/json")
+            .body(Body::from(r#"{"name": ""}"#))
+            .unwrap();
+        let rejection = Json::<Valid<NewItem>>::from_request(req, &JsonConfig::default())
+            .await
+            .unwrap_err();
+        let res = rejection.into_response();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let error: ErrorBody = serde_json::from_slice(&body).unwrap();
+        assert!(error.message().contains("name (length)"));
+    }
+
+    #[test]
+    fn whitespace_only_name_is_rejected() {
+        let value = serde_json::from_str::<Valid<NewItem>>(r#"{"name": "   "}"#);
+        assert!(value.unwrap_err().to_string().contains("name (length)"));
+    }
+
+    #[test]
+    fn too_long_description_is_rejected() {
+        let data = serde_json::json!({ "name": "item", "description": "a".repeat(4097) });
+        let value = serde_json::from_value::<Valid<NewItem>>(data);
+        assert!(value
+            .unwrap_err()
+            .to_string()
+            .contains("description (length)"));
+    }
+
+    #[test]
+    fn surrounding_whitespace_is_trimmed() {
+        let data = r#"{"name": "  item ", "description": " text\n"}"#;
+        let value = serde_json::from_str::<Valid<NewItem>>(data).unwrap();
+        assert_eq!("item", value.inner().name);
+        assert_eq!(Some("text"), value.inner().description.as_deref());
+    }
 }
@@ -0,0 +1,20 @@
+//! The version of the running application.
+//!
+//! The commit and build time are set by the build script.
+
+use chrono::{DateTime, Utc};
+
+/// The application version.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The short sha of the commit that was built, or `unknown`.
+pub const GIT_SHA: &str = env!("GIT_SHA");
+
+/// When the application was built.
+pub fn build_time() -> DateTime<Utc> {
+    env!("BUILD_TIMESTAMP")
+        .parse()
+        .ok()
+        .and_then(|secs| DateTime::from_timestamp(secs, 0))
+        .unwrap_or_default()
+}
@@ -1,12 +1,9 @@
 //! An example web service with axum.
 
-use axum_demo::infra::{self};
-use sqlx::migrate::Migrator;
+use axum_demo::infra::{self, database::MIGRATOR};
 use std::time::Duration;
 use tokio::net::TcpListener;
 
-static MIGRATOR: Migrator = sqlx::migrate!();
-
 #[tokio::main]
 async fn main() -> color_eyre::Result<()> {
     // Load environment variables from .env file
@@ -16,6 +13,7 @@ async fn main() -> color_eyre::Result<()> {
     let config = infra::config::load_config()?;
     let _guard = infra::logging::init_logging(&config.logging);
     let db = infra::database::init_db(&config.database);
+    infra::selfcheck::run(&config, &db).await;
 
     // Run normal migrations
     while let Err(e) = MIGRATOR.run(&db).await {
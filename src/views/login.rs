@@ -5,8 +5,11 @@ use serde::{Deserialize, Serialize};
 use tower_sessions::Session;
 
 use crate::infra::{
+    config::Config,
     database::DbPool,
-    error::{ApiResult, ClientError},
+    error::{ApiResult, ClientError, InternalError},
+    extract::ClientIp,
+    rate_limit::LoginLimiter,
     security,
     state::AppState,
 };
@@ -40,13 +43,24 @@ pub async fn post_login(
     _: LoginPath,
     session: Session,
     db: State<DbPool>,
+    config: State<Config>,
+    limiter: State<LoginLimiter>,
+    ClientIp(ip): ClientIp,
     Form(params): Form<LoginParams>,
 ) -> ApiResult<Redirect> {
-    let mut tx = db.begin().await.unwrap();
+    let mut tx = db.begin().await?;
     let username = params.username;
     let password = params.password;
-    let user = security::authenticate(&mut tx, &username, &password).await?;
-    session.insert(SESSION_USER_KEY, user).await.unwrap();
+    let cost = config.security.bcrypt_cost;
+    let login = security::authenticate(&mut tx, &username, &password, cost);
+    let user = limiter
+        .attempt(ip, &username, &config.security.login_limit, login)
+        .await?;
+    tx.commit().await?;
+    session.insert(SESSION_USER_KEY, user).await.map_err(|e| {
+        tracing::error!("Failed to store user in session: {}", e);
+        InternalError::Other(e.to_string())
+    })?;
     let home = Index.to_string();
     Ok(Redirect::to(&home))
 }